@@ -2,7 +2,7 @@ import struct
 import uuid
 
 MAGIC = b"YAVS"     # 4 bytes
-VERSION = 1         # 4-byte version
+VERSION = 2         # 4-byte version
 RESERVED_SIZE = 16  # 16 bytes reserved
 HEADER_SIZE = 4 + 4 + 8 + 4 + RESERVED_SIZE 
 
@@ -27,14 +27,24 @@ def get_header_info(filename: str):
         if magic != MAGIC:
             raise ValueError("Not a valid YAVS file.")
         version = struct.unpack("<I", f.read(4))[0]
-        if version != VERSION:
-            raise ValueError(f"File version mismatch. Expected {VERSION}, got {version}.")
+        if version < 1 or version > VERSION:
+            raise ValueError(f"File version mismatch. Expected at most {VERSION}, got {version}.")
         # Read n_records
         n_records = struct.unpack("<Q", f.read(8))[0]
         # Read dimension
         dim = struct.unpack("<I", f.read(4))[0]
     return n_records, dim
 
+def records_end(filename: str, n_records: int, dim: int):
+    # Offset just past the last record; version 2 files may have sections after it
+    with open(filename, "rb") as f:
+        offset = HEADER_SIZE
+        for _ in range(n_records):
+            f.seek(offset + 16 + 4 * dim)
+            meta_len = struct.unpack("<I", f.read(4))[0]
+            offset += 16 + 4 * dim + 4 + meta_len
+    return offset
+
 def insert(filename: str, embedding, metadata: bytes, record_id: bytes = None):
     # Validate the file dimension
     n_records, dim = get_header_info(filename)
@@ -50,9 +60,16 @@ def insert(filename: str, embedding, metadata: bytes, record_id: bytes = None):
 
     # Convert embedding to bytes (little-endian float32)
     embedding_bytes = struct.pack("<" + "f" * dim, *embedding)
+    if isinstance(metadata, str):
+        metadata = metadata.encode("utf-8")
     meta_len = len(metadata)
 
-    with open(filename, "ab") as f:
+    end = records_end(filename, n_records, dim)
+    with open(filename, "r+b") as f:
+        # Keep any trailing sections and write them back after the new record
+        f.seek(end)
+        sections = f.read()
+        f.seek(end)
         # Write ID
         f.write(record_id)
         # Write embedding
@@ -60,13 +77,10 @@ def insert(filename: str, embedding, metadata: bytes, record_id: bytes = None):
         # Write metadata length
         f.write(struct.pack("<I", meta_len))
         # Write metadata
-        try:
-            f.write(metadata)
-        except TypeError:
-            f.write(metadata.encode("utf-8"))
+        f.write(metadata)
+        f.write(sections)
 
-    # Update header's n_records
-    with open(filename, "r+b") as f:
+        # Update header's n_records
         f.seek(4 + 4)  # skip MAGIC (4) + VERSION (4)
         f.write(struct.pack("<Q", n_records + 1))
 
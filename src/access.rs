@@ -0,0 +1,76 @@
+// Optional per-record access statistics, updated whenever a record is
+// returned from a query. Queries take `&self`, so the counters sit behind a
+// mutex; tracking is off by default to keep the query path lock-free.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use crate::YAVS;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    pub hits: u64,
+    // Store clock time of the most recent hit
+    pub last_accessed: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AccessTracker {
+    stats: Mutex<HashMap<[u8; 16], AccessStats>>,
+}
+
+impl AccessTracker {
+    fn lock(&self) -> MutexGuard<'_, HashMap<[u8; 16], AccessStats>> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn hit<'a, I: Iterator<Item = &'a [u8; 16]>>(&self, ids: I, now: u64) {
+        let mut stats = self.lock();
+        for id in ids {
+            let entry = stats.entry(*id).or_default();
+            entry.hits += 1;
+            entry.last_accessed = now;
+        }
+    }
+
+    pub(crate) fn rekey(&self, old_id: &[u8; 16], new_id: [u8; 16]) {
+        let mut stats = self.lock();
+        if let Some(entry) = stats.remove(old_id) {
+            stats.insert(new_id, entry);
+        }
+    }
+
+    pub(crate) fn forget<'a, I: Iterator<Item = &'a [u8; 16]>>(&self, ids: I) {
+        let mut stats = self.lock();
+        for id in ids {
+            stats.remove(id);
+        }
+    }
+}
+
+impl YAVS {
+    // Enables or disables access tracking. Disabling discards collected stats.
+    pub fn set_access_tracking(&mut self, enabled: bool) {
+        self.access = if enabled { Some(AccessTracker::default()) } else { None };
+    }
+
+    // None if tracking is off or the record has never been returned by a query
+    pub fn stats_for(&self, id: &[u8; 16]) -> Option<AccessStats> {
+        self.access.as_ref()?.lock().get(id).copied()
+    }
+
+    // The `n` live records with the most hits, most popular first
+    pub fn most_accessed(&self, n: usize) -> Vec<([u8; 16], AccessStats)> {
+        let Some(access) = &self.access else {
+            return Vec::new();
+        };
+        let mut top: Vec<([u8; 16], AccessStats)> = access
+            .lock()
+            .iter()
+            .filter(|(id, _)| self.position(id).is_some())
+            .map(|(id, s)| (*id, *s))
+            .collect();
+        top.sort_by(|a, b| b.1.hits.cmp(&a.1.hits).then(b.1.last_accessed.cmp(&a.1.last_accessed)));
+        top.truncate(n);
+        top
+    }
+}
@@ -0,0 +1,288 @@
+// Appendable file mode. A store opened with `open_rw` keeps its file open,
+// and a plain insert writes just the new record: the file is cut back to the
+// end of its record block (the sections after it are rewritten by `flush`),
+// the record is appended and synced, then the header's record count patched
+// and synced (once per batch under `set_group_commit`). Any other change (removals, tags, index training, ...) can't be
+// expressed as an append, so the next `flush` rewrites the whole file instead,
+// through a temp file like `save`.
+//
+// Between flushes the file is a valid store holding every appended record,
+// but without the sections (tags, timestamps, indexes, ...) or a file digest.
+// A crash mid-append leaves at most a record past the count, which loading
+// ignores and the next append overwrites.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::format::{self, digest_field, record_size, DIGEST_OFFSET, HEADER_SIZE};
+use crate::group::Batch;
+use crate::wal;
+use crate::{Record, YAVS, YAVSError};
+
+// Offset of the record count in the header, after MAGIC and VERSION
+const COUNT_OFFSET: u64 = 4 + 4;
+
+#[derive(Debug)]
+pub(crate) struct Appender {
+    file: File,
+    path: PathBuf,
+    // End of the record block, where the next record goes
+    records_end: u64,
+    n_records: u64,
+    // The record count in the header, behind `n_records` while a group
+    // commit batch is pending
+    counted: u64,
+    pub(crate) batch: Batch,
+    // Whether anything follows the record block on disk
+    has_sections: bool,
+    // Whether the header may carry a digest, which appending invalidates
+    has_digest: bool,
+    // `YAVS::changes` when the file last held exactly the in-memory records;
+    // any other value means some change wasn't an append
+    synced: u64,
+}
+
+impl Appender {
+    fn append(&mut self, rec: &Record, db: &YAVS) -> Result<(), YAVSError> {
+        if self.has_digest {
+            self.file.seek(SeekFrom::Start(DIGEST_OFFSET))?;
+            self.file.write_all(&digest_field(None, db.encoding()))?;
+            self.has_digest = false;
+        }
+        if self.has_sections {
+            self.file.set_len(self.records_end)?;
+            self.has_sections = false;
+        }
+        let mut bytes = Vec::with_capacity(record_size(rec, db.encoding()) as usize);
+        format::write_record(&mut bytes, rec, db.encoding())?;
+        self.file.seek(SeekFrom::Start(self.records_end))?;
+        if let Err(e) = self.file.write_all(&bytes) {
+            // Don't leave a partial record where sections would be read from
+            self.file.set_len(self.records_end)?;
+            return Err(e.into());
+        }
+        self.records_end += bytes.len() as u64;
+        self.n_records += 1;
+        if self.batch.add(db.group_commit, db.clock.now_ms()) {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    // Syncs the records appended since the last commit, then the count that
+    // takes them in
+    pub(crate) fn commit(&mut self) -> Result<(), YAVSError> {
+        if self.counted != self.n_records {
+            self.file.sync_data()?;
+            self.file.seek(SeekFrom::Start(COUNT_OFFSET))?;
+            self.file.write_all(&self.n_records.to_le_bytes())?;
+            self.file.sync_data()?;
+            self.counted = self.n_records;
+        }
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+impl YAVS {
+    // Loads a store and keeps its file open for appending inserts; see
+    // `flush`. The file must not be written through other handles meanwhile.
+    pub fn open_rw<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        let path = path.as_ref();
+        wal::check_no_pending_log(path)?;
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut db = format::read_store(&mut BufReader::new(&mut file))?;
+        let records_end = HEADER_SIZE + db.iter().map(|r| record_size(r, db.encoding())).sum::<u64>();
+        let has_sections = file.metadata()?.len() > records_end;
+        db.appender = Some(Appender {
+            file,
+            path: path.to_path_buf(),
+            records_end,
+            n_records: db.len() as u64,
+            counted: db.len() as u64,
+            batch: Batch::default(),
+            has_sections,
+            has_digest: true,
+            synced: db.changes,
+        });
+        Ok(db)
+    }
+
+    pub fn is_appendable(&self) -> bool {
+        self.appender.is_some()
+    }
+
+    // Brings the open file fully up to date: rewrites the sections after the
+    // record block, or the whole file if some change wasn't an append. Does
+    // nothing for a store not opened with `open_rw`.
+    pub fn flush(&mut self) -> Result<(), YAVSError> {
+        self.flush_with_backups(0)
+    }
+
+    // `flush`, keeping `backups` rotated copies of the file as `save_with`
+    // does; any backups mean a whole new file
+    pub(crate) fn flush_with_backups(&mut self, backups: usize) -> Result<(), YAVSError> {
+        let Some(mut appender) = self.appender.take() else {
+            return Ok(());
+        };
+        let result = self.flush_to(&mut appender, backups);
+        self.appender = Some(appender);
+        result
+    }
+
+    fn flush_to(&mut self, appender: &mut Appender, backups: usize) -> Result<(), YAVSError> {
+        if appender.synced == self.changes && backups == 0 {
+            appender.commit()?;
+            let body = format::encode_body(self)?;
+            appender.file.set_len(appender.records_end)?;
+            appender.file.seek(SeekFrom::Start(appender.records_end))?;
+            appender.file.write_all(&body.sections)?;
+            appender.file.seek(SeekFrom::Start(DIGEST_OFFSET))?;
+            appender.file.write_all(&digest_field(Some(body.digest), self.encoding()))?;
+        } else {
+            // The old handle is left on the replaced file; nothing may be
+            // appended through it, even if the new one fails to open
+            appender.synced = self.changes.wrapping_sub(1);
+            self.write_file(&appender.path, backups)?;
+            appender.file = OpenOptions::new().read(true).write(true).open(&appender.path)?;
+            appender.records_end = HEADER_SIZE + self.iter().map(|r| record_size(r, self.encoding())).sum::<u64>();
+            appender.n_records = self.len() as u64;
+            appender.counted = appender.n_records;
+            appender.batch.clear();
+        }
+        appender.has_sections = appender.file.metadata()?.len() > appender.records_end;
+        appender.has_digest = true;
+        appender.file.sync_data()?;
+        appender.synced = self.changes;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Flushes and closes the file, leaving an ordinary in-memory store
+    pub fn close_rw(&mut self) -> Result<(), YAVSError> {
+        self.flush()?;
+        self.appender = None;
+        Ok(())
+    }
+
+    // Path of the file opened with `open_rw`
+    pub fn rw_path(&self) -> Option<&Path> {
+        self.appender.as_ref().map(|a| a.path.as_path())
+    }
+
+    // Called by inserts once the new record is in place, with the change
+    // count from before the insert. On an I/O error the caller takes the
+    // record out again and the next `flush` rewrites the file.
+    pub(crate) fn append_inserted(&mut self, changes_before: u64) -> Result<(), YAVSError> {
+        let Some(mut appender) = self.appender.take() else {
+            return Ok(());
+        };
+        let result = match self.records.last() {
+            Some(rec) if appender.synced == changes_before => appender.append(rec, self).map(|_| appender.synced = self.changes),
+            _ => Ok(()),
+        };
+        self.appender = Some(appender);
+        result
+    }
+}
+
+// Best effort; call `flush` or `close_rw` (and `commit` for the log) to see
+// errors
+impl Drop for YAVS {
+    fn drop(&mut self) {
+        if self.appender.is_some() && self.dirty {
+            let _ = self.flush();
+        }
+        if let Some(wal) = &mut self.wal {
+            let _ = wal.commit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use crate::format;
+    use crate::{test_path, Precision, Record, SaveOptions, YAVS, YAVSError};
+
+    #[test]
+    fn appends_survive_reload() {
+        let path = test_path("append-reload.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_rw(&path).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+
+        // Mid-append the file holds the records without their sections
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(&b).unwrap().metadata, b"b");
+
+        db.add_tag(&a, "t");
+        db.flush().unwrap();
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.get(&a).unwrap().tags.contains("t"));
+    }
+
+    #[test]
+    fn torn_append_is_ignored() {
+        let path = test_path("append-torn.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_rw(&path).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        // A crash: no flush on drop
+        std::mem::forget(db);
+
+        // A record written but not yet counted, whole and then cut short
+        let mut torn = Vec::new();
+        format::write_record(&mut torn, &Record::new([7; 16], vec![0.5, 0.5], b"torn".to_vec()), Precision::F32.into()).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&torn).unwrap();
+        for end in [len + torn.len() as u64, len + torn.len() as u64 / 2] {
+            OpenOptions::new().write(true).open(&path).unwrap().set_len(end).unwrap();
+            let loaded = YAVS::load_file(&path).unwrap();
+            assert_eq!(loaded.len(), 1);
+            assert!(loaded.get(&a).is_some());
+            assert!(loaded.get(&[7; 16]).is_none());
+        }
+
+        // The next append takes its place
+        let mut db = YAVS::open_rw(&path).unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        std::mem::forget(db);
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(&b).unwrap().metadata, b"b");
+    }
+
+    #[test]
+    fn save_as_refuses_the_open_file() {
+        let path = test_path("append-save-as.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_rw(&path).unwrap();
+        db.insert(&[1.0, 0.0], b"a").unwrap();
+        assert!(matches!(db.save_as(&path), Err(YAVSError::OpenFile)));
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        std::mem::forget(db);
+        assert_eq!(YAVS::load_file(&path).unwrap().get(&b).unwrap().metadata, b"b");
+    }
+
+    #[test]
+    fn rewrite_replaces_the_file_and_keeps_appending() {
+        let path = test_path("append-rewrite.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_rw(&path).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        db.remove(&a);
+        db.save_with(&path, &SaveOptions { backups: 1, ..Default::default() }).unwrap();
+        assert!(path.with_extension("yavs.1").exists());
+
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        db.close_rw().unwrap();
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert!(loaded.get(&a).unwrap().deleted);
+        assert_eq!(loaded.get(&b).unwrap().metadata, b"b");
+    }
+}
@@ -0,0 +1,188 @@
+// Cold-storage archives for rarely used corpora. An archive keeps each live
+// record's embedding only as its product quantization code (a byte per
+// subspace) next to the codebooks, drops every index and cache, and zstd-
+// compresses the lot, so it is a small fraction of the store file. Restoring
+// decodes the codes back to centroids and rebuilds a queryable store in one
+// pass; its embeddings are the quantized approximations, so rankings come
+// close to the original store's but aren't identical. A store at Pq precision
+// is restored at it, keeping the codes as they are; one at Int8 is restored
+// at F32. Requires the `zstd` feature.
+//
+// Layout: magic, version, CRC32 of the compressed body, then the body: dim,
+// metric, precision, record count, codebooks (length-prefixed, empty for a
+// store with nothing to quantize), and per record its code followed by the
+// full record entry with an empty embedding.
+
+use std::fs;
+use std::path::Path;
+use crc32fast::Hasher;
+use crate::compression;
+use crate::format::{read_full_record, read_u32, read_u64, write_full_record, Encoding};
+use crate::pq::ProductQuantizer;
+use crate::{Metric, Precision, Record, YAVS, YAVSError};
+
+const ARCHIVE_MAGIC: &[u8] = b"YAVA";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    // Subspaces to quantize with, clamped to the dimension; 0 for one per 4
+    // dimensions. Ignored if the store has trained PQ codebooks, which are
+    // reused.
+    pub n_subspaces: usize,
+    // zstd level for the body
+    pub level: i32,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self { n_subspaces: 0, level: 19 }
+    }
+}
+
+impl YAVS {
+    pub fn export_archive<P: AsRef<Path>>(&self, path: P, options: &ArchiveOptions) -> Result<(), YAVSError> {
+        fs::write(path, self.archive_mem(options)?)?;
+        Ok(())
+    }
+
+    // The archive of the live records; tombstones, indexes, hooks, eval sets,
+    // field declarations and the KV namespace are left out
+    pub fn archive_mem(&self, options: &ArchiveOptions) -> Result<Vec<u8>, YAVSError> {
+        let live: Vec<(usize, &Record)> = self.records.iter().enumerate().filter(|(_, r)| !r.deleted).collect();
+        let trained;
+        let pq = match &self.pq {
+            Some(pq) => Some((pq, true)),
+            None if live.is_empty() || self.dim == 0 => None,
+            None => {
+                let dim = self.dim as usize;
+                let n_subspaces = match options.n_subspaces {
+                    0 => dim.div_ceil(4),
+                    n => n.clamp(1, dim),
+                };
+                let mut pq = ProductQuantizer::train(live.len(), dim, n_subspaces, |i| self.vector(live[i].1));
+                for (i, (_, rec)) in live.iter().enumerate() {
+                    pq.encode(i, &self.vector(rec));
+                }
+                trained = pq;
+                Some((&trained, false))
+            }
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.dim.to_le_bytes());
+        body.extend_from_slice(&self.metric.code().to_le_bytes());
+        // There are no int8 ranges in an archive to keep Int8 codes with
+        let precision = match self.precision {
+            Precision::Int8 => Precision::F32,
+            precision => precision,
+        };
+        body.extend_from_slice(&precision.code().to_le_bytes());
+        body.extend_from_slice(&(live.len() as u64).to_le_bytes());
+        let codebooks = pq.map(|(pq, _)| pq.encode_codebooks()).unwrap_or_default();
+        body.extend_from_slice(&(codebooks.len() as u64).to_le_bytes());
+        body.extend_from_slice(&codebooks);
+        for (i, &(pos, rec)) in live.iter().enumerate() {
+            // The store's own codes are by record position, fresh ones by
+            // live order
+            if let Some((pq, own)) = pq {
+                body.extend_from_slice(if own { self.pq_code(pq, pos) } else { pq.code(i) });
+            }
+            let stripped = Record { embedding: Vec::new(), code: Vec::new(), ..rec.clone() };
+            write_full_record(&mut body, &stripped, Encoding::default())?;
+        }
+
+        let compressed = compression::compress_at(&body, options.level)?;
+        let mut hasher = Hasher::new();
+        hasher.update(&compressed);
+        let mut out = Vec::with_capacity(12 + compressed.len());
+        out.extend_from_slice(ARCHIVE_MAGIC);
+        out.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        out.extend_from_slice(&hasher.finalize().to_le_bytes());
+        out.extend_from_slice(&compressed);
+        Ok(out)
+    }
+
+    pub fn restore_archive<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        Self::restore_archive_mem(&fs::read(path)?)
+    }
+
+    // A store of the archived records, with embeddings decoded from their
+    // codes and the archive's codebooks trained for `query_pq`
+    pub fn restore_archive_mem(buf: &[u8]) -> Result<Self, YAVSError> {
+        if buf.len() < 12 || &buf[..4] != ARCHIVE_MAGIC {
+            return Err(YAVSError::InvalidFile);
+        }
+        if u32::from_le_bytes(buf[4..8].try_into().unwrap_or_default()) != ARCHIVE_VERSION {
+            return Err(YAVSError::VersionMismatch);
+        }
+        let mut hasher = Hasher::new();
+        hasher.update(&buf[12..]);
+        if buf[8..12] != hasher.finalize().to_le_bytes() {
+            return Err(YAVSError::InvalidFile);
+        }
+        let body = compression::decompress(&buf[12..])?;
+        let mut payload = body.as_slice();
+
+        let dim = read_u32(&mut payload)?;
+        let metric = Metric::from_code(read_u32(&mut payload)?).ok_or(YAVSError::InvalidFile)?;
+        let precision = Precision::from_code(read_u32(&mut payload)?)
+            .filter(|&p| p != Precision::Int8)
+            .ok_or(YAVSError::InvalidFile)?;
+        let count = read_u64(&mut payload)?;
+        let codebooks_len = read_u64(&mut payload)?;
+        if codebooks_len > payload.len() as u64 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let (mut codebooks, rest) = payload.split_at(codebooks_len as usize);
+        payload = rest;
+        let pq = match codebooks_len {
+            0 => None,
+            _ => Some(ProductQuantizer::decode_codebooks(&mut codebooks)?),
+        };
+        match &pq {
+            Some(pq) if pq.dim() != dim as usize => return Err(YAVSError::InvalidFile),
+            None if dim > 0 && count > 0 => return Err(YAVSError::InvalidFile),
+            _ => {}
+        }
+
+        let m = pq.as_ref().map_or(0, ProductQuantizer::n_subspaces);
+        let capacity = count.min(payload.len() as u64 / (m as u64 + 16)) as usize;
+        let mut records = Vec::with_capacity(capacity);
+        let mut codes = Vec::with_capacity(capacity * m);
+        for _ in 0..count {
+            if payload.len() < m {
+                return Err(YAVSError::InvalidFile);
+            }
+            let (code, rest) = payload.split_at(m);
+            payload = rest;
+            codes.extend_from_slice(code);
+            let mut rec = read_full_record(&mut payload, 0, Encoding::default())?;
+            match &pq {
+                Some(pq) if precision == Precision::Pq && pq.fits(code) => rec.code = code.to_vec(),
+                Some(_) if precision == Precision::Pq => return Err(YAVSError::InvalidFile),
+                Some(pq) => {
+                    rec.embedding = pq.decode(code).ok_or(YAVSError::InvalidFile)?;
+                    precision.round_all(&mut rec.embedding);
+                }
+                None => {}
+            }
+            records.push(rec);
+        }
+
+        if precision == Precision::Pq && pq.as_ref().is_none_or(|pq| pq.n_subspaces() > u16::MAX as usize) {
+            return Err(YAVSError::InvalidFile);
+        }
+        let mut db = Self::from_records(dim, metric, records);
+        db.precision = precision;
+        // The codes are already known; no need to encode the decoded records,
+        // nor to keep them twice when the records hold them
+        db.pq = pq.map(|mut pq| {
+            if precision != Precision::Pq {
+                pq.set_codes(codes);
+            }
+            pq
+        });
+        Ok(db)
+    }
+}
@@ -0,0 +1,57 @@
+// Arrow input, for pipelines that already hold embeddings in Arrow buffers.
+// Embeddings come as a FixedSizeList<Float32> array, one list per record,
+// whose list size must be the store dimension; each list is read in place
+// from the values buffer rather than converted first. Null lists or values
+// are rejected.
+
+use arrow_array::{Array, BinaryArray, FixedSizeListArray, Float32Array};
+use crate::{YAVS, YAVSError};
+
+type Results = Vec<([u8; 16], f32)>;
+
+impl YAVS {
+    // Inserts a record per list, with the matching `metadata` row (empty for
+    // a null row or without `metadata`). Like `insert_batch`, nothing is
+    // inserted unless every row is valid.
+    pub fn insert_arrow(&mut self, embeddings: &FixedSizeListArray, metadata: Option<&BinaryArray>) -> Result<Vec<[u8; 16]>, YAVSError> {
+        let slots = self.arrow_embeddings(embeddings)?;
+        if let Some(metadata) = metadata {
+            if metadata.len() != slots.len() {
+                return Err(YAVSError::ArrowInput(format!("{} metadata rows for {} embeddings", metadata.len(), slots.len())));
+            }
+        }
+        let items: Vec<(&[f32], &[u8])> = slots
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| {
+                let row = metadata.filter(|m| m.is_valid(i)).map_or(&[][..], |m| m.value(i));
+                (embedding, row)
+            })
+            .collect();
+        self.insert_batch(&items)
+    }
+
+    // `query` for each list
+    pub fn query_arrow(&self, queries: &FixedSizeListArray, k: usize) -> Result<Vec<Results>, YAVSError> {
+        self.arrow_embeddings(queries)?
+            .into_iter()
+            .map(|q| self.query(q, k))
+            .collect()
+    }
+
+    // Each list as a slice of the values buffer
+    fn arrow_embeddings<'a>(&self, lists: &'a FixedSizeListArray) -> Result<Vec<&'a [f32]>, YAVSError> {
+        let Some(values) = lists.values().as_any().downcast_ref::<Float32Array>() else {
+            return Err(YAVSError::ArrowInput(format!("expected FixedSizeList<Float32>, got {}", lists.data_type())));
+        };
+        if lists.value_length() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        if lists.null_count() > 0 || values.null_count() > 0 {
+            return Err(YAVSError::ArrowInput("null embeddings or values".to_string()));
+        }
+        let dim = self.dim as usize;
+        let floats: &[f32] = values.values();
+        Ok((0..lists.len()).map(|i| &floats[i * dim..(i + 1) * dim]).collect())
+    }
+}
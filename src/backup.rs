@@ -0,0 +1,168 @@
+// Rotating, checksummed backups of a store into a directory.
+//
+// Each backup is `yavs-<ms>.yavs` (or `.yavs.zst` when compressed), with the
+// timestamp taken from the store clock, plus a `<name>.crc32` sidecar holding
+// the CRC32 of the backup file in hex. Both are written through a temp file
+// like `save`, so a crash mid-backup leaves no partial backup behind.
+
+use std::cmp::Reverse;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::compression;
+use crate::{write_atomic, YAVS, YAVSError};
+
+const PREFIX: &str = "yavs-";
+const EXT: &str = ".yavs";
+const ZSTD_EXT: &str = ".yavs.zst";
+const CHECKSUM_EXT: &str = ".crc32";
+
+#[derive(Debug, Clone)]
+pub struct BackupPolicy {
+    // Number of backups kept after pruning; 0 keeps every backup
+    pub keep_last: usize,
+    // zstd-compress backups (requires the `zstd` feature)
+    pub compress: bool,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 7,
+            compress: false,
+        }
+    }
+}
+
+impl YAVS {
+    // Writes a new backup into `dir`, prunes old ones per `policy`, and returns
+    // the path of the new backup
+    pub fn backup_to<P: AsRef<Path>>(&self, dir: P, policy: &BackupPolicy) -> Result<PathBuf, YAVSError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let bytes = self.save_mem()?;
+        let (bytes, ext) = if policy.compress {
+            (compression::compress(&bytes)?, ZSTD_EXT)
+        } else {
+            (bytes, EXT)
+        };
+
+        // Never overwrite an existing backup taken in the same millisecond
+        let mut ts = self.clock.now_ms();
+        let mut path = dir.join(format!("{}{}{}", PREFIX, ts, ext));
+        while path.exists() {
+            ts += 1;
+            path = dir.join(format!("{}{}{}", PREFIX, ts, ext));
+        }
+
+        // Each file goes in whole or not at all, the checksum first so any
+        // backup that is listed has one
+        let checksum = format!("{:08x}\n", crc32fast::hash(&bytes));
+        write_atomic(&checksum_path(&path), 0, |w| Ok(w.write_all(checksum.as_bytes())?))?;
+        write_atomic(&path, 0, |w| Ok(w.write_all(&bytes)?))?;
+
+        if policy.keep_last > 0 {
+            for old in list_backups(dir)?.into_iter().skip(policy.keep_last) {
+                fs::remove_file(checksum_path(&old))?;
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(path)
+    }
+
+    // Restores from a backup file, or from the newest backup in a directory
+    // that passes its checksum
+    pub fn restore_from<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return restore_file(path);
+        }
+        let mut last_err = YAVSError::NotFound;
+        for backup in list_backups(path)? {
+            match restore_file(&backup) {
+                Ok(db) => return Ok(db),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+// Backups in `dir`, newest first
+pub fn list_backups<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, YAVSError> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(ts) = path.file_name().and_then(|n| n.to_str()).and_then(backup_timestamp) {
+            backups.push((ts, path));
+        }
+    }
+    backups.sort_by_key(|b| Reverse(b.0));
+    Ok(backups.into_iter().map(|(_, p)| p).collect())
+}
+
+fn backup_timestamp(name: &str) -> Option<u64> {
+    let rest = name.strip_prefix(PREFIX)?;
+    let digits = rest
+        .strip_suffix(ZSTD_EXT)
+        .or_else(|| rest.strip_suffix(EXT))?;
+    digits.parse().ok()
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(CHECKSUM_EXT);
+    PathBuf::from(name)
+}
+
+fn restore_file(path: &Path) -> Result<YAVS, YAVSError> {
+    let bytes = fs::read(path)?;
+    let expected = fs::read_to_string(checksum_path(path))?;
+    let expected = u32::from_str_radix(expected.trim(), 16).map_err(|_| YAVSError::InvalidFile)?;
+    if crc32fast::hash(&bytes) != expected {
+        return Err(YAVSError::ChecksumMismatch);
+    }
+
+    let is_compressed = path.to_str().is_some_and(|p| p.ends_with(ZSTD_EXT));
+    if is_compressed {
+        YAVS::load_mem(&compression::decompress(&bytes)?)
+    } else {
+        YAVS::load_mem(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+    use super::{checksum_path, list_backups, BackupPolicy};
+    use crate::{test_path, ManualClock, YAVS, YAVSError};
+
+    #[test]
+    fn backups_rotate_and_restore_the_newest_intact_one() {
+        let dir = test_path("backups");
+        let _ = fs::remove_dir_all(&dir);
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut db = YAVS::new(2);
+        db.set_clock(clock.clone());
+        let policy = BackupPolicy { keep_last: 2, ..Default::default() };
+        for i in 0..3 {
+            db.insert(&[1.0, i as f32], b"").unwrap();
+            db.backup_to(&dir, &policy).unwrap();
+        }
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups.len(), 2);
+        // Just the backups and their checksums, no temp files
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        assert_eq!(YAVS::restore_from(&dir).unwrap().len(), 3);
+
+        // A damaged newest backup falls back to the one before
+        let mut bytes = fs::read(&backups[0]).unwrap();
+        bytes[20] ^= 1;
+        fs::write(&backups[0], &bytes).unwrap();
+        assert!(matches!(YAVS::restore_from(&backups[0]), Err(YAVSError::ChecksumMismatch)));
+        assert!(checksum_path(&backups[1]).exists());
+        assert_eq!(YAVS::restore_from(&dir).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,243 @@
+// Store for binary vectors (bit-packed hashes) ranked by Hamming distance,
+// for use as a compact first stage ahead of a float store. Records can take
+// the IDs of the float records they were derived from, so first-stage hits
+// feed straight into `YAVS` lookups.
+//
+// File layout: MAGIC "YAVB", version (u32), record count (u64), bits per
+// vector (u32), then per record its ID, the packed vector and the metadata
+// length and bytes.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use crate::format::{read_u32, read_u64};
+use crate::{IdSource, RandomIds, YAVSError};
+
+const MAGIC: &[u8] = b"YAVB";
+const VERSION: u32 = 1;
+const PREALLOC_LIMIT: u64 = 1 << 16;
+// Largest vector a file may declare, 128KB packed; anything wider is
+// taken as corruption rather than allocated
+const MAX_FILE_BITS: u32 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryRecord {
+    pub id: [u8; 16],
+    // Packed bits, first bit in the lowest bit of the first byte
+    pub bits: Vec<u8>,
+    pub metadata: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct BinaryYAVS {
+    n_bits: u32,
+    records: Vec<BinaryRecord>,
+    // Position of each record by ID
+    positions: HashMap<[u8; 16], usize>,
+    id_source: Box<dyn IdSource>,
+}
+
+// Packs the sign of each component, one bit per dimension (set when > 0)
+pub fn sign_bits(embedding: &[f32]) -> Vec<u8> {
+    let mut bits = vec![0u8; embedding.len().div_ceil(8)];
+    for (i, &v) in embedding.iter().enumerate() {
+        if v > 0.0 {
+            bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bits
+}
+
+pub fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    let mut chunks_a = a.chunks_exact(8);
+    let mut chunks_b = b.chunks_exact(8);
+    let mut distance = 0;
+    for (x, y) in chunks_a.by_ref().zip(chunks_b.by_ref()) {
+        let x = u64::from_le_bytes(x.try_into().unwrap_or_default());
+        let y = u64::from_le_bytes(y.try_into().unwrap_or_default());
+        distance += (x ^ y).count_ones();
+    }
+    for (x, y) in chunks_a.remainder().iter().zip(chunks_b.remainder()) {
+        distance += (x ^ y).count_ones();
+    }
+    distance
+}
+
+impl BinaryYAVS {
+    pub fn new(n_bits: u32) -> Self {
+        Self {
+            n_bits,
+            records: Vec::new(),
+            positions: HashMap::new(),
+            id_source: Box::new(RandomIds),
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.n_bits
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn set_id_source<S: IdSource + 'static>(&mut self, source: S) {
+        self.id_source = Box::new(source);
+    }
+
+    pub fn insert(&mut self, bits: &[u8], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        let bits = self.packed(bits)?;
+        let id = self.id_source.next_id();
+        if self.positions.contains_key(&id) {
+            return Err(YAVSError::DuplicateId);
+        }
+        self.push(BinaryRecord {
+            id,
+            bits,
+            metadata: metadata.to_vec(),
+        });
+        Ok(id)
+    }
+
+    // Inserts under a caller-chosen ID, e.g. that of the matching float record
+    pub fn insert_with_id(&mut self, id: [u8; 16], bits: &[u8], metadata: &[u8]) -> Result<(), YAVSError> {
+        let bits = self.packed(bits)?;
+        if self.positions.contains_key(&id) {
+            return Err(YAVSError::DuplicateId);
+        }
+        self.push(BinaryRecord {
+            id,
+            bits,
+            metadata: metadata.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn push(&mut self, rec: BinaryRecord) {
+        self.positions.insert(rec.id, self.records.len());
+        self.records.push(rec);
+    }
+
+    // Validates the length and clears padding bits past `n_bits`
+    fn packed(&self, bits: &[u8]) -> Result<Vec<u8>, YAVSError> {
+        if bits.len() != (self.n_bits as usize).div_ceil(8) {
+            return Err(YAVSError::DimMismatch);
+        }
+        let mut bits = bits.to_vec();
+        let tail = self.n_bits % 8;
+        if let (1.., Some(last)) = (tail, bits.last_mut()) {
+            *last &= (1u8 << tail) - 1;
+        }
+        Ok(bits)
+    }
+
+    pub fn get(&self, id: &[u8; 16]) -> Option<&BinaryRecord> {
+        self.positions.get(id).map(|&i| &self.records[i])
+    }
+
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        let Some(i) = self.positions.remove(id) else {
+            return false;
+        };
+        self.records.swap_remove(i);
+        if let Some(moved) = self.records.get(i) {
+            self.positions.insert(moved.id, i);
+        }
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BinaryRecord> {
+        self.records.iter()
+    }
+
+    // The `k` records closest to `bits` by Hamming distance, ties by ID
+    pub fn query(&self, bits: &[u8], k: usize) -> Result<Vec<([u8; 16], u32)>, YAVSError> {
+        let query = self.packed(bits)?;
+        let mut dists: Vec<([u8; 16], u32)> = self.records
+            .iter()
+            .map(|r| (r.id, hamming(&query, &r.bits)))
+            .collect();
+        let k = k.min(dists.len());
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let by_distance = |a: &([u8; 16], u32), b: &([u8; 16], u32)| a.1.cmp(&b.1).then(a.0.cmp(&b.0));
+        dists.select_nth_unstable_by(k - 1, by_distance);
+        dists.truncate(k);
+        dists.sort_unstable_by(by_distance);
+        Ok(dists)
+    }
+
+    // Records within `max_distance` of `bits`, closest first
+    pub fn query_radius(&self, bits: &[u8], max_distance: u32) -> Result<Vec<([u8; 16], u32)>, YAVSError> {
+        let query = self.packed(bits)?;
+        let mut hits: Vec<([u8; 16], u32)> = self.records
+            .iter()
+            .map(|r| (r.id, hamming(&query, &r.bits)))
+            .filter(|&(_, d)| d <= max_distance)
+            .collect();
+        hits.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        Ok(hits)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), YAVSError> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(self.records.len() as u64).to_le_bytes())?;
+        w.write_all(&self.n_bits.to_le_bytes())?;
+        for rec in &self.records {
+            w.write_all(&rec.id)?;
+            w.write_all(&rec.bits)?;
+            w.write_all(&(rec.metadata.len() as u32).to_le_bytes())?;
+            w.write_all(&rec.metadata)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, YAVSError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(YAVSError::InvalidFile);
+        }
+        if read_u32(r)? != VERSION {
+            return Err(YAVSError::VersionMismatch);
+        }
+        let n_records = read_u64(r)?;
+        let n_bits = read_u32(r)?;
+        if n_bits > MAX_FILE_BITS {
+            return Err(YAVSError::InvalidFile);
+        }
+        let mut db = Self::new(n_bits);
+        db.records.reserve(n_records.min(PREALLOC_LIMIT) as usize);
+        db.positions.reserve(n_records.min(PREALLOC_LIMIT) as usize);
+        for _ in 0..n_records {
+            let mut id = [0u8; 16];
+            r.read_exact(&mut id)?;
+            let mut bits = vec![0u8; (db.n_bits as usize).div_ceil(8)];
+            r.read_exact(&mut bits)?;
+            let meta_len = read_u32(r)? as u64;
+            let mut metadata = Vec::with_capacity(meta_len.min(PREALLOC_LIMIT) as usize);
+            r.by_ref().take(meta_len).read_to_end(&mut metadata)?;
+            if metadata.len() as u64 != meta_len {
+                return Err(YAVSError::InvalidFile);
+            }
+            let bits = db.packed(&bits)?;
+            db.insert_with_id(id, &bits, &metadata).map_err(|_| YAVSError::InvalidFile)?;
+        }
+        Ok(db)
+    }
+
+    pub fn save_mem(&self) -> Result<Vec<u8>, YAVSError> {
+        let mut out = Vec::new();
+        self.write_to(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn load_mem(buf: &[u8]) -> Result<Self, YAVSError> {
+        Self::read_from(&mut std::io::Cursor::new(buf))
+    }
+}
@@ -0,0 +1,60 @@
+// Queries with a latency budget, for interactive features where a slightly
+// worse answer beats a late one. The exact scan stops once the budget (by
+// the store clock) is spent and returns the best records seen so far, marked
+// incomplete. The clock is read every `CLOCK_CHECK_EVERY` records, so a scan
+// can run over by that many distance computations.
+
+use std::cell::Cell;
+use crate::cache::Scope;
+use crate::slowlog::QueryRun;
+use crate::{YAVS, YAVSError};
+
+const CLOCK_CHECK_EVERY: usize = 256;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BudgetedResults {
+    pub results: Vec<([u8; 16], f32)>,
+    // Live records scored
+    pub scanned: usize,
+    // Whether every live record was scored before the budget ran out
+    pub complete: bool,
+}
+
+impl YAVS {
+    // Like `query`, but gives up scanning after `budget_ms`; results are
+    // never cached. The budget starts once the scan has a heavy query slot.
+    pub fn query_within(&self, query_embedding: &[f32], k: usize, budget_ms: u64) -> Result<BudgetedResults, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let _permit = self.heavy_query_permit()?;
+        let started = self.clock.now_ms();
+        let deadline = started.saturating_add(budget_ms);
+        let scanned = Cell::new(0usize);
+        let out_of_time = Cell::new(false);
+        let candidates = self.iter().take_while(|_| {
+            let n = scanned.get();
+            if n > 0 && n.is_multiple_of(CLOCK_CHECK_EVERY) && self.clock.now_ms() >= deadline {
+                out_of_time.set(true);
+                return false;
+            }
+            scanned.set(n + 1);
+            true
+        });
+        let results = self.scan_metric(self.metric, candidates, query_embedding, k);
+        self.record_hits(&results);
+        self.log_query(self.query_started().map(|_| started), QueryRun {
+            scope: &Scope::All,
+            metric: Some(self.metric),
+            query_embedding,
+            k,
+            results: results.len(),
+            scanned: scanned.get(),
+        });
+        Ok(BudgetedResults {
+            results,
+            scanned: scanned.get(),
+            complete: !out_of_time.get(),
+        })
+    }
+}
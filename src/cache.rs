@@ -0,0 +1,139 @@
+// Optional LRU cache of query results for read-heavy workloads where the
+// same or near-identical queries repeat. Query vectors are snapped to a grid
+// of `resolution` before lookup, so tiny float differences share an entry.
+// Any change to the store clears the cache.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use crate::{FieldFilter, Metric, Provenance, YAVS};
+
+// Which records a query ranks over; part of the cache key
+pub(crate) enum Scope<'a> {
+    All,
+    Tags(&'a [&'a str]),
+    // Any of these language tags
+    Langs(&'a [&'a str]),
+    Tenant(&'a str),
+    Provenance(&'a Provenance),
+    // Records matching every filter on indexed metadata fields
+    Fields(&'a [FieldFilter<'a>]),
+    // A caller-supplied predicate; never cached
+    Filter,
+    // The nprobe closest IVF cells
+    Cells(usize),
+    // The given number of best candidates by quantized code, per kind
+    Quantized(&'static str, usize),
+}
+
+impl Scope<'_> {
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Scope::All => String::new(),
+            Scope::Tags(tags) => {
+                let mut tags = tags.to_vec();
+                tags.sort_unstable();
+                format!("tags:{}", tags.join("\u{0}"))
+            }
+            Scope::Langs(langs) => format!("langs:{}", langs.join("\u{0}")),
+            Scope::Tenant(tenant) => format!("tenant:{}", tenant),
+            Scope::Provenance(filter) => format!("provenance:{:?}", filter),
+            Scope::Filter => "filter".to_string(),
+            Scope::Fields(filters) => format!("fields:{:?}", filters),
+            Scope::Cells(nprobe) => format!("ivf:{}", nprobe),
+            Scope::Quantized(kind, rerank) => format!("{}:{}", kind, rerank),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    scope: String,
+    metric: Metric,
+    k: usize,
+    query: Vec<i64>,
+}
+
+type Results = Vec<([u8; 16], f32)>;
+
+#[derive(Debug, Default)]
+struct CacheState {
+    // Results and the tick they were last used at
+    entries: HashMap<CacheKey, (Results, u64)>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryCache {
+    capacity: usize,
+    resolution: f32,
+    state: Mutex<CacheState>,
+}
+
+impl QueryCache {
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn key(&self, scope: &Scope<'_>, metric: Metric, query: &[f32], k: usize) -> CacheKey {
+        let scope = scope.describe();
+        let query = query
+            .iter()
+            .map(|&v| {
+                if self.resolution > 0.0 {
+                    (v / self.resolution).round() as i64
+                } else {
+                    v.to_bits() as i64
+                }
+            })
+            .collect();
+        CacheKey { scope, metric, k, query }
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Results> {
+        let mut state = self.lock();
+        state.tick += 1;
+        let tick = state.tick;
+        let (results, last_used) = state.entries.get_mut(key)?;
+        *last_used = tick;
+        Some(results.clone())
+    }
+
+    pub(crate) fn put(&self, key: CacheKey, results: Results) {
+        let mut state = self.lock();
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let oldest = state.entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key, (results, tick));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().entries.clear();
+    }
+}
+
+impl YAVS {
+    // Caches up to `capacity` query results; 0 disables the cache. Queries
+    // whose vectors agree after rounding to multiples of `resolution` share
+    // an entry; a resolution of 0 only matches bit-identical vectors.
+    pub fn set_query_cache(&mut self, capacity: usize, resolution: f32) {
+        self.cache = (capacity > 0).then(|| QueryCache {
+            capacity,
+            resolution,
+            state: Mutex::new(CacheState::default()),
+        });
+    }
+
+    pub fn clear_query_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+}
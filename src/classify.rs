@@ -0,0 +1,48 @@
+// Weighted k-NN classification over a label stored in JSON metadata, for
+// simple classification without an external ML runtime.
+
+use std::collections::HashMap;
+use crate::{Distance, YAVS, YAVSError};
+
+impl YAVS {
+    // Label probabilities from the `k` nearest records, each voting for the
+    // value of `label_field` in its JSON metadata with weight 1 / (1 + distance),
+    // or softmax of the scores for `Metric::Dot`. Neighbors without the field
+    // don't vote. Sorted most likely first.
+    pub fn classify(&self, embedding: &[f32], k: usize, label_field: &str) -> Result<Vec<(String, f32)>, YAVSError> {
+        let mut labeled = Vec::new();
+        for (id, dist) in self.query(embedding, k)? {
+            let Some(rec) = self.find(&id) else {
+                continue;
+            };
+            let Ok(serde_json::Value::Object(fields)) = serde_json::from_slice(&rec.metadata) else {
+                continue;
+            };
+            let label = match fields.get(label_field) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(serde_json::Value::Null) | None => continue,
+                Some(other) => other.to_string(),
+            };
+            labeled.push((label, dist));
+        }
+
+        let best = labeled.iter().map(|(_, d)| *d).fold(f32::NEG_INFINITY, f32::max);
+        let mut votes: HashMap<String, f32> = HashMap::new();
+        for (label, dist) in labeled {
+            let weight = if self.metric.larger_is_closer() {
+                (dist - best).exp()
+            } else {
+                1.0 / (1.0 + dist)
+            };
+            *votes.entry(label).or_default() += weight;
+        }
+
+        let total: f32 = votes.values().sum();
+        let mut probabilities: Vec<(String, f32)> = votes
+            .into_iter()
+            .map(|(label, weight)| (label, weight / total))
+            .collect();
+        probabilities.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(probabilities)
+    }
+}
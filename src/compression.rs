@@ -0,0 +1,66 @@
+// Zstd compression of metadata in saved files. Each record's metadata is
+// compressed on its own, so records stay independently readable (appends,
+// repair, mmap); in memory metadata is always plain. A header flag marks
+// compressed files. Requires the `zstd` feature.
+
+use std::borrow::Cow;
+use crate::{YAVS, YAVSError};
+
+#[cfg(feature = "zstd")]
+pub(crate) fn compress(bytes: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    Ok(zstd::encode_all(bytes, 0)?)
+}
+
+// At a given zstd level, for archives where size matters more than speed
+#[cfg(feature = "zstd")]
+pub(crate) fn compress_at(bytes: &[u8], level: i32) -> Result<Vec<u8>, YAVSError> {
+    Ok(zstd::encode_all(bytes, level)?)
+}
+
+#[cfg(feature = "zstd")]
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    zstd::decode_all(bytes).map_err(|_| YAVSError::InvalidFile)
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn compress(_: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    Err(YAVSError::FeatureDisabled("zstd"))
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn compress_at(_: &[u8], _: i32) -> Result<Vec<u8>, YAVSError> {
+    Err(YAVSError::FeatureDisabled("zstd"))
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn decompress(_: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    Err(YAVSError::FeatureDisabled("zstd"))
+}
+
+// Metadata as written to the file
+pub(crate) fn stored(metadata: &[u8], compressed: bool) -> Result<Cow<'_, [u8]>, YAVSError> {
+    if compressed {
+        compress(metadata).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(metadata))
+    }
+}
+
+impl YAVS {
+    pub fn metadata_compression(&self) -> bool {
+        self.compress_metadata
+    }
+
+    // Whether saves compress metadata. Takes effect on the next save; a store
+    // loaded from a compressed file keeps compressing.
+    pub fn set_metadata_compression(&mut self, on: bool) -> Result<(), YAVSError> {
+        if on {
+            compress(&[])?;
+        }
+        if on != self.compress_metadata {
+            self.compress_metadata = on;
+            self.mark_changed();
+        }
+        Ok(())
+    }
+}
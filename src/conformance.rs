@@ -0,0 +1,286 @@
+// Randomized round-trip and corruption checks for anything that turns a store
+// into bytes and back. Third-party backends and new format versions implement
+// `Codec` and call `run` to get a report instead of hand-writing these tests.
+
+use std::panic::{self, AssertUnwindSafe};
+use crate::ids::splitmix64;
+use crate::{embeddings_equal, HnswParams, Metric, Precision, SeededIds, YAVS, YAVSError};
+
+pub trait Codec {
+    fn name(&self) -> String;
+    fn encode(&self, db: &YAVS) -> Result<Vec<u8>, YAVSError>;
+    fn decode(&self, bytes: &[u8]) -> Result<YAVS, YAVSError>;
+}
+
+// The built-in format, via `save_mem`/`load_mem`
+#[derive(Debug, Default)]
+pub struct NativeFormat;
+
+impl Codec for NativeFormat {
+    fn name(&self) -> String {
+        "native".to_string()
+    }
+
+    fn encode(&self, db: &YAVS) -> Result<Vec<u8>, YAVSError> {
+        db.save_mem()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<YAVS, YAVSError> {
+        YAVS::load_mem(bytes)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConformanceConfig {
+    pub seed: u64,
+    pub cases: usize,
+    pub max_dim: u32,
+    pub max_records: usize,
+    // Corrupted variants (truncations and bit flips each) tried per case
+    pub corruptions_per_case: usize,
+    // Per-component slack when comparing embeddings, for lossy codecs
+    pub embedding_tolerance: f32,
+}
+
+impl Default for ConformanceConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            cases: 64,
+            max_dim: 32,
+            max_records: 64,
+            corruptions_per_case: 8,
+            embedding_tolerance: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    RoundTrip,
+    Truncation,
+    BitFlip,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConformanceFailure {
+    pub case: usize,
+    pub check: Check,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    pub codec: String,
+    pub round_trips: usize,
+    pub truncations: usize,
+    pub bit_flips: usize,
+    // Corrupted inputs that decoded without error into a different store.
+    // Not a failure on its own: formats without checksums can't catch these.
+    pub undetected_corruptions: usize,
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+pub fn run<C: Codec>(codec: &C, config: &ConformanceConfig) -> ConformanceReport {
+    let mut report = ConformanceReport {
+        codec: codec.name(),
+        ..Default::default()
+    };
+    let mut rng = config.seed;
+
+    for case in 0..config.cases {
+        let db = random_store(&mut rng, config);
+        let mut fail = |check, detail: String| {
+            report.failures.push(ConformanceFailure { case, check, detail });
+        };
+
+        let bytes = match codec.encode(&db) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                fail(Check::RoundTrip, format!("encode failed: {}", e));
+                continue;
+            }
+        };
+
+        report.round_trips += 1;
+        match decode_guarded(codec, &bytes) {
+            Ok(Ok(decoded)) => {
+                if let Some(diff) = difference(&db, &decoded, config.embedding_tolerance) {
+                    fail(Check::RoundTrip, diff);
+                }
+            }
+            Ok(Err(e)) => fail(Check::RoundTrip, format!("decode failed: {}", e)),
+            Err(msg) => fail(Check::RoundTrip, format!("decode panicked: {}", msg)),
+        }
+
+        if bytes.is_empty() {
+            continue;
+        }
+        for _ in 0..config.corruptions_per_case {
+            let cut = (splitmix64(&mut rng) % bytes.len() as u64) as usize;
+            report.truncations += 1;
+            match decode_guarded(codec, &bytes[..cut]) {
+                Ok(Ok(decoded)) if difference(&db, &decoded, config.embedding_tolerance).is_some() => report.undetected_corruptions += 1,
+                Ok(_) => {}
+                Err(msg) => fail(Check::Truncation, format!("panicked on {} of {} bytes: {}", cut, bytes.len(), msg)),
+            }
+
+            let mut flipped = bytes.clone();
+            let pos = (splitmix64(&mut rng) % bytes.len() as u64) as usize;
+            flipped[pos] ^= 1 << (splitmix64(&mut rng) % 8);
+            report.bit_flips += 1;
+            match decode_guarded(codec, &flipped) {
+                Ok(Ok(decoded)) if difference(&db, &decoded, config.embedding_tolerance).is_some() => report.undetected_corruptions += 1,
+                Ok(_) => {}
+                Err(msg) => fail(Check::BitFlip, format!("panicked with byte {} flipped: {}", pos, msg)),
+            }
+        }
+    }
+
+    report
+}
+
+fn decode_guarded<C: Codec>(codec: &C, bytes: &[u8]) -> Result<Result<YAVS, YAVSError>, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| codec.decode(bytes))).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+// Describes the first difference between the live contents of two stores
+fn difference(a: &YAVS, b: &YAVS, eps: f32) -> Option<String> {
+    if a.dimension() != b.dimension() {
+        return Some(format!("dimension {} != {}", a.dimension(), b.dimension()));
+    }
+    if a.metric() != b.metric() {
+        return Some(format!("metric {:?} != {:?}", a.metric(), b.metric()));
+    }
+    if a.precision() != b.precision() {
+        return Some(format!("precision {:?} != {:?}", a.precision(), b.precision()));
+    }
+    if a.has_index() != b.has_index() {
+        return Some(format!("index {} != {}", a.has_index(), b.has_index()));
+    }
+    if a.has_ivf() != b.has_ivf() {
+        return Some(format!("ivf {} != {}", a.has_ivf(), b.has_ivf()));
+    }
+    if a.has_pq() != b.has_pq() {
+        return Some(format!("pq {} != {}", a.has_pq(), b.has_pq()));
+    }
+    if a.has_sq() != b.has_sq() {
+        return Some(format!("sq {} != {}", a.has_sq(), b.has_sq()));
+    }
+    if a.len() != b.len() {
+        return Some(format!("record count {} != {}", a.len(), b.len()));
+    }
+    for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+        if x.id != y.id {
+            return Some(format!("record {}: id differs", i));
+        }
+        if !embeddings_equal(&x.embedding, &y.embedding, eps) {
+            return Some(format!("record {}: embedding differs", i));
+        }
+        if x.code != y.code {
+            return Some(format!("record {}: code differs", i));
+        }
+        if x.metadata != y.metadata {
+            return Some(format!("record {}: metadata differs", i));
+        }
+        if x.tags != y.tags {
+            return Some(format!("record {}: tags differ", i));
+        }
+        if x.tenant != y.tenant {
+            return Some(format!("record {}: tenant differs", i));
+        }
+        if x.key != y.key {
+            return Some(format!("record {}: key differs", i));
+        }
+        if x.pinned != y.pinned {
+            return Some(format!("record {}: pin differs", i));
+        }
+        if x.updated_at != y.updated_at {
+            return Some(format!("record {}: timestamp differs", i));
+        }
+    }
+    None
+}
+
+fn random_store(rng: &mut u64, config: &ConformanceConfig) -> YAVS {
+    const TAGS: [&str; 4] = ["a", "b", "tenant-1", "ünï"];
+    const TENANTS: [&str; 2] = ["acme", "ünïcorp"];
+
+    let dim = 1 + (splitmix64(rng) % config.max_dim.max(1) as u64) as u32;
+    let n = (splitmix64(rng) % (config.max_records as u64 + 1)) as usize;
+
+    let metric = match splitmix64(rng) % 3 {
+        0 => Metric::Euclidean,
+        1 => Metric::Cosine,
+        _ => Metric::Dot,
+    };
+    let mut db = YAVS::with_metric(dim, metric);
+    db.set_id_source(SeededIds::new(splitmix64(rng)));
+    if cfg!(feature = "half") && splitmix64(rng).is_multiple_of(4) {
+        let _ = db.set_precision(Precision::F16);
+    }
+    if splitmix64(rng).is_multiple_of(4) {
+        db.build_index(HnswParams {
+            m: 4,
+            ef_construction: 16,
+            ef_search: 8,
+        });
+    }
+    for i in 0..n {
+        if i == n / 2 && splitmix64(rng).is_multiple_of(4) {
+            db.train_ivf(1 + (splitmix64(rng) % 4) as usize);
+        }
+        if i == n / 2 && splitmix64(rng).is_multiple_of(4) {
+            db.train_pq(1 + (splitmix64(rng) % 4) as usize);
+            if db.has_pq() && splitmix64(rng).is_multiple_of(2) {
+                let _ = db.set_precision(Precision::Pq);
+            }
+        }
+        if i == n / 2 && splitmix64(rng).is_multiple_of(4) {
+            db.train_sq();
+            if db.has_sq() && splitmix64(rng).is_multiple_of(2) {
+                let _ = db.set_precision(Precision::Int8);
+            }
+        }
+        let embedding: Vec<f32> = (0..dim)
+            .map(|_| f32::from_bits(splitmix64(rng) as u32))
+            .collect();
+        let metadata: Vec<u8> = (0..splitmix64(rng) % 64)
+            .map(|_| splitmix64(rng) as u8)
+            .collect();
+        let tags: Vec<&str> = TAGS
+            .iter()
+            .copied()
+            .filter(|_| splitmix64(rng).is_multiple_of(4))
+            .collect();
+        let inserted = match splitmix64(rng) % 4 {
+            0 => db.tenant(TENANTS[0]).insert_tagged(&embedding, &metadata, &tags),
+            1 => db.tenant(TENANTS[1]).insert_tagged(&embedding, &metadata, &tags),
+            2 => {
+                let key = splitmix64(rng).to_be_bytes();
+                db.insert_keyed_tagged(&key, &embedding, &metadata, &tags)
+            }
+            _ => db.insert_tagged(&embedding, &metadata, &tags),
+        };
+        if let Ok(id) = inserted {
+            if splitmix64(rng).is_multiple_of(8) {
+                db.remove(&id);
+            } else if splitmix64(rng).is_multiple_of(8) {
+                db.pin(&id);
+            }
+        }
+    }
+    db
+}
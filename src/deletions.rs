@@ -0,0 +1,83 @@
+// Deletion tracking for downstream reconciliation. Tombstones live in memory
+// until compaction purges them; with the compaction log enabled, every purge
+// is recorded so replicas and analytics can still learn about deletions that
+// `save` or auto-compaction made disappear.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use crate::YAVS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub id: [u8; 16],
+    pub deleted_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionEntry {
+    // Store clock time of the compaction
+    pub at: u64,
+    pub purged: Vec<Tombstone>,
+}
+
+impl YAVS {
+    // Records deleted but not yet compacted away
+    pub fn tombstones(&self) -> Vec<Tombstone> {
+        self.records
+            .iter()
+            .filter(|r| r.deleted)
+            .map(|r| Tombstone { id: r.id, deleted_at: r.updated_at })
+            .collect()
+    }
+
+    // Starts or stops recording what each compaction purges. Disabling also
+    // drops the history collected so far.
+    pub fn set_compaction_log(&mut self, enabled: bool) {
+        self.compaction_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn compaction_log(&self) -> &[CompactionEntry] {
+        self.compaction_log.as_deref().unwrap_or(&[])
+    }
+
+    // Returns and clears the compaction history, e.g. after shipping it to a replica
+    pub fn take_compaction_log(&mut self) -> Vec<CompactionEntry> {
+        self.compaction_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Applies deletions exported from another copy of the store. A tombstone
+    // only wins over a record last written before it. Returns how many live
+    // records were deleted; none if they couldn't be written to the store's
+    // write-ahead log.
+    pub fn apply_tombstones(&mut self, tombstones: &[Tombstone]) -> usize {
+        let mut removed = Vec::new();
+        let mut seen = HashSet::new();
+        for t in tombstones {
+            if let Some(i) = self.position(&t.id) {
+                if self.records[i].updated_at <= t.deleted_at && seen.insert(i) {
+                    removed.push((i, t.deleted_at));
+                }
+            }
+        }
+        let logged: Vec<Tombstone> = removed.iter().map(|&(i, deleted_at)| Tombstone { id: self.records[i].id, deleted_at }).collect();
+        if removed.is_empty() || self.log_tombstones(&logged).is_err() {
+            return 0;
+        }
+        for (i, deleted_at) in removed.iter().copied() {
+            self.records[i].tombstone(deleted_at);
+        }
+        self.mark_changed();
+        self.maybe_compact();
+        removed.len()
+    }
+
+    pub(crate) fn log_purged(&mut self, purged: Vec<Tombstone>) {
+        if purged.is_empty() {
+            return;
+        }
+        let at = self.clock.now_ms();
+        if let Some(log) = self.compaction_log.as_mut() {
+            log.push(CompactionEntry { at, purged });
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Comparing two versions of a store, e.g. validating a nightly rebuild
+// against the index currently deployed. Only live records are compared.
+
+use std::collections::HashMap;
+use crate::{embeddings_equal, euclidean, Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordChange {
+    pub id: [u8; 16],
+    // Euclidean distance between the old and new embedding
+    pub embedding_delta: f32,
+    pub metadata_changed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreDiff {
+    // In `b` but not `a`
+    pub added: Vec<[u8; 16]>,
+    // In `a` but not `b`
+    pub removed: Vec<[u8; 16]>,
+    pub changed: Vec<RecordChange>,
+    pub unchanged: usize,
+}
+
+impl StoreDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl YAVS {
+    pub fn diff(a: &YAVS, b: &YAVS) -> Result<StoreDiff, YAVSError> {
+        Self::diff_with_tolerance(a, b, 0.0)
+    }
+
+    // Like `diff`, but embeddings within `eps` per component count as unchanged
+    pub fn diff_with_tolerance(a: &YAVS, b: &YAVS, eps: f32) -> Result<StoreDiff, YAVSError> {
+        if a.dim != b.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let mut diff = StoreDiff::default();
+
+        let old: HashMap<[u8; 16], &Record> = a.iter().map(|r| (r.id, r)).collect();
+        for rec in b.iter() {
+            let Some(prev) = old.get(&rec.id) else {
+                diff.added.push(rec.id);
+                continue;
+            };
+            let (prev_embedding, embedding) = (a.vector(prev), b.vector(rec));
+            let same_embedding = embeddings_equal(&prev_embedding, &embedding, eps);
+            let metadata_changed = prev.metadata != rec.metadata;
+            if same_embedding && !metadata_changed {
+                diff.unchanged += 1;
+            } else {
+                diff.changed.push(RecordChange {
+                    id: rec.id,
+                    embedding_delta: if same_embedding { 0.0 } else { euclidean(&prev_embedding, &embedding) },
+                    metadata_changed,
+                });
+            }
+        }
+
+        let new: HashMap<[u8; 16], &Record> = b.iter().map(|r| (r.id, r)).collect();
+        diff.removed = a.ids().filter(|id| !new.contains_key(id)).collect();
+        Ok(diff)
+    }
+}
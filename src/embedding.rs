@@ -0,0 +1,92 @@
+// Dimension-checked embeddings. `Embedding<D>` can only hold exactly `D`
+// values, and `YAVS::typed::<D>()` checks `D` against the store once, so the
+// `Typed` handle's inserts and queries can't fail with `DimMismatch`: a
+// wrong-sized vector is a compile error, or an error where it's converted
+// from a slice.
+
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Embedding<const D: usize>([f32; D]);
+
+impl<const D: usize> Embedding<D> {
+    pub fn new(values: [f32; D]) -> Self {
+        Self(values)
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> [f32; D] {
+        self.0
+    }
+}
+
+impl<const D: usize> From<[f32; D]> for Embedding<D> {
+    fn from(values: [f32; D]) -> Self {
+        Self(values)
+    }
+}
+
+impl<const D: usize> TryFrom<&[f32]> for Embedding<D> {
+    type Error = YAVSError;
+
+    fn try_from(values: &[f32]) -> Result<Self, YAVSError> {
+        values.try_into().map(Self).map_err(|_| YAVSError::DimMismatch)
+    }
+}
+
+impl<const D: usize> TryFrom<Vec<f32>> for Embedding<D> {
+    type Error = YAVSError;
+
+    fn try_from(values: Vec<f32>) -> Result<Self, YAVSError> {
+        Self::try_from(values.as_slice())
+    }
+}
+
+impl<const D: usize> AsRef<[f32]> for Embedding<D> {
+    fn as_ref(&self) -> &[f32] {
+        &self.0
+    }
+}
+
+pub struct Typed<'a, const D: usize> {
+    db: &'a mut YAVS,
+}
+
+impl YAVS {
+    // Fails unless the store's dimension is `D`
+    pub fn typed<const D: usize>(&mut self) -> Result<Typed<'_, D>, YAVSError> {
+        if D as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        Ok(Typed { db: self })
+    }
+}
+
+impl<const D: usize> Typed<'_, D> {
+    pub fn insert(&mut self, embedding: &Embedding<D>, metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.db.insert(&embedding.0, metadata)
+    }
+
+    pub fn insert_tagged(&mut self, embedding: &Embedding<D>, metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        self.db.insert_tagged(&embedding.0, metadata, tags)
+    }
+
+    pub fn query(&self, query_embedding: &Embedding<D>, k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        self.db.query(&query_embedding.0, k)
+    }
+
+    pub fn query_tagged(&self, query_embedding: &Embedding<D>, k: usize, tags: &[&str]) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        self.db.query_tagged(&query_embedding.0, k, tags)
+    }
+
+    pub fn embedding(&self, id: &[u8; 16]) -> Option<Embedding<D>> {
+        self.db.decoded_embedding(id)?.as_ref().try_into().ok()
+    }
+
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        self.db.remove(id)
+    }
+}
@@ -0,0 +1,105 @@
+// Encryption at rest with AES-256-GCM, for stores kept on shared disks.
+// Requires the `encryption` feature.
+//
+// An encrypted file is the plain store file with everything after the
+// header sealed: header, then a random 12-byte nonce, then the ciphertext and
+// its tag. The header stays readable, with a flag set that makes the other
+// loaders refuse the file, and is authenticated as associated data, so a
+// wrong key or a change to any byte fails with `DecryptionFailed`.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use crate::format::{FLAGS_OFFSET, FLAG_ENCRYPTED, HEADER_SIZE, MAGIC};
+use crate::{write_atomic, YAVS, YAVSError};
+
+const NONCE_LEN: usize = 12;
+
+impl YAVS {
+    pub fn save_encrypted<P: AsRef<Path>>(&self, path: P, key: &[u8; 32]) -> Result<(), YAVSError> {
+        let sealed = self.save_encrypted_mem(key)?;
+        write_atomic(path.as_ref(), 0, |w| Ok(w.write_all(&sealed)?))
+    }
+
+    pub fn save_encrypted_mem(&self, key: &[u8; 32]) -> Result<Vec<u8>, YAVSError> {
+        let plain = self.save_mem()?;
+        let (header, body) = plain.split_at(HEADER_SIZE as usize);
+        let mut out = header.to_vec();
+        set_encrypted(&mut out, true);
+        let sealed = seal(key, body, &out)?;
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    pub fn load_encrypted<P: AsRef<Path>>(path: P, key: &[u8; 32]) -> Result<Self, YAVSError> {
+        Self::load_encrypted_mem(&fs::read(path)?, key)
+    }
+
+    // Fails with `InvalidFile` for a file that isn't encrypted
+    pub fn load_encrypted_mem(buf: &[u8], key: &[u8; 32]) -> Result<Self, YAVSError> {
+        let header_len = HEADER_SIZE as usize;
+        if buf.len() < header_len + NONCE_LEN || &buf[..4] != MAGIC || flags(buf) & FLAG_ENCRYPTED == 0 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let (header, sealed) = buf.split_at(header_len);
+        let body = open(key, sealed, header)?;
+        let mut plain = Vec::with_capacity(header_len + body.len());
+        plain.extend_from_slice(header);
+        set_encrypted(&mut plain, false);
+        plain.extend_from_slice(&body);
+        Self::load_mem(&plain)
+    }
+}
+
+fn flags(header: &[u8]) -> u32 {
+    let at = FLAGS_OFFSET as usize;
+    u32::from_le_bytes(header[at..at + 4].try_into().unwrap_or_default())
+}
+
+fn set_encrypted(header: &mut [u8], on: bool) {
+    let flags = match on {
+        true => flags(header) | FLAG_ENCRYPTED,
+        false => flags(header) & !FLAG_ENCRYPTED,
+    };
+    let at = FLAGS_OFFSET as usize;
+    header[at..at + 4].copy_from_slice(&flags.to_le_bytes());
+}
+
+// Nonce, then ciphertext and tag
+#[cfg(feature = "encryption")]
+fn seal(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+    use aes_gcm::Aes256Gcm;
+
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| std::io::Error::other("store too large to encrypt"))?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+#[cfg(feature = "encryption")]
+fn open(key: &[u8; 32], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    let cipher = Aes256Gcm::new(key.into());
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| YAVSError::DecryptionFailed)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn seal(_: &[u8; 32], _: &[u8], _: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    Err(YAVSError::FeatureDisabled("encryption"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn open(_: &[u8; 32], _: &[u8], _: &[u8]) -> Result<Vec<u8>, YAVSError> {
+    Err(YAVSError::FeatureDisabled("encryption"))
+}
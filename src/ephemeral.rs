@@ -0,0 +1,123 @@
+// Scratch store for batch jobs. It starts purely in memory; once more than
+// `max_in_memory` records are held, the oldest half is appended to a private
+// temp file and only their IDs stay in memory. Queries scan the spill file
+// sequentially. The file is deleted when the store is dropped.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use crate::format::{read_record, write_record};
+use crate::{closest_first, Precision, Record, YAVS, YAVSError};
+
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    ids: HashSet<[u8; 16]>,
+    removed: HashSet<[u8; 16]>,
+}
+
+#[derive(Debug)]
+pub struct EphemeralYAVS {
+    hot: YAVS,
+    max_in_memory: usize,
+    spill: Option<Spill>,
+}
+
+impl EphemeralYAVS {
+    pub fn new(dim: u32, max_in_memory: usize) -> Self {
+        Self {
+            hot: YAVS::new(dim),
+            max_in_memory: max_in_memory.max(1),
+            spill: None,
+        }
+    }
+
+    pub fn dimension(&self) -> u32 {
+        self.hot.dimension()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hot.len() + self.spilled()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Live records currently on disk
+    pub fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |s| s.ids.len() - s.removed.len())
+    }
+
+    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        let id = self.hot.insert(embedding, metadata)?;
+        if self.hot.len() > self.max_in_memory {
+            self.spill_oldest()?;
+        }
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        if self.hot.remove(id) {
+            return true;
+        }
+        match &mut self.spill {
+            Some(spill) if spill.ids.contains(id) => spill.removed.insert(*id),
+            _ => false,
+        }
+    }
+
+    pub fn query(&mut self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        let mut results = self.hot.query(query_embedding, k)?;
+        if let Some(spill) = &mut self.spill {
+            spill.writer.flush()?;
+            let mut reader = BufReader::new(File::open(&spill.path)?);
+            for _ in 0..spill.ids.len() {
+                let rec = read_record(&mut reader, self.hot.dim, Precision::F32.into())?;
+                if !spill.removed.contains(&rec.id) {
+                    results.push((rec.id, self.hot.metric.distance(&rec.embedding, query_embedding)));
+                }
+            }
+        }
+        results.sort_by(|a, b| closest_first(&self.hot.metric, a, b));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn spill_oldest(&mut self) -> Result<(), YAVSError> {
+        if self.spill.is_none() {
+            let path = std::env::temp_dir().join(format!("yavs-spill-{}.tmp", uuid::Uuid::new_v4()));
+            let file = OpenOptions::new().create_new(true).append(true).open(&path)?;
+            self.spill = Some(Spill {
+                path,
+                writer: BufWriter::new(file),
+                ids: HashSet::new(),
+                removed: HashSet::new(),
+            });
+        }
+        let Some(spill) = &mut self.spill else {
+            return Ok(());
+        };
+
+        self.hot.compact();
+        let n = self.hot.records.len() / 2;
+        let cold: Vec<Record> = self.hot.records.drain(..n).collect();
+        self.hot.rebuild_indexes();
+        for rec in &cold {
+            write_record(&mut spill.writer, rec, Precision::F32.into())?;
+            spill.ids.insert(rec.id);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EphemeralYAVS {
+    fn drop(&mut self) {
+        if let Some(spill) = self.spill.take() {
+            drop(spill.writer);
+            let _ = fs::remove_file(spill.path);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Retrieval quality tracking. A fixed evaluation set of queries with known
+// relevant records is saved with the store, and each `evaluate` run appends
+// its precision, recall and NDCG to a persisted history, so a regression
+// after re-embedding or changing index settings shows up as a drop between
+// runs.
+
+use std::collections::HashSet;
+use crate::format::{read_u32, read_u64};
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalQuery {
+    pub embedding: Vec<f32>,
+    pub relevant: Vec<[u8; 16]>,
+}
+
+// Means over the evaluation queries, each in [0, 1]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EvalScores {
+    pub precision: f64,
+    pub recall: f64,
+    pub ndcg: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalRun {
+    pub label: String,
+    // Store clock time of the run
+    pub at_ms: u64,
+    pub k: usize,
+    pub scores: EvalScores,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct EvalSet {
+    queries: Vec<EvalQuery>,
+    history: Vec<EvalRun>,
+}
+
+impl EvalSet {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queries.is_empty() && self.history.is_empty()
+    }
+
+    pub(crate) fn dim_matches(&self, dim: u32) -> bool {
+        self.queries.iter().all(|q| q.embedding.len() as u32 == dim)
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.queries.len() as u64).to_le_bytes());
+        for query in &self.queries {
+            payload.extend_from_slice(&(query.embedding.len() as u32).to_le_bytes());
+            for v in &query.embedding {
+                payload.extend_from_slice(&v.to_le_bytes());
+            }
+            payload.extend_from_slice(&(query.relevant.len() as u32).to_le_bytes());
+            for id in &query.relevant {
+                payload.extend_from_slice(id);
+            }
+        }
+        payload.extend_from_slice(&(self.history.len() as u64).to_le_bytes());
+        for run in &self.history {
+            payload.extend_from_slice(&(run.label.len() as u32).to_le_bytes());
+            payload.extend_from_slice(run.label.as_bytes());
+            payload.extend_from_slice(&run.at_ms.to_le_bytes());
+            payload.extend_from_slice(&(run.k as u64).to_le_bytes());
+            for score in [run.scores.precision, run.scores.recall, run.scores.ndcg] {
+                payload.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        payload
+    }
+
+    pub(crate) fn decode(payload: &mut &[u8]) -> Result<Self, YAVSError> {
+        let mut set = Self::default();
+        let n_queries = read_u64(payload)?;
+        for _ in 0..n_queries {
+            let dim = read_u32(payload)? as usize;
+            if dim.checked_mul(4).is_none_or(|n| n > payload.len()) {
+                return Err(YAVSError::InvalidFile);
+            }
+            let mut embedding = Vec::with_capacity(dim);
+            for _ in 0..dim {
+                embedding.push(f32::from_bits(read_u32(payload)?));
+            }
+            let n_relevant = read_u32(payload)? as usize;
+            if n_relevant.checked_mul(16).is_none_or(|n| n > payload.len()) {
+                return Err(YAVSError::InvalidFile);
+            }
+            let relevant = (0..n_relevant)
+                .map(|_| read_id(payload))
+                .collect::<Result<_, _>>()?;
+            set.queries.push(EvalQuery { embedding, relevant });
+        }
+
+        let n_runs = read_u64(payload)?;
+        for _ in 0..n_runs {
+            let len = read_u32(payload)? as usize;
+            if len > payload.len() {
+                return Err(YAVSError::InvalidFile);
+            }
+            let (label, rest) = payload.split_at(len);
+            let label = String::from_utf8(label.to_vec()).map_err(|_| YAVSError::InvalidFile)?;
+            *payload = rest;
+            let at_ms = read_u64(payload)?;
+            let k = read_u64(payload)? as usize;
+            let mut score = || read_u64(payload).map(f64::from_bits);
+            let scores = EvalScores {
+                precision: score()?,
+                recall: score()?,
+                ndcg: score()?,
+            };
+            set.history.push(EvalRun { label, at_ms, k, scores });
+        }
+        Ok(set)
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        let queries: u64 = self.queries
+            .iter()
+            .map(|q| 4 + 4 * q.embedding.len() as u64 + 4 + 16 * q.relevant.len() as u64)
+            .sum();
+        let runs: u64 = self.history.iter().map(|r| 4 + r.label.len() as u64 + 8 + 8 + 3 * 8).sum();
+        8 + queries + 8 + runs
+    }
+}
+
+fn read_id(payload: &mut &[u8]) -> Result<[u8; 16], YAVSError> {
+    let (id, rest) = payload.split_first_chunk::<16>().ok_or(YAVSError::InvalidFile)?;
+    *payload = rest;
+    Ok(*id)
+}
+
+// Scores of one ranked result list against the relevant set
+fn score_query(results: &[[u8; 16]], relevant: &HashSet<[u8; 16]>, k: usize) -> EvalScores {
+    let ideal: f64 = (0..k.min(relevant.len())).map(|i| 1.0 / (i as f64 + 2.0).log2()).sum();
+    let mut hits = 0;
+    let mut dcg = 0.0;
+    for (i, id) in results.iter().take(k).enumerate() {
+        if relevant.contains(id) {
+            hits += 1;
+            dcg += 1.0 / (i as f64 + 2.0).log2();
+        }
+    }
+    EvalScores {
+        precision: if k == 0 { 0.0 } else { hits as f64 / k as f64 },
+        recall: if relevant.is_empty() { 0.0 } else { hits as f64 / relevant.len() as f64 },
+        ndcg: if ideal == 0.0 { 0.0 } else { dcg / ideal },
+    }
+}
+
+impl YAVS {
+    // Replaces the evaluation set. History from earlier runs is kept.
+    pub fn set_eval_set(&mut self, queries: Vec<EvalQuery>) -> Result<(), YAVSError> {
+        if queries.iter().any(|q| q.embedding.len() as u32 != self.dim) {
+            return Err(YAVSError::DimMismatch);
+        }
+        self.eval.queries = queries;
+        self.mark_changed();
+        Ok(())
+    }
+
+    pub fn eval_set(&self) -> &[EvalQuery] {
+        &self.eval.queries
+    }
+
+    pub fn eval_history(&self) -> &[EvalRun] {
+        &self.eval.history
+    }
+
+    pub fn clear_eval_history(&mut self) {
+        if !self.eval.history.is_empty() {
+            self.eval.history.clear();
+            self.mark_changed();
+        }
+    }
+
+    // Runs the evaluation set through `query` and records the scores under
+    // `label`
+    pub fn evaluate(&mut self, label: &str, k: usize) -> Result<EvalRun, YAVSError> {
+        self.evaluate_with(label, k, |db, q, k| db.query(q, k))
+    }
+
+    // Like `evaluate`, but retrieves with `search`, e.g. to score
+    // `query_ivf` at some nprobe
+    pub fn evaluate_with<F>(&mut self, label: &str, k: usize, search: F) -> Result<EvalRun, YAVSError>
+    where
+        F: Fn(&YAVS, &[f32], usize) -> Result<Vec<([u8; 16], f32)>, YAVSError>,
+    {
+        let mut total = EvalScores::default();
+        for query in &self.eval.queries {
+            let results: Vec<[u8; 16]> = search(self, &query.embedding, k)?.into_iter().map(|(id, _)| id).collect();
+            let relevant: HashSet<[u8; 16]> = query.relevant.iter().copied().collect();
+            let scores = score_query(&results, &relevant, k);
+            total.precision += scores.precision;
+            total.recall += scores.recall;
+            total.ndcg += scores.ndcg;
+        }
+        let n = self.eval.queries.len().max(1) as f64;
+        let run = EvalRun {
+            label: label.to_string(),
+            at_ms: self.clock.now_ms(),
+            k,
+            scores: EvalScores {
+                precision: total.precision / n,
+                recall: total.recall / n,
+                ndcg: total.ndcg / n,
+            },
+        };
+        self.eval.history.push(run.clone());
+        self.mark_changed();
+        Ok(run)
+    }
+}
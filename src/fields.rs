@@ -0,0 +1,305 @@
+// Secondary indexes on top-level fields of JSON metadata, so filtered
+// queries pick their candidates from an index instead of parsing every
+// record's metadata. String fields are indexed for equality and numeric
+// fields for ranges. Records whose metadata isn't a JSON object, or lacks
+// the field or has it with another type, are left out of that field's index.
+// Only the declarations are saved; the indexes are rebuilt on load.
+
+use std::collections::{BTreeMap, HashSet};
+use crate::cache::Scope;
+use crate::format::{read_string, read_u32};
+use crate::postings::Postings;
+use crate::{Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    Number,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldFilter<'a> {
+    // A string field with this value
+    Equals(&'a str, &'a str),
+    // A numeric field within these bounds, inclusive
+    Between(&'a str, f64, f64),
+}
+
+impl FieldFilter<'_> {
+    fn field(&self) -> &str {
+        match self {
+            FieldFilter::Equals(field, _) | FieldFilter::Between(field, _, _) => field,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Values {
+    String(Postings),
+    // Keyed by `number_key`; NaNs aren't indexed
+    Number(BTreeMap<u64, Vec<usize>>),
+}
+
+#[derive(Debug)]
+struct FieldIndex {
+    name: String,
+    values: Values,
+}
+
+impl FieldIndex {
+    fn new(name: &str, kind: FieldKind) -> Self {
+        let values = match kind {
+            FieldKind::String => Values::String(Postings::default()),
+            FieldKind::Number => Values::Number(BTreeMap::new()),
+        };
+        Self { name: name.to_string(), values }
+    }
+
+    fn kind(&self) -> FieldKind {
+        match self.values {
+            Values::String(_) => FieldKind::String,
+            Values::Number(_) => FieldKind::Number,
+        }
+    }
+
+    fn add(&mut self, object: &serde_json::Map<String, serde_json::Value>, pos: usize) {
+        match (&mut self.values, object.get(&self.name)) {
+            (Values::String(postings), Some(serde_json::Value::String(s))) => postings.add(s, pos),
+            (Values::Number(numbers), Some(serde_json::Value::Number(n))) => {
+                if let Some(key) = n.as_f64().and_then(number_key) {
+                    numbers.entry(key).or_default().push(pos);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn remove(&mut self, object: &serde_json::Map<String, serde_json::Value>, pos: usize) {
+        match (&mut self.values, object.get(&self.name)) {
+            (Values::String(postings), Some(serde_json::Value::String(s))) => postings.remove(s, pos),
+            (Values::Number(numbers), Some(serde_json::Value::Number(n))) => {
+                let Some(key) = n.as_f64().and_then(number_key) else {
+                    return;
+                };
+                if let Some(positions) = numbers.get_mut(&key) {
+                    positions.retain(|&p| p != pos);
+                    if positions.is_empty() {
+                        numbers.remove(&key);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn relocate(&mut self, object: &serde_json::Map<String, serde_json::Value>, from: usize, to: usize) {
+        match (&mut self.values, object.get(&self.name)) {
+            (Values::String(postings), Some(serde_json::Value::String(s))) => postings.relocate(s, from, to),
+            (Values::Number(numbers), Some(serde_json::Value::Number(n))) => {
+                let positions = n.as_f64().and_then(number_key).and_then(|key| numbers.get_mut(&key));
+                for p in positions.into_iter().flatten().filter(|p| **p == from) {
+                    *p = to;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Positions matching `filter`, deleted records included; None if the
+    // filter doesn't fit the field's kind
+    fn matching(&self, filter: &FieldFilter<'_>) -> Option<Vec<usize>> {
+        match (&self.values, filter) {
+            (Values::String(postings), FieldFilter::Equals(_, value)) => Some(postings.get(value).to_vec()),
+            (Values::Number(numbers), &FieldFilter::Between(_, min, max)) => {
+                let (Some(lo), Some(hi)) = (number_key(min), number_key(max)) else {
+                    return Some(Vec::new());
+                };
+                if lo > hi {
+                    return Some(Vec::new());
+                }
+                Some(numbers.range(lo..=hi).flat_map(|(_, p)| p.iter().copied()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+// Order-preserving map of non-NaN floats onto u64s
+fn number_key(v: f64) -> Option<u64> {
+    if v.is_nan() {
+        return None;
+    }
+    let v = if v == 0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    Some(if bits >> 63 == 1 { !bits } else { bits | 1 << 63 })
+}
+
+fn json_object(metadata: &[u8]) -> Option<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::from_slice(metadata) {
+        Ok(serde_json::Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FieldIndexes {
+    fields: Vec<FieldIndex>,
+}
+
+impl FieldIndexes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for field in &mut self.fields {
+            let empty = FieldIndex::new(&field.name, field.kind());
+            *field = empty;
+        }
+    }
+
+    pub(crate) fn add(&mut self, pos: usize, metadata: &[u8]) {
+        if self.fields.is_empty() {
+            return;
+        }
+        if let Some(object) = json_object(metadata) {
+            for field in &mut self.fields {
+                field.add(&object, pos);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, pos: usize, metadata: &[u8]) {
+        if self.fields.is_empty() {
+            return;
+        }
+        if let Some(object) = json_object(metadata) {
+            for field in &mut self.fields {
+                field.remove(&object, pos);
+            }
+        }
+    }
+
+    pub(crate) fn relocate(&mut self, from: usize, to: usize, metadata: &[u8]) {
+        if self.fields.is_empty() {
+            return;
+        }
+        if let Some(object) = json_object(metadata) {
+            for field in &mut self.fields {
+                field.relocate(&object, from, to);
+            }
+        }
+    }
+
+    // Field declarations: count, then each name and kind
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.size() as usize);
+        payload.extend_from_slice(&(self.fields.len() as u32).to_le_bytes());
+        for field in &self.fields {
+            payload.extend_from_slice(&(field.name.len() as u32).to_le_bytes());
+            payload.extend_from_slice(field.name.as_bytes());
+            let kind: u32 = match field.kind() {
+                FieldKind::String => 0,
+                FieldKind::Number => 1,
+            };
+            payload.extend_from_slice(&kind.to_le_bytes());
+        }
+        payload
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        4 + self.fields.iter().map(|f| 4 + f.name.len() as u64 + 4).sum::<u64>()
+    }
+
+    // Declarations only; the caller fills the indexes from the records
+    pub(crate) fn decode(payload: &mut &[u8]) -> Result<Self, YAVSError> {
+        let count = read_u32(payload)?;
+        let mut indexes = Self::default();
+        for _ in 0..count {
+            let name = read_string(payload)?;
+            let kind = match read_u32(payload)? {
+                0 => FieldKind::String,
+                1 => FieldKind::Number,
+                _ => return Err(YAVSError::InvalidFile),
+            };
+            indexes.fields.push(FieldIndex::new(&name, kind));
+        }
+        Ok(indexes)
+    }
+
+    pub(crate) fn fill(&mut self, records: &[Record]) {
+        self.clear();
+        for (i, rec) in records.iter().enumerate() {
+            self.add(i, &rec.metadata);
+        }
+    }
+}
+
+impl YAVS {
+    // Indexes a top-level field of JSON metadata for `query_fields` and
+    // `ids_where`. Redeclaring a field with another kind replaces its index.
+    pub fn add_field_index(&mut self, name: &str, kind: FieldKind) {
+        let fields = &mut self.fields.fields;
+        match fields.iter_mut().find(|f| f.name == name) {
+            Some(field) if field.kind() == kind => return,
+            Some(field) => *field = FieldIndex::new(name, kind),
+            None => fields.push(FieldIndex::new(name, kind)),
+        }
+        self.fields.fill(&self.records);
+        self.mark_changed();
+    }
+
+    pub fn drop_field_index(&mut self, name: &str) -> bool {
+        let before = self.fields.fields.len();
+        self.fields.fields.retain(|f| f.name != name);
+        let dropped = self.fields.fields.len() < before;
+        if dropped {
+            self.mark_changed();
+        }
+        dropped
+    }
+
+    pub fn field_indexes(&self) -> Vec<(&str, FieldKind)> {
+        self.fields.fields.iter().map(|f| (f.name.as_str(), f.kind())).collect()
+    }
+
+    // IDs of the live records matching every filter. Each filter's field must
+    // be indexed with the matching kind, or this fails with `FieldNotIndexed`.
+    pub fn ids_where(&self, filters: &[FieldFilter<'_>]) -> Result<Vec<[u8; 16]>, YAVSError> {
+        Ok(self.field_candidates(filters)?.map(|r| r.id).collect())
+    }
+
+    // Like `query`, but only over the live records matching every filter (see
+    // `ids_where`)
+    pub fn query_fields(&self, query_embedding: &[f32], k: usize, filters: &[FieldFilter<'_>]) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        if filters.is_empty() {
+            return self.query(query_embedding, k);
+        }
+        let candidates = self.field_candidates(filters)?;
+        Ok(self.rank(started, Scope::Fields(filters), self.metric, candidates, query_embedding, k))
+    }
+
+    fn field_candidates<'a>(&'a self, filters: &[FieldFilter<'_>]) -> Result<impl Iterator<Item = &'a Record> + 'a, YAVSError> {
+        let mut matches = Vec::with_capacity(filters.len());
+        for filter in filters {
+            let positions = self.fields.fields
+                .iter()
+                .find(|f| f.name == filter.field())
+                .and_then(|f| f.matching(filter))
+                .ok_or_else(|| YAVSError::FieldNotIndexed(filter.field().to_string()))?;
+            matches.push(positions);
+        }
+        // Walk the shortest list, checking the others through sets
+        matches.sort_by_key(|m| m.len());
+        let mut lists = matches.into_iter();
+        let mut positions = lists.next().unwrap_or_else(|| (0..self.records.len()).collect());
+        positions.sort_unstable();
+        let others: Vec<HashSet<usize>> = lists.map(|l| l.into_iter().collect()).collect();
+        positions.retain(|p| others.iter().all(|s| s.contains(p)));
+        Ok(positions.into_iter().map(|i| &self.records[i]).filter(|r| !r.deleted))
+    }
+}
@@ -0,0 +1,1122 @@
+// Binary layout shared by the file and in-memory load/save paths.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::io::{Read, Write};
+use crc32fast::Hasher;
+use crate::compression;
+use crate::eval::EvalSet;
+use crate::fields::FieldIndexes;
+use crate::kv::KvStore;
+use crate::index::Hnsw;
+use crate::ivf::Ivf;
+use crate::pq::ProductQuantizer;
+use crate::sq::ScalarQuantizer;
+use crate::precision::{from_f16, to_f16};
+use crate::{Metric, Precision, Provenance, Record, YAVS, YAVSError};
+
+pub(crate) const MAGIC: &[u8] = b"YAVS";
+pub(crate) const VERSION: u32 = 2;
+pub(crate) const RESERVED_SIZE: usize = 16;
+
+// Version 2 files may carry optional sections after the records. Each one is
+// written as kind (u32), payload length (u64), payload. Unknown kinds are skipped
+// so older readers can still open files written by newer ones.
+const SECTION_TAGS: u32 = 1;
+const SECTION_TENANTS: u32 = 2;
+const SECTION_TIMESTAMPS: u32 = 3;
+const SECTION_KEYS: u32 = 4;
+const SECTION_PINS: u32 = 5;
+const SECTION_INDEX: u32 = 6;
+const SECTION_IVF: u32 = 7;
+const SECTION_PQ: u32 = 8;
+const SECTION_SQ: u32 = 9;
+const SECTION_EVAL: u32 = 10;
+const SECTION_CHECKSUMS: u32 = 11;
+const SECTION_TOMBSTONES: u32 = 12;
+const SECTION_PROVENANCE: u32 = 13;
+const SECTION_FIELDS: u32 = 14;
+const SECTION_KV: u32 = 15;
+
+// The rest of the reserved header bytes: a CRC32 digest of the whole file
+// (taken with the digest itself zeroed), then flags saying whether it is set,
+// whether metadata is zstd-compressed and whether the rest of the file is
+// encrypted. Files from before digests have zeros here and are read unchecked.
+const DIGEST_AT: usize = 8;
+const FLAG_DIGEST: u32 = 1;
+const FLAG_ZSTD_METADATA: u32 = 2;
+pub(crate) const FLAG_ENCRYPTED: u32 = 4;
+pub(crate) const DIGEST_OFFSET: u64 = HEADER_SIZE - RESERVED_SIZE as u64 + DIGEST_AT as u64;
+pub(crate) const FLAGS_OFFSET: u64 = DIGEST_OFFSET + 4;
+
+const PREALLOC_LIMIT: u64 = 1 << 16;
+
+// How records are encoded in a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Encoding {
+    pub(crate) precision: Precision,
+    pub(crate) compressed: bool,
+    // Code length under Pq precision, kept in the upper half of the
+    // precision word
+    pub(crate) subspaces: u32,
+}
+
+impl Encoding {
+    // Bytes of each record's embedding, or its code, in the record block
+    pub(crate) fn embedding_size(self, dim: u32) -> u64 {
+        match self.precision {
+            Precision::Pq => self.subspaces as u64,
+            precision => precision.width() * dim as u64,
+        }
+    }
+}
+
+impl From<Precision> for Encoding {
+    fn from(precision: Precision) -> Self {
+        Self { precision, compressed: false, subspaces: 0 }
+    }
+}
+
+impl YAVS {
+    pub(crate) fn encoding(&self) -> Encoding {
+        let subspaces = match (self.precision, &self.pq) {
+            (Precision::Pq, Some(pq)) => pq.n_subspaces() as u32,
+            _ => 0,
+        };
+        Encoding {
+            precision: self.precision,
+            compressed: self.compress_metadata,
+            subspaces,
+        }
+    }
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W, n_records: u64, dim: u32, metric: Metric, enc: Encoding, digest: Option<u32>) -> Result<(), YAVSError> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&n_records.to_le_bytes())?;
+    w.write_all(&dim.to_le_bytes())?;
+    let mut reserved = [0u8; RESERVED_SIZE];
+    reserved[..4].copy_from_slice(&metric.code().to_le_bytes());
+    reserved[4..8].copy_from_slice(&(enc.precision.code() | enc.subspaces << 16).to_le_bytes());
+    reserved[DIGEST_AT..].copy_from_slice(&digest_field(digest, enc));
+    w.write_all(&reserved)?;
+    Ok(())
+}
+
+// The digest and flags words of the header
+pub(crate) fn digest_field(digest: Option<u32>, enc: Encoding) -> [u8; 8] {
+    let mut field = [0u8; 8];
+    let mut flags = 0;
+    if let Some(digest) = digest {
+        field[..4].copy_from_slice(&digest.to_le_bytes());
+        flags |= FLAG_DIGEST;
+    }
+    if enc.compressed {
+        flags |= FLAG_ZSTD_METADATA;
+    }
+    field[4..].copy_from_slice(&flags.to_le_bytes());
+    field
+}
+
+fn read_flags(reserved: &[u8; RESERVED_SIZE]) -> u32 {
+    u32::from_le_bytes(reserved[DIGEST_AT + 4..].try_into().unwrap_or_default())
+}
+
+// The metric word at the start of the reserved header bytes
+pub(crate) fn read_metric(reserved: &[u8; RESERVED_SIZE]) -> Result<Metric, YAVSError> {
+    let code = u32::from_le_bytes([reserved[0], reserved[1], reserved[2], reserved[3]]);
+    Metric::from_code(code).ok_or(YAVSError::InvalidFile)
+}
+
+// Precision (from the precision word after the metric) and the compression
+// flag
+pub(crate) fn read_encoding(reserved: &[u8; RESERVED_SIZE]) -> Result<Encoding, YAVSError> {
+    if read_flags(reserved) & FLAG_ENCRYPTED != 0 {
+        return Err(YAVSError::Encrypted);
+    }
+    let compressed = read_flags(reserved) & FLAG_ZSTD_METADATA != 0;
+    // Fails up front without the feature
+    if compressed {
+        compression::compress(&[])?;
+    }
+    let word = u32::from_le_bytes([reserved[4], reserved[5], reserved[6], reserved[7]]);
+    let precision = Precision::from_code(word & 0xffff).ok_or(YAVSError::InvalidFile)?;
+    let subspaces = word >> 16;
+    if (precision == Precision::Pq) != (subspaces > 0) {
+        return Err(YAVSError::InvalidFile);
+    }
+    Ok(Encoding {
+        precision,
+        compressed,
+        subspaces,
+    })
+}
+
+pub(crate) const HEADER_SIZE: u64 = 4 + 4 + 8 + 4 + RESERVED_SIZE as u64;
+const SECTION_HEADER_SIZE: u64 = 4 + 8;
+
+// Serialized size of a record in the main record block. Compressed metadata
+// has to be compressed to be measured.
+pub(crate) fn record_size(rec: &Record, enc: Encoding) -> u64 {
+    let metadata = compression::stored(&rec.metadata, enc.compressed).map_or(rec.metadata.len(), |m| m.len());
+    let embedding = match enc.precision {
+        Precision::Pq | Precision::Int8 => rec.code.len() as u64,
+        precision => precision.width() * rec.embedding.len() as u64,
+    };
+    16 + embedding + 4 + metadata as u64
+}
+
+// Everything a record adds to the file: its record block entry and its
+// entries in the per-record sections
+pub(crate) fn serialized_size(rec: &Record, enc: Encoding) -> u64 {
+    record_size(rec, enc) + section_entries(rec).iter().sum::<u64>()
+}
+
+// Size of a record's entry in each per-record section (0 when it has none).
+// Its provenance entry is just a reference into the section's value table.
+fn section_entries(rec: &Record) -> [u64; 7] {
+    let tags = if rec.tags.is_empty() {
+        0
+    } else {
+        16 + 4 + rec.tags.iter().map(|t| 4 + t.len() as u64).sum::<u64>()
+    };
+    let tenant = rec.tenant.as_ref().map_or(0, |t| 16 + 4 + t.len() as u64);
+    let timestamp = if rec.updated_at == 0 { 0 } else { 16 + 8 };
+    let key = rec.key.as_ref().map_or(0, |k| 16 + 4 + k.len() as u64);
+    let pin = if rec.pinned { 16 } else { 0 };
+    let provenance = if rec.provenance.is_some() { 16 + 4 } else { 0 };
+    [tags, tenant, timestamp, key, pin, 4, provenance]
+}
+
+// Running serialized size of a set of records, so callers can drop records
+// one at a time and know the resulting file size exactly.
+#[derive(Debug, Default)]
+pub(crate) struct SizeTally {
+    enc: Encoding,
+    records: u64,
+    // (entries, bytes) per section
+    sections: [(u64, u64); 7],
+    // Records per distinct provenance, for the value table
+    provenance: HashMap<Arc<Provenance>, u64>,
+    // (entries, bytes) of deleted records
+    tombstones: (u64, u64),
+    // Index section payloads, if the store has them
+    index: Option<u64>,
+    ivf: Option<u64>,
+    pq: Option<u64>,
+    sq: Option<u64>,
+    eval: u64,
+    fields: u64,
+    kv: u64,
+}
+
+impl SizeTally {
+    pub(crate) fn of(db: &YAVS) -> Self {
+        let mut tally = Self {
+            enc: db.encoding(),
+            ivf: db.ivf.as_ref().map(Ivf::fixed_size),
+            pq: db.pq.as_ref().map(ProductQuantizer::codebooks_size),
+            sq: db.sq.as_ref().map(ScalarQuantizer::params_size),
+            eval: if db.eval.is_empty() { 0 } else { SECTION_HEADER_SIZE + db.eval.size() },
+            fields: if db.fields.is_empty() { 0 } else { SECTION_HEADER_SIZE + db.fields.size() },
+            kv: if db.kv.is_empty() { 0 } else { SECTION_HEADER_SIZE + db.kv.size() },
+            ..Self::default()
+        };
+        for rec in db.iter() {
+            tally.add(rec);
+        }
+        for rec in db.records.iter().filter(|r| r.deleted) {
+            tally.tombstones.0 += 1;
+            tally.tombstones.1 += full_record_size(rec, db.encoding());
+        }
+        tally.index = db.index.as_ref().map(|index| index.encode(&ordinals(db)).len() as u64);
+        tally
+    }
+
+    // Drops a removed record's own graph node. Links to it from other nodes
+    // stay counted, so the total becomes an upper bound.
+    pub(crate) fn sub_index_node(&mut self, bytes: u64) {
+        if let Some(index) = &mut self.index {
+            *index -= bytes;
+        }
+    }
+
+    pub(crate) fn add(&mut self, rec: &Record) {
+        self.records += record_size(rec, self.enc);
+        if let Some(ivf) = &mut self.ivf {
+            *ivf += 4;
+        }
+        for (section, size) in self.sections.iter_mut().zip(section_entries(rec)) {
+            if size > 0 {
+                section.0 += 1;
+                section.1 += size;
+            }
+        }
+        if let Some(p) = &rec.provenance {
+            *self.provenance.entry(p.clone()).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn sub(&mut self, rec: &Record) {
+        self.records -= record_size(rec, self.enc);
+        if let Some(ivf) = &mut self.ivf {
+            *ivf -= 4;
+        }
+        for (section, size) in self.sections.iter_mut().zip(section_entries(rec)) {
+            if size > 0 {
+                section.0 -= 1;
+                section.1 -= size;
+            }
+        }
+        if let Some(p) = &rec.provenance {
+            if let Some(n) = self.provenance.get_mut(p) {
+                *n -= 1;
+                if *n == 0 {
+                    self.provenance.remove(p);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        let sections: u64 = self.sections
+            .iter()
+            .chain([&self.tombstones])
+            .filter(|(entries, _)| *entries > 0)
+            .map(|(_, bytes)| SECTION_HEADER_SIZE + 8 + bytes)
+            .sum();
+        let table: u64 = if self.provenance.is_empty() {
+            0
+        } else {
+            4 + self.provenance.keys().map(|p| provenance_size(p)).sum::<u64>()
+        };
+        let index: u64 = [self.index, self.ivf, self.pq, self.sq]
+            .iter()
+            .flatten()
+            .map(|bytes| SECTION_HEADER_SIZE + bytes)
+            .sum();
+        HEADER_SIZE + self.records + sections + table + index + self.eval + self.fields + self.kv
+    }
+}
+
+// Position in the written record block of each record, None for deleted ones
+fn ordinals(db: &YAVS) -> Vec<Option<u32>> {
+    let mut next = 0;
+    db.records
+        .iter()
+        .map(|r| {
+            (!r.deleted).then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn write_store<W: Write>(db: &YAVS, w: &mut W) -> Result<(), YAVSError> {
+    let body = encode_body(db)?;
+    write_header(w, db.len() as u64, db.dim, db.metric, db.encoding(), Some(body.digest))?;
+
+    for rec in db.iter() {
+        write_record(w, rec, db.encoding())?;
+    }
+    w.write_all(&body.sections)?;
+    Ok(())
+}
+
+// What follows the record block, and the file digest over everything
+pub(crate) struct Body {
+    pub(crate) sections: Vec<u8>,
+    pub(crate) digest: u32,
+}
+
+pub(crate) fn encode_body(db: &YAVS) -> Result<Body, YAVSError> {
+    let mut buf = Vec::new();
+    let mut records = Vec::with_capacity(db.len());
+    for rec in db.iter() {
+        buf.clear();
+        write_record(&mut buf, rec, db.encoding())?;
+        let mut hasher = Hasher::new();
+        hasher.update(&buf);
+        records.push(hasher);
+    }
+    let checksums: Vec<u32> = records.iter().map(|h| h.clone().finalize()).collect();
+    let mut sections = Vec::new();
+    write_sections(db, &checksums, &mut sections)?;
+
+    let mut digest = Hasher::new();
+    buf.clear();
+    write_header(&mut buf, db.len() as u64, db.dim, db.metric, db.encoding(), Some(0))?;
+    digest.update(&buf);
+    for hasher in &records {
+        digest.combine(hasher);
+    }
+    digest.update(&sections);
+    Ok(Body { sections, digest: digest.finalize() })
+}
+
+// Everything after the record block
+fn write_sections<W: Write>(db: &YAVS, checksums: &[u32], w: &mut W) -> Result<(), YAVSError> {
+    // Tags: only records that carry any, keyed by id
+    let tagged: Vec<&Record> = db.iter().filter(|r| !r.tags.is_empty()).collect();
+    if !tagged.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(tagged.len() as u64).to_le_bytes());
+        for rec in tagged {
+            payload.extend_from_slice(&rec.id);
+            payload.extend_from_slice(&(rec.tags.len() as u32).to_le_bytes());
+            for tag in &rec.tags {
+                payload.extend_from_slice(&(tag.len() as u32).to_le_bytes());
+                payload.extend_from_slice(tag.as_bytes());
+            }
+        }
+        write_section(w, SECTION_TAGS, &payload)?;
+    }
+
+    // Tenants: only records that belong to one, keyed by id
+    let owned: Vec<&Record> = db.iter().filter(|r| r.tenant.is_some()).collect();
+    if !owned.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(owned.len() as u64).to_le_bytes());
+        for rec in owned {
+            let tenant = rec.tenant.as_deref().unwrap_or_default();
+            payload.extend_from_slice(&rec.id);
+            payload.extend_from_slice(&(tenant.len() as u32).to_le_bytes());
+            payload.extend_from_slice(tenant.as_bytes());
+        }
+        write_section(w, SECTION_TENANTS, &payload)?;
+    }
+
+    // Timestamps: records written by other tools may have none
+    let stamped: Vec<&Record> = db.iter().filter(|r| r.updated_at != 0).collect();
+    if !stamped.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(stamped.len() as u64).to_le_bytes());
+        for rec in stamped {
+            payload.extend_from_slice(&rec.id);
+            payload.extend_from_slice(&rec.updated_at.to_le_bytes());
+        }
+        write_section(w, SECTION_TIMESTAMPS, &payload)?;
+    }
+
+    // Keys: only records inserted with one
+    let keyed: Vec<&Record> = db.iter().filter(|r| r.key.is_some()).collect();
+    if !keyed.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(keyed.len() as u64).to_le_bytes());
+        for rec in keyed {
+            let key = rec.key.as_deref().unwrap_or_default();
+            payload.extend_from_slice(&rec.id);
+            payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            payload.extend_from_slice(key);
+        }
+        write_section(w, SECTION_KEYS, &payload)?;
+    }
+
+    // Pins: just the ids of pinned records
+    let pinned: Vec<&Record> = db.iter().filter(|r| r.pinned).collect();
+    if !pinned.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(pinned.len() as u64).to_le_bytes());
+        for rec in pinned {
+            payload.extend_from_slice(&rec.id);
+        }
+        write_section(w, SECTION_PINS, &payload)?;
+    }
+
+    // Provenance: a table of the distinct values, in order of first use, then
+    // each record's index into it
+    let sourced: Vec<(&Record, &Provenance)> = db.iter().filter_map(|r| Some((r, r.provenance.as_deref()?))).collect();
+    if !sourced.is_empty() {
+        let mut table: HashMap<&Provenance, u32> = HashMap::new();
+        let mut values = Vec::new();
+        let mut entries = Vec::with_capacity(sourced.len() * 20);
+        for &(rec, p) in &sourced {
+            let next = table.len() as u32;
+            let at = *table.entry(p).or_insert_with(|| {
+                write_provenance(&mut values, p);
+                next
+            });
+            entries.extend_from_slice(&rec.id);
+            entries.extend_from_slice(&at.to_le_bytes());
+        }
+        let mut payload = Vec::with_capacity(4 + values.len() + 8 + entries.len());
+        payload.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&values);
+        payload.extend_from_slice(&(sourced.len() as u64).to_le_bytes());
+        payload.extend_from_slice(&entries);
+        write_section(w, SECTION_PROVENANCE, &payload)?;
+    }
+
+    // Checksums: the CRC32 of each record's entry in the record block, in order
+    if !checksums.is_empty() {
+        let mut payload = Vec::with_capacity(8 + 4 * checksums.len());
+        payload.extend_from_slice(&(checksums.len() as u64).to_le_bytes());
+        for crc in checksums {
+            payload.extend_from_slice(&crc.to_le_bytes());
+        }
+        write_section(w, SECTION_CHECKSUMS, &payload)?;
+    }
+
+    // Tombstones: deleted records in full, so they can still be restored and
+    // merged after a reload
+    let deleted: Vec<&Record> = db.records.iter().filter(|r| r.deleted).collect();
+    if !deleted.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(deleted.len() as u64).to_le_bytes());
+        for rec in deleted {
+            write_full_record(&mut payload, rec, db.encoding())?;
+        }
+        write_section(w, SECTION_TOMBSTONES, &payload)?;
+    }
+
+    // Index: the HNSW graph, with records referred to by their position above
+    if let Some(index) = &db.index {
+        write_section(w, SECTION_INDEX, &index.encode(&ordinals(db)))?;
+    }
+
+    // IVF: centroids, then each cell's records by position
+    if let Some(ivf) = &db.ivf {
+        write_section(w, SECTION_IVF, &ivf.encode(db.dim, &ordinals(db)))?;
+    }
+
+    // PQ: codebooks only, records are re-encoded on load
+    if let Some(pq) = &db.pq {
+        write_section(w, SECTION_PQ, &pq.encode_codebooks())?;
+    }
+
+    // SQ: per-dimension ranges only, likewise
+    if let Some(sq) = &db.sq {
+        write_section(w, SECTION_SQ, &sq.encode_params())?;
+    }
+
+    // Eval: the evaluation set and its run history
+    if !db.eval.is_empty() {
+        write_section(w, SECTION_EVAL, &db.eval.encode())?;
+    }
+
+    // Fields: indexed metadata field declarations, indexes are rebuilt on load
+    if !db.fields.is_empty() {
+        write_section(w, SECTION_FIELDS, &db.fields.encode())?;
+    }
+
+    // KV: the application key-value namespace
+    if !db.kv.is_empty() {
+        write_section(w, SECTION_KV, &db.kv.encode())?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn read_store<R: Read>(r: &mut R) -> Result<YAVS, YAVSError> {
+    let mut magic_buf = [0u8; 4];
+    r.read_exact(&mut magic_buf)?;
+    if magic_buf != MAGIC {
+        return Err(YAVSError::InvalidFile);
+    }
+
+    let version = read_u32(r)?;
+    if version == 0 || version > VERSION {
+        return Err(YAVSError::VersionMismatch);
+    }
+
+    let n_records = read_u64(r)?;
+    let dim = read_u32(r)?;
+
+    let mut reserved = [0u8; RESERVED_SIZE];
+    r.read_exact(&mut reserved)?;
+    let metric = read_metric(&reserved)?;
+    let enc = read_encoding(&reserved)?;
+
+    // Header counts are untrusted; don't let a corrupt one drive a huge allocation
+    let mut records = Vec::with_capacity(n_records.min(PREALLOC_LIMIT) as usize);
+    let mut attached = Attached::default();
+
+    let mut digest = Hasher::new();
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    let mut zeroed = reserved;
+    zeroed[DIGEST_AT..DIGEST_AT + 4].fill(0);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&n_records.to_le_bytes());
+    header.extend_from_slice(&dim.to_le_bytes());
+    header.extend_from_slice(&zeroed);
+    digest.update(&header);
+
+    // Each record is read whole first, so it can be checksummed in one go
+    let fixed = 16 + enc.embedding_size(dim) + 4;
+    let mut buf = Vec::new();
+    let mut offset = HEADER_SIZE;
+    let mut located = Vec::with_capacity(n_records.min(PREALLOC_LIMIT) as usize);
+    for _ in 0..n_records {
+        buf.clear();
+        r.by_ref().take(fixed).read_to_end(&mut buf)?;
+        if buf.len() as u64 == fixed {
+            let meta_len = u32::from_le_bytes(buf[buf.len() - 4..].try_into().unwrap_or_default()) as u64;
+            r.by_ref().take(meta_len).read_to_end(&mut buf)?;
+        }
+        let rec = match read_record(&mut buf.as_slice(), dim, enc) {
+            Ok(rec) => rec,
+            Err(YAVSError::FeatureDisabled(feature)) => return Err(YAVSError::FeatureDisabled(feature)),
+            _ => return Err(YAVSError::Corrupt { offset }),
+        };
+        digest.update(&buf);
+        located.push((offset, crc32fast::hash(&buf)));
+        offset += buf.len() as u64;
+        records.push(rec);
+    }
+
+    if version >= 2 {
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        digest.update(&rest);
+        let has_digest = u32::from_le_bytes(reserved[DIGEST_AT + 4..].try_into().unwrap_or_default()) & FLAG_DIGEST != 0;
+        if has_digest && digest.finalize().to_le_bytes() != reserved[DIGEST_AT..DIGEST_AT + 4] {
+            return Err(YAVSError::Corrupt { offset: locate_corruption(&rest, offset, &located) });
+        }
+
+        let mut rest = rest.as_slice();
+        let mut sections = 0;
+        while !rest.is_empty() {
+            match read_section(&mut rest, &mut records, &mut attached, dim, enc) {
+                // Appending drops the sections and the digest, so what follows
+                // the record block is then an append cut short before its count
+                Err(_) if !has_digest && sections == 0 => break,
+                result => result?,
+            }
+            sections += 1;
+        }
+    }
+    // Without a digest (e.g. mid-append) the checksums, if any, still apply
+    if let Some(checksums) = &attached.checksums {
+        if let Some((at, _)) = located.iter().zip(checksums).find(|((_, crc), expected)| crc != *expected).map(|(l, _)| l) {
+            return Err(YAVSError::Corrupt { offset: *at });
+        }
+    }
+
+    // Indexes were saved over the live records only
+    let live = records.len();
+    records.append(&mut attached.deleted);
+    if !codes_fit(&records, &attached, enc) {
+        return Err(YAVSError::InvalidFile);
+    }
+    // Nothing to search them over without embeddings
+    if enc.precision.is_quantized() {
+        attached.hnsw = None;
+        attached.ivf = None;
+    }
+
+    let mut db = YAVS::from_records(dim, metric, records);
+    db.precision = enc.precision;
+    db.compress_metadata = enc.compressed;
+    if attached.ivf.as_ref().is_some_and(|ivf| ivf.dim() != dim as usize)
+        || attached.pq.as_ref().is_some_and(|pq| pq.dim() != dim as usize)
+        || attached.sq.as_ref().is_some_and(|sq| sq.dim() != dim as usize)
+        || !attached.eval.dim_matches(dim)
+    {
+        return Err(YAVSError::InvalidFile);
+    }
+    // Encode records before attaching the other indexes, which are loaded whole
+    if attached.pq.is_some() || attached.sq.is_some() {
+        db.pq = attached.pq;
+        db.sq = attached.sq;
+        db.rebuild_indexes();
+    }
+    db.index = attached.hnsw;
+    db.ivf = attached.ivf;
+    db.eval = attached.eval;
+    db.fields = attached.fields;
+    db.fields.fill(&db.records);
+    db.kv = attached.kv;
+    for i in live..db.records.len() {
+        if let Some(index) = &mut db.index {
+            index.insert(i, &db.records, db.metric);
+        }
+        if let Some(ivf) = &mut db.ivf {
+            ivf.assign(i, &db.records, db.metric);
+        }
+    }
+    Ok(db)
+}
+
+// Under Pq and Int8 precision every record's code must fit the saved
+// quantizer
+pub(crate) fn codes_fit(records: &[Record], attached: &Attached, enc: Encoding) -> bool {
+    match enc.precision {
+        Precision::Pq => attached.pq.as_ref().is_some_and(|pq| pq.n_subspaces() == enc.subspaces as usize && records.iter().all(|r| pq.fits(&r.code))),
+        Precision::Int8 => attached.sq.as_ref().is_some_and(|sq| records.iter().all(|r| r.code.len() == sq.dim())),
+        _ => true,
+    }
+}
+
+// Best guess at where a file whose digest doesn't match went wrong: the first
+// record failing its checksum, else the first malformed section, else the
+// start of the sections. `located` holds each record's offset and CRC32.
+fn locate_corruption(mut sections: &[u8], records_end: u64, located: &[(u64, u32)]) -> u64 {
+    let len = sections.len();
+    while !sections.is_empty() {
+        let at = records_end + (len - sections.len()) as u64;
+        let (Ok(kind), Ok(size)) = (read_u32(&mut sections), read_u64(&mut sections)) else {
+            return at;
+        };
+        if size > sections.len() as u64 {
+            return at;
+        }
+        let (mut payload, tail) = sections.split_at(size as usize);
+        sections = tail;
+        if kind != SECTION_CHECKSUMS {
+            continue;
+        }
+        match read_checksums(&mut payload) {
+            Ok(checksums) if checksums.len() == located.len() => {
+                if let Some(((offset, _), _)) = located.iter().zip(&checksums).find(|((_, crc), expected)| crc != *expected) {
+                    return *offset;
+                }
+            }
+            _ => return at,
+        }
+    }
+    records_end
+}
+
+// Whether `bytes` is exactly a run of known sections (possibly none)
+pub(crate) fn is_section_chain(mut bytes: &[u8]) -> bool {
+    while !bytes.is_empty() {
+        let (Ok(kind), Ok(len)) = (read_u32(&mut bytes), read_u64(&mut bytes)) else {
+            return false;
+        };
+        if !(SECTION_TAGS..=SECTION_KV).contains(&kind) || len > bytes.len() as u64 {
+            return false;
+        }
+        bytes = &bytes[len as usize..];
+    }
+    true
+}
+
+// Store-wide state read from sections (search indexes, the eval set), to
+// attach once the store is built
+#[derive(Default)]
+pub(crate) struct Attached {
+    hnsw: Option<Hnsw>,
+    ivf: Option<Ivf>,
+    pub(crate) pq: Option<ProductQuantizer>,
+    pub(crate) sq: Option<ScalarQuantizer>,
+    eval: EvalSet,
+    fields: FieldIndexes,
+    kv: KvStore,
+    checksums: Option<Vec<u32>>,
+    deleted: Vec<Record>,
+}
+
+// Reads one optional section and applies it to `records`, or to `attached`
+// for store-wide sections
+pub(crate) fn read_section(rest: &mut &[u8], records: &mut [Record], attached: &mut Attached, dim: u32, enc: Encoding) -> Result<(), YAVSError> {
+    let kind = read_u32(rest)?;
+    let len = read_u64(rest)? as usize;
+    if len > rest.len() {
+        return Err(YAVSError::InvalidFile);
+    }
+    let (mut payload, tail) = rest.split_at(len);
+    *rest = tail;
+
+    match kind {
+        SECTION_TAGS => read_tags(&mut payload, records),
+        SECTION_TENANTS => read_tenants(&mut payload, records),
+        SECTION_TIMESTAMPS => read_timestamps(&mut payload, records),
+        SECTION_KEYS => read_keys(&mut payload, records),
+        SECTION_PINS => read_pins(&mut payload, records),
+        SECTION_PROVENANCE => read_provenance_section(&mut payload, records),
+        SECTION_INDEX => {
+            attached.hnsw = Some(Hnsw::decode(&mut payload, records.len())?);
+            Ok(())
+        }
+        SECTION_IVF => {
+            attached.ivf = Some(Ivf::decode(&mut payload, records.len())?);
+            Ok(())
+        }
+        SECTION_PQ => {
+            attached.pq = Some(ProductQuantizer::decode_codebooks(&mut payload)?);
+            Ok(())
+        }
+        SECTION_SQ => {
+            attached.sq = Some(ScalarQuantizer::decode_params(&mut payload)?);
+            Ok(())
+        }
+        SECTION_EVAL => {
+            attached.eval = EvalSet::decode(&mut payload)?;
+            Ok(())
+        }
+        SECTION_FIELDS => {
+            attached.fields = FieldIndexes::decode(&mut payload)?;
+            Ok(())
+        }
+        SECTION_KV => {
+            attached.kv = KvStore::decode(&mut payload)?;
+            Ok(())
+        }
+        SECTION_CHECKSUMS => {
+            attached.checksums = Some(read_checksums(&mut payload)?);
+            Ok(())
+        }
+        SECTION_TOMBSTONES => {
+            let count = read_u64(&mut payload)?;
+            for _ in 0..count {
+                let mut rec = read_full_record(&mut payload, dim, enc)?;
+                rec.deleted = true;
+                attached.deleted.push(rec);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Record block entry: id, embedding (or code), metadata length and bytes
+pub(crate) fn write_record<W: Write>(w: &mut W, rec: &Record, enc: Encoding) -> Result<(), YAVSError> {
+    w.write_all(&rec.id)?;
+    match enc.precision {
+        Precision::F32 => {
+            for &val in &rec.embedding {
+                w.write_all(&val.to_le_bytes())?;
+            }
+        }
+        Precision::F16 => {
+            for &val in &rec.embedding {
+                w.write_all(&to_f16(val)?.to_le_bytes())?;
+            }
+        }
+        Precision::Pq | Precision::Int8 => w.write_all(&rec.code)?,
+    }
+    let metadata = compression::stored(&rec.metadata, enc.compressed)?;
+    w.write_all(&(metadata.len() as u32).to_le_bytes())?;
+    w.write_all(&metadata)?;
+    Ok(())
+}
+
+const FLAG_PINNED: u8 = 1;
+const FLAG_DELETED: u8 = 2;
+
+// A record with everything the sections would carry for it: the record
+// block entry, then timestamp, flags, tags, tenant, key and provenance
+pub(crate) fn write_full_record(buf: &mut Vec<u8>, rec: &Record, enc: Encoding) -> Result<(), YAVSError> {
+    write_record(buf, rec, enc)?;
+    buf.extend_from_slice(&rec.updated_at.to_le_bytes());
+    let mut flags = 0;
+    if rec.pinned {
+        flags |= FLAG_PINNED;
+    }
+    if rec.deleted {
+        flags |= FLAG_DELETED;
+    }
+    buf.push(flags);
+    buf.extend_from_slice(&(rec.tags.len() as u32).to_le_bytes());
+    for tag in &rec.tags {
+        buf.extend_from_slice(&(tag.len() as u32).to_le_bytes());
+        buf.extend_from_slice(tag.as_bytes());
+    }
+    for field in [rec.tenant.as_ref().map(|t| t.as_bytes()), rec.key.as_deref()] {
+        match field {
+            Some(bytes) => {
+                buf.push(1);
+                buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                buf.extend_from_slice(bytes);
+            }
+            None => buf.push(0),
+        }
+    }
+    match &rec.provenance {
+        Some(p) => {
+            buf.push(1);
+            write_provenance(buf, p);
+        }
+        None => buf.push(0),
+    }
+    Ok(())
+}
+
+pub(crate) fn full_record_size(rec: &Record, enc: Encoding) -> u64 {
+    let tags: u64 = rec.tags.iter().map(|t| 4 + t.len() as u64).sum();
+    let tenant = rec.tenant.as_ref().map_or(0, |t| 4 + t.len() as u64);
+    let key = rec.key.as_ref().map_or(0, |k| 4 + k.len() as u64);
+    let provenance = rec.provenance.as_deref().map_or(0, provenance_size);
+    record_size(rec, enc) + 8 + 1 + 4 + tags + 1 + tenant + 1 + key + 1 + provenance
+}
+
+pub(crate) fn read_full_record(payload: &mut &[u8], dim: u32, enc: Encoding) -> Result<Record, YAVSError> {
+    let mut rec = read_record(payload, dim, enc)?;
+    rec.updated_at = read_u64(payload)?;
+    let [flags] = read_array(payload)?;
+    rec.pinned = flags & FLAG_PINNED != 0;
+    rec.deleted = flags & FLAG_DELETED != 0;
+    for _ in 0..read_u32(payload)? {
+        rec.tags.insert(read_string(payload)?);
+    }
+    let [has_tenant] = read_array(payload)?;
+    if has_tenant == 1 {
+        rec.tenant = Some(read_string(payload)?);
+    }
+    let [has_key] = read_array(payload)?;
+    if has_key == 1 {
+        rec.key = Some(read_bytes(payload)?);
+    }
+    let [has_provenance] = read_array(payload)?;
+    if has_provenance == 1 {
+        rec.provenance = Some(Arc::new(read_provenance(payload)?));
+    }
+    Ok(rec)
+}
+
+// Each field as a presence byte, then the string if present
+fn write_provenance(buf: &mut Vec<u8>, p: &Provenance) {
+    for field in [&p.source, &p.pipeline_version, &p.embedder_version] {
+        match field {
+            Some(value) => {
+                buf.push(1);
+                buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                buf.extend_from_slice(value.as_bytes());
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+fn provenance_size(p: &Provenance) -> u64 {
+    [&p.source, &p.pipeline_version, &p.embedder_version]
+        .iter()
+        .map(|field| 1 + field.as_ref().map_or(0, |v| 4 + v.len() as u64))
+        .sum()
+}
+
+fn read_provenance(payload: &mut &[u8]) -> Result<Provenance, YAVSError> {
+    let mut field = || -> Result<Option<String>, YAVSError> {
+        let [present] = read_array(payload)?;
+        if present == 1 {
+            Ok(Some(read_string(payload)?))
+        } else {
+            Ok(None)
+        }
+    };
+    Ok(Provenance {
+        source: field()?,
+        pipeline_version: field()?,
+        embedder_version: field()?,
+    })
+}
+
+pub(crate) fn read_record<R: Read>(r: &mut R, dim: u32, enc: Encoding) -> Result<Record, YAVSError> {
+    let id: [u8; 16] = read_array(r)?;
+
+    let mut embedding = Vec::new();
+    let mut code = Vec::new();
+    match enc.precision {
+        Precision::F32 | Precision::F16 => {
+            embedding.reserve((dim as u64).min(PREALLOC_LIMIT) as usize);
+            for _ in 0..dim {
+                embedding.push(match enc.precision {
+                    Precision::F16 => from_f16(u16::from_le_bytes(read_array(r)?))?,
+                    _ => f32::from_le_bytes(read_array(r)?),
+                });
+            }
+        }
+        Precision::Pq | Precision::Int8 => {
+            let len = enc.embedding_size(dim);
+            r.by_ref().take(len).read_to_end(&mut code)?;
+            if code.len() as u64 != len {
+                return Err(YAVSError::InvalidFile);
+            }
+        }
+    }
+
+    let meta_len = read_u32(r)? as u64;
+    let mut metadata = Vec::with_capacity(meta_len.min(PREALLOC_LIMIT) as usize);
+    r.by_ref().take(meta_len).read_to_end(&mut metadata)?;
+    if metadata.len() as u64 != meta_len {
+        return Err(YAVSError::InvalidFile);
+    }
+    if enc.compressed {
+        metadata = compression::decompress(&metadata)?;
+    }
+
+    let mut rec = Record::new(id, embedding, metadata);
+    rec.code = code;
+    Ok(rec)
+}
+
+fn positions(records: &[Record]) -> HashMap<[u8; 16], usize> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.id, i))
+        .collect()
+}
+
+fn read_tags(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        let n_tags = read_u32(payload)?;
+        let mut tags = BTreeSet::new();
+        for _ in 0..n_tags {
+            tags.insert(read_string(payload)?);
+        }
+        if let Some(&i) = positions.get(&id) {
+            records[i].tags = tags;
+        }
+    }
+    Ok(())
+}
+
+fn read_tenants(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        let tenant = read_string(payload)?;
+        if let Some(&i) = positions.get(&id) {
+            records[i].tenant = Some(tenant);
+        }
+    }
+    Ok(())
+}
+
+fn read_timestamps(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        let updated_at = read_u64(payload)?;
+        if let Some(&i) = positions.get(&id) {
+            records[i].updated_at = updated_at;
+        }
+    }
+    Ok(())
+}
+
+fn read_keys(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        let key = read_bytes(payload)?;
+        if let Some(&i) = positions.get(&id) {
+            records[i].key = Some(key);
+        }
+    }
+    Ok(())
+}
+
+fn read_checksums(payload: &mut &[u8]) -> Result<Vec<u32>, YAVSError> {
+    let count = read_u64(payload)?;
+    if count > payload.len() as u64 / 4 {
+        return Err(YAVSError::InvalidFile);
+    }
+    (0..count).map(|_| read_u32(payload)).collect()
+}
+
+fn read_pins(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        if let Some(&i) = positions.get(&id) {
+            records[i].pinned = true;
+        }
+    }
+    Ok(())
+}
+
+fn read_provenance_section(payload: &mut &[u8], records: &mut [Record]) -> Result<(), YAVSError> {
+    let positions = positions(records);
+
+    let n_values = read_u32(payload)?;
+    let mut table = Vec::with_capacity((n_values as u64).min(PREALLOC_LIMIT) as usize);
+    for _ in 0..n_values {
+        table.push(Arc::new(read_provenance(payload)?));
+    }
+    let count = read_u64(payload)?;
+    for _ in 0..count {
+        let id: [u8; 16] = read_array(payload)?;
+        let at = read_u32(payload)? as usize;
+        let p = table.get(at).ok_or(YAVSError::InvalidFile)?;
+        if let Some(&i) = positions.get(&id) {
+            records[i].provenance = Some(p.clone());
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn read_string(payload: &mut &[u8]) -> Result<String, YAVSError> {
+    String::from_utf8(read_bytes(payload)?).map_err(|_| YAVSError::InvalidFile)
+}
+
+pub(crate) fn read_bytes(payload: &mut &[u8]) -> Result<Vec<u8>, YAVSError> {
+    let len = read_u32(payload)? as usize;
+    if len > payload.len() {
+        return Err(YAVSError::InvalidFile);
+    }
+    let mut buf = vec![0u8; len];
+    payload.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_section<W: Write>(w: &mut W, kind: u32, payload: &[u8]) -> Result<(), YAVSError> {
+    w.write_all(&kind.to_le_bytes())?;
+    w.write_all(&(payload.len() as u64).to_le_bytes())?;
+    w.write_all(payload)?;
+    Ok(())
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], YAVSError> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> Result<u32, YAVSError> {
+    Ok(u32::from_le_bytes(read_array(r)?))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> Result<u64, YAVSError> {
+    Ok(u64::from_le_bytes(read_array(r)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{record_size, HEADER_SIZE};
+    use crate::{test_path, YAVS, YAVSError};
+
+    #[test]
+    fn corrupted_byte_fails_the_checksum() {
+        let path = test_path("format-corrupt.yavs");
+        let mut db = YAVS::new(2);
+        let a = db.insert(&[1.0, 0.0], b"first").unwrap();
+        db.insert(&[0.0, 1.0], b"second").unwrap();
+        db.add_tag(&a, "t");
+        db.save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let second = HEADER_SIZE + record_size(db.get(&a).unwrap(), db.encoding());
+
+        // A flipped bit in the second record's metadata points at that record
+        let mut corrupt = bytes.clone();
+        corrupt[second as usize + 16 + 2 * 4 + 4] ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        assert!(matches!(YAVS::load_file(&path), Err(YAVSError::Corrupt { offset }) if offset == second));
+
+        // One in the sections after the records fails the file digest
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        assert!(matches!(YAVS::load_file(&path), Err(YAVSError::Corrupt { .. })));
+
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(YAVS::load_file(&path).unwrap().len(), 2);
+    }
+}
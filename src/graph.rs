@@ -0,0 +1,56 @@
+// Exact k-nearest-neighbor graph over the live records, for visualization
+// (e.g. seeding UMAP), community detection, or building graph indexes.
+// Brute force, O(n^2); rows are computed in parallel with the `rayon` feature.
+
+use std::borrow::Cow;
+use crate::metric::compare_scores;
+use crate::YAVS;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KnnGraph {
+    pub ids: Vec<[u8; 16]>,
+    // For each record in `ids`, its neighbors as (index into `ids`, distance),
+    // nearest first
+    pub neighbors: Vec<Vec<(usize, f32)>>,
+}
+
+impl KnnGraph {
+    // Directed edges (from, to, distance)
+    pub fn edges(&self) -> impl Iterator<Item = ([u8; 16], [u8; 16], f32)> + '_ {
+        self.neighbors.iter().enumerate().flat_map(move |(i, row)| {
+            row.iter().map(move |&(j, dist)| (self.ids[i], self.ids[j], dist))
+        })
+    }
+}
+
+impl YAVS {
+    pub fn knn_graph(&self, k: usize) -> KnnGraph {
+        let live: Vec<_> = self.iter().collect();
+        let vectors: Vec<Cow<[f32]>> = live.iter().map(|r| self.vector(r)).collect();
+
+        let row = |(i, a): (usize, &Cow<[f32]>)| -> Vec<(usize, f32)> {
+            let mut dists: Vec<(usize, f32)> = vectors
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(j, b)| (j, self.metric.distance(a, b)))
+                .collect();
+            dists.sort_by(|x, y| compare_scores(&self.metric, x.1, y.1));
+            dists.truncate(k);
+            dists
+        };
+
+        #[cfg(feature = "rayon")]
+        let neighbors = {
+            use rayon::prelude::*;
+            vectors.par_iter().enumerate().map(row).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let neighbors = vectors.iter().enumerate().map(row).collect();
+
+        KnnGraph {
+            ids: live.iter().map(|r| r.id).collect(),
+            neighbors,
+        }
+    }
+}
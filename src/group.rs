@@ -0,0 +1,141 @@
+// Group commit for durable writes. A store opened with `open_rw` syncs its
+// file twice per insert (the record, then the header count that takes it in),
+// and one opened with `open_wal` with `set_wal_sync(true)` syncs the log after
+// every entry. Under `set_group_commit` those syncs happen once per batch:
+// writes reach the file straight away but are only made durable once
+// `max_batch` of them are pending, once the oldest has waited `max_delay_ms`
+// by the store clock (checked as later writes arrive, so call `commit` when
+// writes stop), or on `commit`, `flush` and `checkpoint`. A crash loses at
+// most the pending batch; appended records past the header count are
+// ignored on load.
+
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupCommit {
+    // Pending writes that trigger a sync
+    pub max_batch: usize,
+    // How long the oldest pending write may wait for one
+    pub max_delay_ms: u64,
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        Self { max_batch: 64, max_delay_ms: 10 }
+    }
+}
+
+// Writes made since the last sync
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    pending: usize,
+    // Store clock time of the oldest pending write
+    since: u64,
+}
+
+impl Batch {
+    // Counts a write made at `now` and returns whether the batch is due; it
+    // always is without group commit
+    pub(crate) fn add(&mut self, group: Option<GroupCommit>, now: u64) -> bool {
+        let Some(group) = group else {
+            return true;
+        };
+        if self.pending == 0 {
+            self.since = now;
+        }
+        self.pending += 1;
+        self.pending >= group.max_batch || now.saturating_sub(self.since) >= group.max_delay_ms
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending = 0;
+    }
+}
+
+impl YAVS {
+    // Batches the syncs of appends and log entries; None (the default) syncs
+    // every write. Turning it off commits what is pending.
+    pub fn set_group_commit(&mut self, group: Option<GroupCommit>) -> Result<(), YAVSError> {
+        self.group_commit = group;
+        match group {
+            Some(_) => Ok(()),
+            None => self.commit(),
+        }
+    }
+
+    pub fn group_commit(&self) -> Option<GroupCommit> {
+        self.group_commit
+    }
+
+    // Makes the writes pending under group commit durable
+    pub fn commit(&mut self) -> Result<(), YAVSError> {
+        if let Some(appender) = &mut self.appender {
+            appender.commit()?;
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.commit()?;
+        }
+        Ok(())
+    }
+
+    // Writes made but not yet synced
+    pub fn pending_commits(&self) -> usize {
+        self.appender.as_ref().map_or(0, |a| a.batch.len()) + self.wal.as_ref().map_or(0, |w| w.batch.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{test_path, GroupCommit, ManualClock, YAVS};
+
+    #[test]
+    fn appends_are_counted_once_per_batch() {
+        let path = test_path("group-append.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_rw(&path).unwrap();
+        db.set_group_commit(Some(GroupCommit { max_batch: 3, max_delay_ms: u64::MAX })).unwrap();
+        db.insert(&[1.0, 0.0], b"a").unwrap();
+        db.insert(&[0.0, 1.0], b"b").unwrap();
+        assert_eq!(db.pending_commits(), 2);
+        assert_eq!(YAVS::load_file(&path).unwrap().len(), 0);
+
+        db.insert(&[1.0, 1.0], b"c").unwrap();
+        assert_eq!(db.pending_commits(), 0);
+        assert_eq!(YAVS::load_file(&path).unwrap().len(), 3);
+
+        db.insert(&[0.5, 1.0], b"d").unwrap();
+        db.commit().unwrap();
+        // A crash after the commit keeps everything
+        std::mem::forget(db);
+        assert_eq!(YAVS::load_file(&path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn log_syncs_when_the_oldest_entry_is_due() {
+        let path = test_path("group-wal.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut db = YAVS::open_wal(&path).unwrap();
+        db.set_clock(clock.clone());
+        db.set_wal_sync(true);
+        db.set_group_commit(Some(GroupCommit { max_batch: 100, max_delay_ms: 10 })).unwrap();
+        db.insert(&[1.0, 0.0], b"a").unwrap();
+        clock.set(1_005);
+        db.insert(&[0.0, 1.0], b"b").unwrap();
+        assert_eq!(db.pending_commits(), 2);
+        clock.set(1_010);
+        db.insert(&[1.0, 1.0], b"c").unwrap();
+        assert_eq!(db.pending_commits(), 0);
+
+        db.insert(&[0.5, 1.0], b"d").unwrap();
+        db.set_group_commit(None).unwrap();
+        assert_eq!(db.pending_commits(), 0);
+        drop(db);
+        assert_eq!(YAVS::open_wal(&path).unwrap().len(), 4);
+    }
+}
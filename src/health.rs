@@ -0,0 +1,117 @@
+// Store health report: cheap checks that turn into maintenance advice.
+
+use std::fmt;
+use crate::{Metric, YAVS};
+
+const MAX_DELETED_RATIO: f32 = 0.4;
+const MAX_AVG_METADATA_BYTES: usize = 64 * 1024;
+// How far an L2 norm may be from 1 and still count as normalized; loose
+// enough for f16 rounding
+const UNIT_NORM_TOLERANCE: f32 = 1e-2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthWarning {
+    // Fraction of stored records that are tombstones; compact to reclaim them
+    HighDeletedRatio(f32),
+    // Average metadata size in bytes across live records
+    LargeMetadata(usize),
+    // Live records whose embedding contains NaN or infinity
+    NonFiniteEmbeddings(usize),
+    // Live records whose embedding is all zeros
+    ZeroEmbeddings(usize),
+    // Live records whose embedding isn't unit length in a cosine store
+    UnnormalizedEmbeddings(usize),
+}
+
+impl fmt::Display for HealthWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthWarning::HighDeletedRatio(ratio) => write!(
+                f,
+                "deleted ratio {:.0}% > {:.0}%, consider compact()",
+                ratio * 100.0,
+                MAX_DELETED_RATIO * 100.0
+            ),
+            HealthWarning::LargeMetadata(avg) => write!(
+                f,
+                "metadata average size {}KB > {}KB, consider storing payloads elsewhere",
+                avg / 1024,
+                MAX_AVG_METADATA_BYTES / 1024
+            ),
+            HealthWarning::NonFiniteEmbeddings(n) => {
+                write!(f, "{} embeddings contain NaN or infinite values", n)
+            }
+            HealthWarning::ZeroEmbeddings(n) => write!(f, "{} embeddings are all zeros", n),
+            HealthWarning::UnnormalizedEmbeddings(n) => write!(
+                f,
+                "{} embeddings are not normalized but the metric is cosine, consider normalizing them",
+                n
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    pub live_records: usize,
+    pub deleted_records: usize,
+    pub warnings: Vec<HealthWarning>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+pub(crate) fn check(db: &YAVS) -> HealthReport {
+    let mut report = HealthReport::default();
+    let mut metadata_bytes = 0usize;
+    let mut non_finite = 0usize;
+    let mut zero = 0usize;
+    let mut unnormalized = 0usize;
+
+    for rec in &db.records {
+        if rec.deleted {
+            report.deleted_records += 1;
+            continue;
+        }
+        report.live_records += 1;
+        metadata_bytes += rec.metadata.len();
+        let embedding = db.vector(rec);
+        if embedding.iter().any(|v| !v.is_finite()) {
+            non_finite += 1;
+        } else if embedding.iter().all(|&v| v == 0.0) {
+            zero += 1;
+        } else if db.metric == Metric::Cosine {
+            let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+            if (norm - 1.0).abs() > UNIT_NORM_TOLERANCE {
+                unnormalized += 1;
+            }
+        }
+    }
+
+    let total = report.live_records + report.deleted_records;
+    if total > 0 {
+        let ratio = report.deleted_records as f32 / total as f32;
+        if ratio > MAX_DELETED_RATIO {
+            report.warnings.push(HealthWarning::HighDeletedRatio(ratio));
+        }
+    }
+    if let Some(avg) = metadata_bytes.checked_div(report.live_records) {
+        if avg > MAX_AVG_METADATA_BYTES {
+            report.warnings.push(HealthWarning::LargeMetadata(avg));
+        }
+    }
+    if non_finite > 0 {
+        report.warnings.push(HealthWarning::NonFiniteEmbeddings(non_finite));
+    }
+    if zero > 0 {
+        report.warnings.push(HealthWarning::ZeroEmbeddings(zero));
+    }
+    if unnormalized > 0 {
+        report.warnings.push(HealthWarning::UnnormalizedEmbeddings(unnormalized));
+    }
+
+    report
+}
@@ -0,0 +1,86 @@
+// Match offsets for displaying results: where query terms occur in each
+// result's metadata text, plus a snippet around the first match, so UIs can
+// highlight why a result matched without re-tokenizing on the client. Terms
+// match whole words, ignoring ASCII case; metadata that isn't UTF-8 never
+// matches.
+
+use std::ops::Range;
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlighted {
+    pub id: [u8; 16],
+    pub score: f32,
+    // Byte ranges into the metadata, in order and not overlapping
+    pub matches: Vec<Range<usize>>,
+    // At most `snippet_len` bytes of the metadata around the first match, or
+    // from its start if nothing matched
+    pub snippet: String,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Byte ranges of whole-word occurrences of `terms` in `text`. Where terms
+// overlap at the same position the longest wins.
+pub fn highlight(text: &str, terms: &[&str]) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut matches = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut skip_to = 0;
+    for (i, c) in text.char_indices() {
+        let at_start = !prev.is_some_and(is_word_char);
+        prev = Some(c);
+        if i < skip_to || !at_start || !is_word_char(c) {
+            continue;
+        }
+        let longest = terms
+            .iter()
+            .map(|t| t.len())
+            .filter(|&len| len > 0 && i + len <= bytes.len())
+            .filter(|&len| text.is_char_boundary(i + len) && !text[i + len..].starts_with(is_word_char))
+            .filter(|&len| terms.iter().any(|t| t.len() == len && bytes[i..i + len].eq_ignore_ascii_case(t.as_bytes())))
+            .max();
+        if let Some(len) = longest {
+            matches.push(i..i + len);
+            skip_to = i + len;
+        }
+    }
+    matches
+}
+
+fn floor_boundary(text: &str, mut i: usize) -> usize {
+    while !text.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+// About `len` bytes of `text` centred on `around`, cut at character boundaries
+fn snippet(text: &str, around: Option<&Range<usize>>, len: usize) -> String {
+    let start = match around {
+        Some(m) => m.start.saturating_sub(len.saturating_sub(m.len()) / 2),
+        None => 0,
+    };
+    let start = floor_boundary(text, start.min(text.len().saturating_sub(len)));
+    let end = floor_boundary(text, (start + len).min(text.len()));
+    text[start..end].to_string()
+}
+
+impl YAVS {
+    // `query`, with the occurrences of `terms` in each result's metadata and
+    // a snippet of up to `snippet_len` bytes
+    pub fn query_highlighted(&self, query_embedding: &[f32], k: usize, terms: &[&str], snippet_len: usize) -> Result<Vec<Highlighted>, YAVSError> {
+        let results = self.query(query_embedding, k)?;
+        Ok(results
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let text = std::str::from_utf8(&self.find(&id)?.metadata).unwrap_or_default();
+                let matches = highlight(text, terms);
+                let snippet = snippet(text, matches.first(), snippet_len);
+                Some(Highlighted { id, score, matches, snippet })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,32 @@
+// Insert-time hooks that derive metadata from the record being inserted (a
+// vector norm, a content hash, an ingest timestamp, ...), so every insert
+// path fills those fields the same way instead of each caller doing it.
+
+use std::fmt::Debug;
+use crate::{YAVS, YAVSError};
+
+pub trait InsertHook: Debug + Send + Sync {
+    // May rewrite `metadata`; an error aborts the insert. `now_ms` is the
+    // store clock's time, which the record is also stamped with.
+    fn on_insert(&self, embedding: &[f32], metadata: &mut Vec<u8>, now_ms: u64) -> Result<(), YAVSError>;
+}
+
+impl YAVS {
+    // Hooks run in the order they were added, on every insert that generates
+    // an ID (including tenant and keyed inserts). Records added through
+    // `push_record` or `merge` are taken as-is.
+    pub fn add_insert_hook<H: InsertHook + 'static>(&mut self, hook: H) {
+        self.insert_hooks.push(Box::new(hook));
+    }
+
+    pub fn clear_insert_hooks(&mut self) {
+        self.insert_hooks.clear();
+    }
+
+    pub(crate) fn run_insert_hooks(&self, embedding: &[f32], metadata: &mut Vec<u8>, now_ms: u64) -> Result<(), YAVSError> {
+        for hook in &self.insert_hooks {
+            hook.on_insert(embedding, metadata, now_ms)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// Sources of record IDs for `insert`. The default is random UUIDv4; a seeded
+// source makes store files byte-identical across runs.
+
+use std::fmt::Debug;
+use uuid::{Builder, Uuid};
+
+pub trait IdSource: Debug + Send + Sync {
+    fn next_id(&mut self) -> [u8; 16];
+}
+
+#[derive(Debug, Default)]
+pub struct RandomIds;
+
+impl IdSource for RandomIds {
+    fn next_id(&mut self) -> [u8; 16] {
+        *Uuid::new_v4().as_bytes()
+    }
+}
+
+// Deterministic UUIDv4-shaped IDs from a splitmix64 stream
+#[derive(Debug, Clone)]
+pub struct SeededIds {
+    state: u64,
+}
+
+impl SeededIds {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+}
+
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl IdSource for SeededIds {
+    fn next_id(&mut self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        *Builder::from_random_bytes(bytes).into_uuid().as_bytes()
+    }
+}
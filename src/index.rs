@@ -0,0 +1,582 @@
+// Optional HNSW graph index for approximate nearest neighbor search, used by
+// `query` instead of a full scan once built.
+//
+// Nodes refer to records by position and follow them through the same
+// add/remove/relocate hooks as the tag and tenant postings. Deleted records
+// stay in the graph for routing and are filtered from results. A record
+// purged by `compact_step` or replaced by `merge` leaves a dead node behind,
+// which searches skip; `compact` rebuilds the graph from scratch.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use crate::format::{read_u32, read_u64};
+use crate::ids::splitmix64;
+use crate::{Distance, Metric, Record, YAVS, YAVSError};
+
+const NONE: u32 = u32::MAX;
+const LEVEL_SEED: u64 = 0x5eed;
+// Bounds decoded graphs from untrusted files
+const MAX_LEVELS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HnswParams {
+    // Links per node on upper layers; layer 0 allows twice as many
+    pub m: usize,
+    pub ef_construction: usize,
+    // Candidate list size at query time; raised to k when smaller
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    // None once the record it indexed was purged or replaced
+    pos: Option<usize>,
+    layers: Vec<Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    // Lower is closer, whatever the metric
+    dist: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist.total_cmp(&other.dist).then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Hnsw {
+    params: HnswParams,
+    nodes: Vec<Node>,
+    // Node for each record position, or NONE
+    node_at: Vec<u32>,
+    entry: Option<u32>,
+    rng: u64,
+}
+
+impl Hnsw {
+    fn new(params: HnswParams) -> Self {
+        Self {
+            params,
+            nodes: Vec::new(),
+            node_at: Vec::new(),
+            entry: None,
+            rng: LEVEL_SEED,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.nodes.clear();
+        self.node_at.clear();
+        self.entry = None;
+        self.rng = LEVEL_SEED;
+    }
+
+    fn random_level(&mut self) -> usize {
+        let m = self.params.m.max(2) as f64;
+        let unit = ((splitmix64(&mut self.rng) >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        ((-unit.ln() / m.ln()) as usize).min(MAX_LEVELS - 1)
+    }
+
+    fn dist(&self, metric: Metric, query: &[f32], node: u32, records: &[Record]) -> Option<f32> {
+        let pos = self.nodes[node as usize].pos?;
+        let score = metric.score(query, &records[pos].embedding);
+        let dist = if metric.larger_is_closer() { -score } else { score };
+        Some(if dist.is_nan() { f32::INFINITY } else { dist })
+    }
+
+    pub(crate) fn insert(&mut self, pos: usize, records: &[Record], metric: Metric) {
+        let level = self.random_level();
+        let id = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            pos: Some(pos),
+            layers: vec![Vec::new(); level + 1],
+        });
+        if self.node_at.len() <= pos {
+            self.node_at.resize(pos + 1, NONE);
+        }
+        self.node_at[pos] = id;
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(id);
+            return;
+        };
+        let query = &records[pos].embedding;
+        let top = self.nodes[entry as usize].layers.len() - 1;
+        let Some(dist) = self.dist(metric, query, entry, records) else {
+            return;
+        };
+
+        let mut nearest = Candidate { dist, node: entry };
+        for layer in (level + 1..=top).rev() {
+            nearest = self.greedy(metric, query, nearest, layer, records);
+        }
+
+        let mut entry_points = vec![nearest];
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(metric, query, &entry_points, self.params.ef_construction, layer, records);
+            let neighbors: Vec<u32> = found
+                .iter()
+                .take(self.params.m)
+                .map(|c| c.node)
+                .collect();
+            let max_links = if layer == 0 { 2 * self.params.m } else { self.params.m };
+            for &n in &neighbors {
+                self.nodes[n as usize].layers[layer].push(id);
+                if self.nodes[n as usize].layers[layer].len() > max_links {
+                    self.prune(metric, n, layer, max_links, records);
+                }
+            }
+            self.nodes[id as usize].layers[layer] = neighbors;
+            if !found.is_empty() {
+                entry_points = found;
+            }
+        }
+
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    // The record at `pos` is going away; its node stays as a dead end
+    pub(crate) fn detach(&mut self, pos: usize) {
+        let Some(&node) = self.node_at.get(pos) else {
+            return;
+        };
+        if node == NONE {
+            return;
+        }
+        self.nodes[node as usize].pos = None;
+        self.node_at[pos] = NONE;
+        if self.entry == Some(node) {
+            self.entry = self.highest_live_node();
+        }
+    }
+
+    pub(crate) fn relocate(&mut self, from: usize, to: usize) {
+        let Some(&node) = self.node_at.get(from) else {
+            return;
+        };
+        if node != NONE {
+            self.nodes[node as usize].pos = Some(to);
+            if self.node_at.len() <= to {
+                self.node_at.resize(to + 1, NONE);
+            }
+            self.node_at[to] = node;
+            self.node_at[from] = NONE;
+        }
+        while self.node_at.last() == Some(&NONE) {
+            self.node_at.pop();
+        }
+    }
+
+    // Positions of up to max(ef_search, k) records near `query`, deleted ones included
+    pub(crate) fn search(&self, query: &[f32], k: usize, records: &[Record], metric: Metric) -> Vec<usize> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let Some(dist) = self.dist(metric, query, entry, records) else {
+            return Vec::new();
+        };
+        let mut nearest = Candidate { dist, node: entry };
+        for layer in (1..self.nodes[entry as usize].layers.len()).rev() {
+            nearest = self.greedy(metric, query, nearest, layer, records);
+        }
+        self.search_layer(metric, query, &[nearest], self.params.ef_search.max(k), 0, records)
+            .into_iter()
+            .filter_map(|c| self.nodes[c.node as usize].pos)
+            .collect()
+    }
+
+    // Walks every link once; returns the bytes read
+    pub(crate) fn warm_up(&self) -> u64 {
+        let mut touched = 0;
+        let mut sum = 0u32;
+        for node in &self.nodes {
+            for links in &node.layers {
+                sum = links.iter().fold(sum, |acc, &n| acc.wrapping_add(n));
+                touched += 4 * links.len() as u64;
+            }
+        }
+        std::hint::black_box(sum);
+        touched
+    }
+
+    pub(crate) fn set_ef_search(&mut self, ef_search: usize) {
+        self.params.ef_search = ef_search;
+    }
+
+    fn greedy(&self, metric: Metric, query: &[f32], mut nearest: Candidate, layer: usize, records: &[Record]) -> Candidate {
+        loop {
+            let mut improved = false;
+            for &n in &self.nodes[nearest.node as usize].layers[layer] {
+                if let Some(dist) = self.dist(metric, query, n, records) {
+                    if dist < nearest.dist {
+                        nearest = Candidate { dist, node: n };
+                        improved = true;
+                    }
+                }
+            }
+            if !improved {
+                return nearest;
+            }
+        }
+    }
+
+    // The `ef` nodes closest to `query` reachable on `layer`, closest first
+    fn search_layer(
+        &self,
+        metric: Metric,
+        query: &[f32],
+        entry_points: &[Candidate],
+        ef: usize,
+        layer: usize,
+        records: &[Record],
+    ) -> Vec<Candidate> {
+        let ef = ef.max(1);
+        let mut visited: HashSet<u32> = entry_points.iter().map(|c| c.node).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = entry_points.iter().copied().map(Reverse).collect();
+        let mut results: BinaryHeap<Candidate> = entry_points.iter().copied().collect();
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if results.len() >= ef && results.peek().is_some_and(|worst| current.dist > worst.dist) {
+                break;
+            }
+            let Some(links) = self.nodes[current.node as usize].layers.get(layer) else {
+                continue;
+            };
+            for &n in links {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let Some(dist) = self.dist(metric, query, n, records) else {
+                    continue;
+                };
+                let candidate = Candidate { dist, node: n };
+                if results.len() < ef || results.peek().is_some_and(|worst| candidate < *worst) {
+                    candidates.push(Reverse(candidate));
+                    results.push(candidate);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    // Keeps the `max_links` closest live neighbors of `node` on `layer`
+    fn prune(&mut self, metric: Metric, node: u32, layer: usize, max_links: usize, records: &[Record]) {
+        let Some(pos) = self.nodes[node as usize].pos else {
+            return;
+        };
+        let base = &records[pos].embedding;
+        let mut links: Vec<Candidate> = self.nodes[node as usize].layers[layer]
+            .iter()
+            .filter_map(|&n| self.dist(metric, base, n, records).map(|dist| Candidate { dist, node: n }))
+            .collect();
+        links.sort_unstable();
+        links.truncate(max_links);
+        self.nodes[node as usize].layers[layer] = links.into_iter().map(|c| c.node).collect();
+    }
+
+    // Bytes the node for `pos` adds to the encoded graph, counting links to
+    // records that are still live
+    pub(crate) fn node_size(&self, pos: usize, records: &[Record]) -> u64 {
+        let Some(&node) = self.node_at.get(pos) else {
+            return 0;
+        };
+        if node == NONE {
+            return 0;
+        }
+        let live = |n: &&u32| self.nodes[**n as usize].pos.is_some_and(|p| !records[p].deleted);
+        self.nodes[node as usize]
+            .layers
+            .iter()
+            .map(|links| 4 + 4 * links.iter().filter(live).count() as u64)
+            .sum::<u64>()
+            + 8
+    }
+
+    fn highest_live_node(&self) -> Option<u32> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.pos.is_some())
+            .max_by_key(|(i, n)| (n.layers.len(), Reverse(*i)))
+            .map(|(i, _)| i as u32)
+    }
+
+    // Serialized form, with records numbered by their order among the records
+    // that get written (`ordinals`, None for records that don't). Links to
+    // records that aren't written are dropped.
+    pub(crate) fn encode(&self, ordinals: &[Option<u32>]) -> Vec<u8> {
+        let ordinal = |node: u32| self.nodes[node as usize].pos.and_then(|p| ordinals.get(p).copied().flatten());
+        let written: Vec<(u32, &Node)> = self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, n)| ordinal(i as u32).map(|o| (o, n)))
+            .collect();
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.params.m as u32).to_le_bytes());
+        payload.extend_from_slice(&(self.params.ef_construction as u32).to_le_bytes());
+        payload.extend_from_slice(&(self.params.ef_search as u32).to_le_bytes());
+        payload.extend_from_slice(&(written.len() as u64).to_le_bytes());
+        for (o, node) in written {
+            payload.extend_from_slice(&o.to_le_bytes());
+            payload.extend_from_slice(&(node.layers.len() as u32).to_le_bytes());
+            for links in &node.layers {
+                let links: Vec<u32> = links.iter().filter_map(|&n| ordinal(n)).collect();
+                payload.extend_from_slice(&(links.len() as u32).to_le_bytes());
+                for l in links {
+                    payload.extend_from_slice(&l.to_le_bytes());
+                }
+            }
+        }
+        payload
+    }
+
+    // Inverse of `encode` for a store whose records were read in ordinal order
+    pub(crate) fn decode(payload: &mut &[u8], n_records: usize) -> Result<Self, YAVSError> {
+        let params = HnswParams {
+            m: read_u32(payload)? as usize,
+            ef_construction: read_u32(payload)? as usize,
+            ef_search: read_u32(payload)? as usize,
+        };
+        let mut index = Self::new(params);
+        index.node_at = vec![NONE; n_records];
+
+        let count = read_u64(payload)?;
+        let mut links_by_ordinal = Vec::new();
+        for _ in 0..count {
+            let pos = read_u32(payload)? as usize;
+            let n_layers = read_u32(payload)? as usize;
+            if pos >= n_records || index.node_at[pos] != NONE || n_layers == 0 || n_layers > MAX_LEVELS {
+                return Err(YAVSError::InvalidFile);
+            }
+            let mut layers = Vec::with_capacity(n_layers);
+            for _ in 0..n_layers {
+                let n_links = read_u32(payload)? as usize;
+                if n_links * 4 > payload.len() {
+                    return Err(YAVSError::InvalidFile);
+                }
+                let mut links = Vec::with_capacity(n_links);
+                for _ in 0..n_links {
+                    links.push(read_u32(payload)?);
+                }
+                layers.push(links);
+            }
+            index.node_at[pos] = index.nodes.len() as u32;
+            index.nodes.push(Node {
+                pos: Some(pos),
+                layers: vec![Vec::new(); n_layers],
+            });
+            links_by_ordinal.push(layers);
+        }
+
+        // Links were stored as ordinals; keep those to nodes present on that layer
+        for (i, layers) in links_by_ordinal.into_iter().enumerate() {
+            for (layer, links) in layers.into_iter().enumerate() {
+                let resolved = links
+                    .into_iter()
+                    .filter_map(|o| index.node_at.get(o as usize).copied())
+                    .filter(|&n| n != NONE && index.nodes[n as usize].layers.len() > layer)
+                    .collect();
+                index.nodes[i].layers[layer] = resolved;
+            }
+        }
+        index.entry = index.highest_live_node();
+        Ok(index)
+    }
+}
+
+// An index being built a few records at a time, see `start_index_build`.
+// Records before `cursor` are in the graph and kept up to date like the
+// live index's; the rest are added by later steps.
+#[derive(Debug)]
+pub(crate) struct IndexBuild {
+    index: Hnsw,
+    cursor: usize,
+}
+
+impl IndexBuild {
+    pub(crate) fn insert(&mut self, pos: usize, records: &[Record], metric: Metric) {
+        if pos < self.cursor {
+            self.index.insert(pos, records, metric);
+        }
+    }
+
+    pub(crate) fn detach(&mut self, pos: usize) {
+        if pos < self.cursor {
+            self.index.detach(pos);
+        }
+    }
+
+    pub(crate) fn restart(&mut self) {
+        self.index.clear();
+        self.cursor = 0;
+    }
+
+    // After the record at `pos` was swap-removed, with the last record moved
+    // there from `last` (detached already, if built)
+    pub(crate) fn swap_removed(&mut self, pos: usize, last: usize, records: &[Record], metric: Metric) {
+        if pos != last {
+            if last < self.cursor {
+                self.index.relocate(last, pos);
+            } else {
+                self.insert(pos, records, metric);
+            }
+        }
+        self.cursor = self.cursor.min(records.len());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexProgress {
+    // Records in the graph so far, deleted ones included
+    pub indexed: usize,
+    pub total: usize,
+}
+
+impl IndexProgress {
+    pub fn is_done(&self) -> bool {
+        self.indexed >= self.total
+    }
+}
+
+impl YAVS {
+    // Builds an HNSW index over all records; `query` uses it from then on.
+    // Kept up to date on insert and persisted with the store. Does nothing
+    // under Pq and Int8 precision, where records have no embeddings to link.
+    pub fn build_index(&mut self, params: HnswParams) {
+        if self.precision.is_quantized() {
+            return;
+        }
+        self.index_build = None;
+        self.index = Some(Hnsw::new(params));
+        self.rebuild_indexes();
+        self.mark_changed();
+    }
+
+    // `build_index`, calling `progress` after every `step` records
+    pub fn build_index_with_progress<F: FnMut(IndexProgress)>(&mut self, params: HnswParams, step: usize, mut progress: F) {
+        self.start_index_build(params);
+        loop {
+            let p = self.build_index_step(step.max(1));
+            progress(p);
+            if p.is_done() {
+                break;
+            }
+        }
+    }
+
+    // Starts building an index in steps (see `build_index_step`), so a large
+    // store can keep answering queries in between; until the build finishes,
+    // `query` goes on using the current index or a full scan. Replaces any
+    // unfinished build. A build in progress isn't saved. Like `build_index`,
+    // does nothing under Pq and Int8 precision.
+    pub fn start_index_build(&mut self, params: HnswParams) {
+        if self.precision.is_quantized() {
+            return;
+        }
+        self.index_build = Some(IndexBuild { index: Hnsw::new(params), cursor: 0 });
+    }
+
+    // Adds up to `max_records` records to the index being built. Once every
+    // record is in, the new index replaces the current one in one go.
+    // Reports nothing left to do when no build is in progress.
+    pub fn build_index_step(&mut self, max_records: usize) -> IndexProgress {
+        let Some(mut build) = self.index_build.take() else {
+            return IndexProgress { indexed: self.records.len(), total: self.records.len() };
+        };
+        let end = build.cursor.saturating_add(max_records).min(self.records.len());
+        while build.cursor < end {
+            build.index.insert(build.cursor, &self.records, self.metric);
+            build.cursor += 1;
+        }
+        let progress = IndexProgress { indexed: build.cursor, total: self.records.len() };
+        if progress.is_done() {
+            self.index = Some(build.index);
+            self.mark_changed();
+        } else {
+            self.index_build = Some(build);
+        }
+        progress
+    }
+
+    pub fn index_build_progress(&self) -> Option<IndexProgress> {
+        self.index_build.as_ref().map(|b| IndexProgress { indexed: b.cursor, total: self.records.len() })
+    }
+
+    pub fn cancel_index_build(&mut self) {
+        self.index_build = None;
+    }
+
+    pub fn drop_index(&mut self) {
+        if self.index.take().is_some() {
+            self.mark_changed();
+        }
+    }
+
+    pub fn has_index(&self) -> bool {
+        self.index.is_some()
+    }
+
+    // Trades recall for speed; doesn't change what is saved
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        if let Some(index) = &mut self.index {
+            index.set_ef_search(ef_search);
+            self.clear_query_cache();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_recall, test_vectors, HnswParams, YAVS};
+
+    #[test]
+    fn recall_against_a_full_scan() {
+        let mut db = YAVS::new(16);
+        for v in test_vectors(1000, 16, 1) {
+            db.insert(&v, b"").unwrap();
+        }
+        let queries = test_vectors(20, 16, 2);
+        let exact: Vec<_> = queries.iter().map(|q| db.query(q, 10).unwrap()).collect();
+
+        db.build_index(HnswParams::default());
+        assert!(db.has_index());
+        let recall = queries.iter().zip(&exact).map(|(q, e)| test_recall(&db.query(q, 10).unwrap(), e)).sum::<f32>() / queries.len() as f32;
+        assert!(recall >= 0.9, "recall {recall}");
+
+        // Removed records don't come back from the graph
+        let nearest = exact[0][0].0;
+        assert!(db.remove(&nearest));
+        assert!(db.query(&queries[0], 10).unwrap().iter().all(|r| r.0 != nearest));
+    }
+}
@@ -0,0 +1,140 @@
+// Bulk ingestion from iterators and (with the `stream` feature) async streams.
+// Items are pulled in batches of at most `batch_size`, so the source is never
+// read further ahead than one batch while inserts or checkpoint saves run.
+
+use std::path::PathBuf;
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    pub batch_size: usize,
+    // Saved to after every `checkpoint_every` inserted records and at the end
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024,
+            checkpoint: None,
+            checkpoint_every: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    pub inserted: usize,
+    pub checkpoints: usize,
+}
+
+impl YAVS {
+    // Inserts every (embedding, metadata) pair and returns their IDs in
+    // order. Dimensions and metadata sizes are checked before anything is
+    // inserted; an insert hook or validator rejecting a record stops the
+    // batch there, with the records before it kept.
+    pub fn insert_batch(&mut self, items: &[(&[f32], &[u8])]) -> Result<Vec<[u8; 16]>, YAVSError> {
+        for (embedding, metadata) in items {
+            if embedding.len() as u32 != self.dim {
+                return Err(YAVSError::DimMismatch);
+            }
+            self.check_metadata_len(metadata)?;
+        }
+        self.reserve(items.len());
+        let mut ids = Vec::with_capacity(items.len());
+        for (embedding, metadata) in items {
+            ids.push(self.insert(embedding, metadata)?);
+        }
+        Ok(ids)
+    }
+
+    pub fn ingest<I>(&mut self, items: I, options: &IngestOptions) -> Result<IngestStats, YAVSError>
+    where
+        I: IntoIterator<Item = (Vec<f32>, Vec<u8>)>,
+    {
+        let mut items = items.into_iter();
+        let mut stats = IngestStats::default();
+        let mut since_checkpoint = 0;
+        let mut batch = Vec::with_capacity(options.batch_size.max(1));
+        loop {
+            batch.extend(items.by_ref().take(options.batch_size.max(1)));
+            if batch.is_empty() {
+                break;
+            }
+            self.ingest_batch(&mut batch, options, &mut stats, &mut since_checkpoint)?;
+        }
+        self.finish_ingest(options, &mut stats)?;
+        Ok(stats)
+    }
+
+    #[cfg(feature = "stream")]
+    pub async fn ingest_stream<S>(&mut self, mut stream: S, options: &IngestOptions) -> Result<IngestStats, YAVSError>
+    where
+        S: futures_core::Stream<Item = (Vec<f32>, Vec<u8>)> + Unpin,
+    {
+        use std::pin::Pin;
+        use std::task::Poll;
+
+        let mut stats = IngestStats::default();
+        let mut since_checkpoint = 0;
+        let mut batch = Vec::with_capacity(options.batch_size.max(1));
+        let mut done = false;
+        while !done {
+            // Wait for one item, then take whatever else is ready up to the batch size
+            let first = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+            match first {
+                Some(item) => batch.push(item),
+                None => done = true,
+            }
+            while !done && batch.len() < options.batch_size.max(1) {
+                let ready = std::future::poll_fn(|cx| match Pin::new(&mut stream).poll_next(cx) {
+                    Poll::Ready(item) => Poll::Ready(Some(item)),
+                    Poll::Pending => Poll::Ready(None),
+                })
+                .await;
+                match ready {
+                    Some(Some(item)) => batch.push(item),
+                    Some(None) => done = true,
+                    None => break,
+                }
+            }
+            self.ingest_batch(&mut batch, options, &mut stats, &mut since_checkpoint)?;
+        }
+        self.finish_ingest(options, &mut stats)?;
+        Ok(stats)
+    }
+
+    fn ingest_batch(
+        &mut self,
+        batch: &mut Vec<(Vec<f32>, Vec<u8>)>,
+        options: &IngestOptions,
+        stats: &mut IngestStats,
+        since_checkpoint: &mut usize,
+    ) -> Result<(), YAVSError> {
+        self.reserve(batch.len());
+        for (embedding, metadata) in batch.drain(..) {
+            self.insert_owned(embedding, metadata)?;
+            stats.inserted += 1;
+            *since_checkpoint += 1;
+        }
+        if let Some(path) = &options.checkpoint {
+            if options.checkpoint_every > 0 && *since_checkpoint >= options.checkpoint_every {
+                self.save(path)?;
+                stats.checkpoints += 1;
+                *since_checkpoint = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_ingest(&mut self, options: &IngestOptions, stats: &mut IngestStats) -> Result<(), YAVSError> {
+        if let Some(path) = &options.checkpoint {
+            if self.is_dirty() {
+                self.save(path)?;
+                stats.checkpoints += 1;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+// Optional IVF (inverted file) index: k-means centroids partition the
+// records into cells, and `query_ivf` only scans the cells closest to the
+// query. Cheaper to build and smaller than the HNSW graph, at some recall
+// cost that `nprobe` trades back.
+//
+// Like the tag postings, cells hold record positions (deleted records
+// included) and follow them through insert, removal and compaction. Centroids
+// only change when the index is retrained.
+
+use std::cmp::Ordering;
+use crate::cache::Scope;
+use crate::format::read_u32;
+use crate::ids::splitmix64;
+use crate::metric::compare_scores;
+use crate::{Distance, Metric, Record, YAVS, YAVSError};
+
+const NONE: u32 = u32::MAX;
+const TRAIN_SEED: u64 = 0x1f5eed;
+const TRAIN_ITERATIONS: usize = 20;
+// Training looks at no more than this many records per cell
+const SAMPLES_PER_CELL: usize = 256;
+
+#[derive(Debug)]
+pub(crate) struct Ivf {
+    centroids: Vec<Vec<f32>>,
+    cells: Vec<Vec<usize>>,
+    // Cell of each record position, or NONE
+    cell_of: Vec<u32>,
+}
+
+impl Ivf {
+    fn train(records: &[&Record], n_cells: usize, metric: Metric) -> Self {
+        let mut rng = TRAIN_SEED;
+        let sample = sample_embeddings(records, n_cells * SAMPLES_PER_CELL, &mut rng);
+        let centroids = kmeans(&sample, n_cells, metric, &mut rng);
+        Self {
+            cells: vec![Vec::new(); centroids.len()],
+            centroids,
+            cell_of: Vec::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+        self.cell_of.clear();
+    }
+
+    pub(crate) fn assign(&mut self, pos: usize, records: &[Record], metric: Metric) {
+        let cell = nearest_centroid(&self.centroids, &records[pos].embedding, metric);
+        self.cells[cell].push(pos);
+        if self.cell_of.len() <= pos {
+            self.cell_of.resize(pos + 1, NONE);
+        }
+        self.cell_of[pos] = cell as u32;
+    }
+
+    pub(crate) fn unassign(&mut self, pos: usize) {
+        let Some(&cell) = self.cell_of.get(pos) else {
+            return;
+        };
+        if cell != NONE {
+            self.cells[cell as usize].retain(|&p| p != pos);
+            self.cell_of[pos] = NONE;
+        }
+    }
+
+    pub(crate) fn relocate(&mut self, from: usize, to: usize) {
+        let Some(&cell) = self.cell_of.get(from) else {
+            return;
+        };
+        if cell != NONE {
+            for p in self.cells[cell as usize].iter_mut().filter(|p| **p == from) {
+                *p = to;
+            }
+            if self.cell_of.len() <= to {
+                self.cell_of.resize(to + 1, NONE);
+            }
+            self.cell_of[to] = cell;
+            self.cell_of[from] = NONE;
+        }
+        while self.cell_of.last() == Some(&NONE) {
+            self.cell_of.pop();
+        }
+    }
+
+    // Record positions in the `nprobe` cells closest to `query`
+    fn probe(&self, query: &[f32], nprobe: usize, metric: Metric) -> impl Iterator<Item = usize> + '_ {
+        let mut order: Vec<(usize, f32)> = self.centroids
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, metric.score(query, c)))
+            .collect();
+        order.sort_by(|a, b| compare_scores(&metric, a.1, b.1).then(a.0.cmp(&b.0)));
+        order
+            .into_iter()
+            .take(nprobe.max(1))
+            .flat_map(|(i, _)| self.cells[i].iter().copied())
+    }
+
+    pub(crate) fn n_cells(&self) -> usize {
+        self.centroids.len()
+    }
+
+    pub(crate) fn dim(&self) -> usize {
+        self.centroids[0].len()
+    }
+
+    // Serialized size without the cell entries, which take 4 bytes per record
+    pub(crate) fn fixed_size(&self) -> u64 {
+        let dim = self.centroids.first().map_or(0, |c| c.len()) as u64;
+        4 + 4 + self.centroids.len() as u64 * (4 * dim + 4)
+    }
+
+    // Cells list records by their order among the records that get written
+    // (`ordinals`, None for records that don't)
+    pub(crate) fn encode(&self, dim: u32, ordinals: &[Option<u32>]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.centroids.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&dim.to_le_bytes());
+        for centroid in &self.centroids {
+            for v in centroid {
+                payload.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        for cell in &self.cells {
+            let written: Vec<u32> = cell.iter().filter_map(|&p| ordinals.get(p).copied().flatten()).collect();
+            payload.extend_from_slice(&(written.len() as u32).to_le_bytes());
+            for o in written {
+                payload.extend_from_slice(&o.to_le_bytes());
+            }
+        }
+        payload
+    }
+
+    // Inverse of `encode`; every record must be in exactly one cell
+    pub(crate) fn decode(payload: &mut &[u8], n_records: usize) -> Result<Self, YAVSError> {
+        let n_cells = read_u32(payload)? as usize;
+        let dim = read_u32(payload)? as usize;
+        if n_cells == 0 || (n_cells as u64) * (dim as u64) * 4 > payload.len() as u64 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let mut centroids = Vec::with_capacity(n_cells);
+        for _ in 0..n_cells {
+            let mut centroid = Vec::with_capacity(dim);
+            for _ in 0..dim {
+                centroid.push(f32::from_bits(read_u32(payload)?));
+            }
+            centroids.push(centroid);
+        }
+
+        let mut ivf = Self {
+            centroids,
+            cells: Vec::with_capacity(n_cells),
+            cell_of: vec![NONE; n_records],
+        };
+        for cell in 0..n_cells {
+            let count = read_u32(payload)? as usize;
+            if count > n_records {
+                return Err(YAVSError::InvalidFile);
+            }
+            let mut positions = Vec::with_capacity(count);
+            for _ in 0..count {
+                let pos = read_u32(payload)? as usize;
+                if pos >= n_records || ivf.cell_of[pos] != NONE {
+                    return Err(YAVSError::InvalidFile);
+                }
+                ivf.cell_of[pos] = cell as u32;
+                positions.push(pos);
+            }
+            ivf.cells.push(positions);
+        }
+        if ivf.cell_of.contains(&NONE) {
+            return Err(YAVSError::InvalidFile);
+        }
+        Ok(ivf)
+    }
+}
+
+// Up to `max` embeddings drawn from `records`, or all of them if that's fewer
+pub(crate) fn sample_embeddings<'a>(records: &[&'a Record], max: usize, rng: &mut u64) -> Vec<&'a [f32]> {
+    sample_positions(records.len(), max, rng)
+        .into_iter()
+        .map(|i| records[i].embedding.as_slice())
+        .collect()
+}
+
+// Up to `max` positions drawn from 0..len, or all of them if that's fewer
+pub(crate) fn sample_positions(len: usize, max: usize, rng: &mut u64) -> Vec<usize> {
+    if len > max {
+        (0..max).map(|_| (splitmix64(rng) % len as u64) as usize).collect()
+    } else {
+        (0..len).collect()
+    }
+}
+
+// `k` centroids for `sample` (which must hold at least `k` points): seeded
+// with distinct sample points, then refined with Lloyd's iterations
+pub(crate) fn kmeans(sample: &[&[f32]], k: usize, metric: Metric, rng: &mut u64) -> Vec<Vec<f32>> {
+    let mut picked: Vec<usize> = (0..sample.len()).collect();
+    for i in 0..k {
+        let j = i + (splitmix64(rng) % (sample.len() - i) as u64) as usize;
+        picked.swap(i, j);
+    }
+    let mut centroids: Vec<Vec<f32>> = picked[..k].iter().map(|&i| sample[i].to_vec()).collect();
+
+    let mut assignment = vec![usize::MAX; sample.len()];
+    for _ in 0..TRAIN_ITERATIONS {
+        let mut moved = false;
+        for (embedding, cell) in sample.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_centroid(&centroids, embedding, metric);
+            moved |= *cell != nearest;
+            *cell = nearest;
+        }
+        if !moved {
+            break;
+        }
+
+        let dim = centroids[0].len();
+        let mut sums = vec![vec![0f64; dim]; k];
+        let mut counts = vec![0usize; k];
+        for (embedding, &cell) in sample.iter().zip(&assignment) {
+            // Non-finite components would poison the mean
+            if embedding.iter().all(|v| v.is_finite()) {
+                for (s, &v) in sums[cell].iter_mut().zip(embedding.iter()) {
+                    *s += v as f64;
+                }
+                counts[cell] += 1;
+            }
+        }
+        // Cells left empty keep their previous centroid
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                for (c, s) in centroid.iter_mut().zip(sum) {
+                    *c = (s / count as f64) as f32;
+                }
+            }
+        }
+    }
+    centroids
+}
+
+// Closest centroid under `metric`, NaN scores last
+pub(crate) fn nearest_centroid(centroids: &[Vec<f32>], embedding: &[f32], metric: Metric) -> usize {
+    centroids
+        .iter()
+        .map(|c| metric.score(embedding, c))
+        .enumerate()
+        .min_by(|a, b| match compare_scores(&metric, a.1, b.1) {
+            Ordering::Equal => a.0.cmp(&b.0),
+            other => other,
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+impl YAVS {
+    // Clusters the live records into up to `n_clusters` cells with k-means
+    // and builds an IVF index over them, replacing any previous one. New
+    // records are assigned to the nearest existing cell. Returns the number
+    // of cells, which is 0 (and no index) for an empty store or under Pq and
+    // Int8 precision.
+    pub fn train_ivf(&mut self, n_clusters: usize) -> usize {
+        if self.precision.is_quantized() {
+            return 0;
+        }
+        let live: Vec<&Record> = self.iter().collect();
+        let n_cells = n_clusters.min(live.len());
+        self.ivf = (n_cells > 0).then(|| Ivf::train(&live, n_cells, self.metric));
+        self.rebuild_indexes();
+        self.mark_changed();
+        n_cells
+    }
+
+    pub fn drop_ivf(&mut self) {
+        if self.ivf.take().is_some() {
+            self.mark_changed();
+        }
+    }
+
+    pub fn has_ivf(&self) -> bool {
+        self.ivf.is_some()
+    }
+
+    // Approximate `query` over the `nprobe` cells nearest the query; without
+    // a trained IVF index this is just `query`
+    pub fn query_ivf(&self, query_embedding: &[f32], k: usize, nprobe: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let Some(ivf) = &self.ivf else {
+            return self.query(query_embedding, k);
+        };
+        let candidates = ivf
+            .probe(query_embedding, nprobe, self.metric)
+            .map(|i| &self.records[i])
+            .filter(|r| !r.deleted);
+        Ok(self.rank(started, Scope::Cells(nprobe.clamp(1, ivf.n_cells())), self.metric, candidates, query_embedding, k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_recall, test_vectors, YAVS};
+
+    fn mean_recall(db: &YAVS, queries: &[Vec<f32>], exact: &[Vec<([u8; 16], f32)>], nprobe: usize) -> f32 {
+        queries.iter().zip(exact).map(|(q, e)| test_recall(&db.query_ivf(q, 10, nprobe).unwrap(), e)).sum::<f32>() / queries.len() as f32
+    }
+
+    #[test]
+    fn recall_against_a_full_scan() {
+        let mut db = YAVS::new(16);
+        let vectors = test_vectors(1000, 16, 3);
+        for v in &vectors[..800] {
+            db.insert(v, b"").unwrap();
+        }
+        assert_eq!(db.train_ivf(16), 16);
+        // Records inserted after training are assigned to cells too
+        for v in &vectors[800..] {
+            db.insert(v, b"").unwrap();
+        }
+        let queries = test_vectors(20, 16, 4);
+        let exact: Vec<_> = queries.iter().map(|q| db.query(q, 10).unwrap()).collect();
+
+        let recalls: Vec<f32> = [2, 8, 16].iter().map(|&nprobe| mean_recall(&db, &queries, &exact, nprobe)).collect();
+        assert!(recalls.windows(2).all(|w| w[0] <= w[1]));
+        assert!(recalls[1] >= 0.7, "recall {recalls:?}");
+        // Probing every cell is a full scan
+        assert_eq!(recalls[2], 1.0);
+    }
+}
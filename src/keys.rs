@@ -0,0 +1,51 @@
+// Caller-chosen record keys. Records can carry an arbitrary byte-string key
+// (a database primary key, a URL hash, `u64::to_be_bytes`, ...) alongside the
+// generated 16-byte ID, so callers can address records by their own keys
+// without keeping a separate mapping table. Keys are unique among live
+// records, indexed for constant-time lookup and persisted with the store.
+
+use crate::{NewRecord, YAVS, YAVSError};
+
+pub const MAX_KEY_LEN: usize = 256;
+
+impl YAVS {
+    pub fn insert_keyed(&mut self, key: &[u8], embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.insert_keyed_tagged(key, embedding, metadata, &[])
+    }
+
+    pub fn insert_keyed_tagged(&mut self, key: &[u8], embedding: &[f32], metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(YAVSError::KeyTooLong);
+        }
+        if self.id_for_key(key).is_some() {
+            return Err(YAVSError::DuplicateId);
+        }
+        let new = NewRecord {
+            tags,
+            key: Some(key),
+            ..Default::default()
+        };
+        self.insert_record(embedding.into(), metadata.into(), new)
+    }
+
+    pub fn id_for_key(&self, key: &[u8]) -> Option<[u8; 16]> {
+        self.key_position(key).map(|i| self.records[i].id)
+    }
+
+    pub(crate) fn key_position(&self, key: &[u8]) -> Option<usize> {
+        let &i = self.key_index.get(key)?;
+        let rec = &self.records[i];
+        (!rec.deleted && rec.key.as_deref() == Some(key)).then_some(i)
+    }
+
+    pub fn key_of(&self, id: &[u8; 16]) -> Option<&[u8]> {
+        self.find(id).and_then(|r| r.key.as_deref())
+    }
+
+    pub fn remove_key(&mut self, key: &[u8]) -> bool {
+        match self.id_for_key(key) {
+            Some(id) => self.remove(&id),
+            None => false,
+        }
+    }
+}
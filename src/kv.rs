@@ -0,0 +1,72 @@
+// A small key-value namespace saved inside the store file, for application
+// state that belongs with the vectors (sync cursors, prompt templates, model
+// version tags, ...), so shipping one file is enough. Not meant for bulk
+// data: the whole namespace is kept in memory and rewritten on every save.
+
+use std::collections::BTreeMap;
+use crate::format::{read_bytes, read_string, read_u64};
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct KvStore {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl KvStore {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Count, then each key and value
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.size() as usize);
+        payload.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for (key, value) in &self.entries {
+            payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            payload.extend_from_slice(key.as_bytes());
+            payload.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            payload.extend_from_slice(value);
+        }
+        payload
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        8 + self.entries.iter().map(|(k, v)| 4 + k.len() as u64 + 4 + v.len() as u64).sum::<u64>()
+    }
+
+    pub(crate) fn decode(payload: &mut &[u8]) -> Result<Self, YAVSError> {
+        let count = read_u64(payload)?;
+        let mut kv = Self::default();
+        for _ in 0..count {
+            let key = read_string(payload)?;
+            let value = read_bytes(payload)?;
+            kv.entries.insert(key, value);
+        }
+        Ok(kv)
+    }
+}
+
+impl YAVS {
+    // Values are limited to u32::MAX bytes, like metadata
+    pub fn kv_set(&mut self, key: &str, value: &[u8]) {
+        if self.kv.entries.get(key).map(Vec::as_slice) != Some(value) {
+            self.kv.entries.insert(key.to_string(), value.to_vec());
+            self.mark_changed();
+        }
+    }
+
+    pub fn kv_get(&self, key: &str) -> Option<&[u8]> {
+        self.kv.entries.get(key).map(Vec::as_slice)
+    }
+
+    pub fn kv_remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        let value = self.kv.entries.remove(key)?;
+        self.mark_changed();
+        Some(value)
+    }
+
+    // Keys in order
+    pub fn kv_keys(&self) -> impl Iterator<Item = &str> {
+        self.kv.entries.keys().map(String::as_str)
+    }
+}
@@ -0,0 +1,67 @@
+// Language routing for multilingual stores. A record's language is kept as a
+// `lang:<code>` tag, so it persists, filters and compacts like any other tag;
+// these helpers just spell out the common setup of inserting with a language
+// and querying one or several of them. Codes are compared case-insensitively.
+
+use crate::cache::Scope;
+use crate::{YAVS, YAVSError};
+
+const LANG_PREFIX: &str = "lang:";
+
+fn lang_tag(lang: &str) -> String {
+    format!("{}{}", LANG_PREFIX, lang.to_ascii_lowercase())
+}
+
+impl YAVS {
+    pub fn insert_lang(&mut self, lang: &str, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.insert_lang_tagged(lang, embedding, metadata, &[])
+    }
+
+    pub fn insert_lang_tagged(&mut self, lang: &str, embedding: &[f32], metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        let lang = lang_tag(lang);
+        let mut tags = tags.to_vec();
+        tags.push(&lang);
+        self.insert_tagged(embedding, metadata, &tags)
+    }
+
+    // The language the record was inserted with, if any
+    pub fn lang_of(&self, id: &[u8; 16]) -> Option<&str> {
+        self.find(id)?
+            .tags
+            .iter()
+            .find_map(|t| t.strip_prefix(LANG_PREFIX))
+    }
+
+    // Languages in use by live records, with their record counts
+    pub fn languages(&self) -> Vec<(&str, usize)> {
+        self.tags()
+            .into_iter()
+            .filter_map(|(tag, n)| tag.strip_prefix(LANG_PREFIX).map(|lang| (lang, n)))
+            .collect()
+    }
+
+    // Like `query`, but only over records in any of `langs`
+    pub fn query_langs(&self, query_embedding: &[f32], k: usize, langs: &[&str]) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let mut tags: Vec<String> = langs.iter().map(|l| lang_tag(l)).collect();
+        tags.sort_unstable();
+        tags.dedup();
+
+        // A record carrying several language tags is only ranked once
+        let mut positions: Vec<usize> = tags
+            .iter()
+            .flat_map(|t| self.tag_index.get(t).iter().copied())
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        let candidates = positions
+            .into_iter()
+            .map(|i| &self.records[i])
+            .filter(|r| !r.deleted);
+        let scope: Vec<&str> = tags.iter().map(String::as_str).collect();
+        Ok(self.rank(started, Scope::Langs(&scope), self.metric, candidates, query_embedding, k))
+    }
+}
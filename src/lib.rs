@@ -1,25 +1,187 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use uuid::Uuid;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use thiserror::Error;
+use access::AccessTracker;
+use append::Appender;
+use cache::{QueryCache, Scope};
+use eval::EvalSet;
+use fields::FieldIndexes;
+use index::{Hnsw, IndexBuild};
+use ivf::Ivf;
+use kv::KvStore;
+use metric::compare_scores;
+use postings::Postings;
+use pq::ProductQuantizer;
+use slowlog::{QueryRun, SlowQueryLog};
+use sq::ScalarQuantizer;
+use throttle::QueryLimiter;
+use topk::TopK;
+use wal::Wal;
 
-const MAGIC: &[u8] = b"YAVS";
-const VERSION: u32 = 1;
-const RESERVED_SIZE: usize = 16;
+// Smallest share of the records worth handing to another thread in exact search
+#[cfg(feature = "rayon")]
+const MIN_PARALLEL_CHUNK: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct Record {
     pub id: [u8; 16],
     pub embedding: Vec<f32>,
+    // Under Pq and Int8 precision, the record's code in place of its
+    // embedding, which is then empty; see `Precision`
+    pub code: Vec<u8>,
     pub metadata: Vec<u8>,
+    pub tags: BTreeSet<String>,
+    pub tenant: Option<String>,
+    // Caller-chosen key, see `insert_keyed`
+    pub key: Option<Vec<u8>>,
+    // Exempt from automatic removal, see `pin`
+    pub pinned: bool,
+    // Shared between records with the same provenance, see `insert_with_provenance`
+    pub provenance: Option<Arc<Provenance>>,
+    // Last write or deletion, in ms from the store clock; drives merge()
+    pub updated_at: u64,
     pub deleted: bool,
 }
 
+impl Record {
+    // A live record with no tags, tenant, key or timestamp
+    pub fn new(id: [u8; 16], embedding: Vec<f32>, metadata: Vec<u8>) -> Self {
+        Self {
+            id,
+            embedding,
+            code: Vec::new(),
+            metadata,
+            tags: BTreeSet::new(),
+            tenant: None,
+            key: None,
+            pinned: false,
+            provenance: None,
+            updated_at: 0,
+            deleted: false,
+        }
+    }
+
+    fn tombstone(&mut self, now: u64) {
+        self.deleted = true;
+        self.updated_at = now;
+    }
+}
+
+// Everything about a new record besides its embedding and metadata, see
+// `YAVS::insert_record`. A None ID is generated.
+#[derive(Default)]
+pub(crate) struct NewRecord<'a> {
+    id: Option<[u8; 16]>,
+    tags: &'a [&'a str],
+    tenant: Option<&'a str>,
+    key: Option<&'a [u8]>,
+    provenance: Option<Arc<Provenance>>,
+}
+
+// A live record's contents, see `YAVS::view`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordView<'a> {
+    pub id: [u8; 16],
+    pub embedding: &'a [f32],
+    pub metadata: &'a [u8],
+    pub tags: &'a BTreeSet<String>,
+    pub tenant: Option<&'a str>,
+    pub key: Option<&'a [u8]>,
+    pub provenance: Option<&'a Provenance>,
+}
+
+// A query hit with its record's metadata, see `YAVS::query_with_metadata`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryResult<'a> {
+    pub id: [u8; 16],
+    pub score: f32,
+    pub metadata: &'a [u8],
+}
+
+impl<'a> From<&'a Record> for RecordView<'a> {
+    fn from(r: &'a Record) -> Self {
+        RecordView {
+            id: r.id,
+            embedding: &r.embedding,
+            metadata: &r.metadata,
+            tags: &r.tags,
+            tenant: r.tenant.as_deref(),
+            key: r.key.as_deref(),
+            provenance: r.provenance.as_deref(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct YAVS {
     dim: u32,
+    metric: Metric,
     records: Vec<Record>,
+    tag_index: Postings,
+    tenant_index: Postings,
+    fields: FieldIndexes,
+    // Position of each live record by ID. Entries left behind by deletions
+    // are only cleared when the record moves, so lookups check `deleted`.
+    id_index: HashMap<[u8; 16], usize>,
+    // Position of each live keyed record by key, kept like `id_index`
+    key_index: HashMap<Vec<u8>, usize>,
+    // compact automatically once this fraction of records is deleted
+    auto_compact: Option<f32>,
+    id_source: Box<dyn IdSource>,
+    clock: Box<dyn Clock>,
+    compaction_log: Option<Vec<CompactionEntry>>,
+    // set by any change to what `save` would write, cleared by `save`
+    dirty: bool,
+    access: Option<AccessTracker>,
+    cache: Option<QueryCache>,
+    trash: TrashPolicy,
+    index: Option<Hnsw>,
+    index_build: Option<IndexBuild>,
+    ivf: Option<Ivf>,
+    pq: Option<ProductQuantizer>,
+    sq: Option<ScalarQuantizer>,
+    eval: EvalSet,
+    kv: KvStore,
+    precision: Precision,
+    compress_metadata: bool,
+    insert_hooks: Vec<Box<dyn InsertHook>>,
+    validators: Vec<(Box<dyn Validator>, OnInvalid)>,
+    slow_log: SlowQueryLog,
+    // Chunks for parallel exact search: 0 for rayon's thread count, 1 for none
+    query_threads: usize,
+    // Bumped by every change, see `append`
+    changes: u64,
+    appender: Option<Appender>,
+    max_metadata_len: Option<usize>,
+    wal: Option<Wal>,
+    duplicate_policy: DuplicatePolicy,
+    query_limit: Option<QueryLimiter>,
+    group_commit: Option<GroupCommit>,
+}
+
+// What `insert_with_id` does when a live record already has the ID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    // Fail with `DuplicateId`
+    #[default]
+    Reject,
+    // Replace its embedding and metadata, as `update` does
+    Overwrite,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    // Purge deleted records from memory before writing
+    pub compact: bool,
+    // Previous versions of the file kept as `<path>.1` (newest) up to
+    // `<path>.<backups>`
+    pub backups: usize,
 }
 
 #[derive(Error, Debug)]
@@ -32,262 +194,1391 @@ pub enum YAVSError {
     IoError(#[from] std::io::Error),
     #[error("Dimension mismatch")]
     DimMismatch,
+    #[error("Record not found")]
+    NotFound,
+    #[error("Duplicate record ID")]
+    DuplicateId,
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+    #[error("Corrupt data at byte {offset}")]
+    Corrupt { offset: u64 },
+    #[error("Distance metric mismatch")]
+    MetricMismatch,
+    #[error("Record key longer than MAX_KEY_LEN")]
+    KeyTooLong,
+    #[error("Record metadata longer than the store's limit")]
+    MetadataTooLarge,
+    #[error("Reranker returned a different number of scores than candidates")]
+    RerankMismatch,
+    #[error("Invalid noise parameters")]
+    InvalidNoise,
+    #[error("Too many concurrent queries")]
+    Busy,
+    #[error("Replica does not match the primary")]
+    ReplicaMismatch,
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("No index on metadata field `{0}` for this filter")]
+    FieldNotIndexed(String),
+    #[error("No store for route `{0}`")]
+    UnknownRoute(String),
+    #[error("Invalid store name `{0}`")]
+    InvalidStoreName(String),
+    #[error("Store has write-ahead log entries not yet checkpointed; open it with open_wal")]
+    PendingWal,
+    #[error("Write-ahead log failed; checkpoint before writing again")]
+    WalFailed,
+    #[error("Path is the file the store has open; save it with save")]
+    OpenFile,
+    #[error("Store file is encrypted; open it with load_encrypted")]
+    Encrypted,
+    #[error("Wrong key or tampered encrypted store")]
+    DecryptionFailed,
+    #[error("Unsupported Arrow input: {0}")]
+    ArrowInput(String),
+    #[error("NATS error: {0}")]
+    Nats(String),
+    #[error("Store keeps quantized codes in place of embeddings; load it with load_file")]
+    Quantized,
+    #[error("No trained {0} quantizer")]
+    Untrained(&'static str),
+    #[error("Requires the `{0}` feature")]
+    FeatureDisabled(&'static str),
 }
 
 impl YAVS {
     pub fn new(dim: u32) -> Self {
-        Self {
-            dim,
-            records: Vec::new(),
-        }
+        Self::with_metric(dim, Metric::Euclidean)
     }
 
-    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
-        let mut file = File::open(path.as_ref())?;
+    pub fn with_metric(dim: u32, metric: Metric) -> Self {
+        Self::from_records(dim, metric, Vec::new())
+    }
 
-        // Read header
-        let mut magic_buf = [0u8; 4];
-        file.read_exact(&mut magic_buf)?;
-        if magic_buf != MAGIC {
-            return Err(YAVSError::InvalidFile);
-        }
+    fn from_records(dim: u32, metric: Metric, records: Vec<Record>) -> Self {
+        let mut db = Self {
+            dim,
+            metric,
+            records,
+            tag_index: Postings::default(),
+            tenant_index: Postings::default(),
+            fields: FieldIndexes::default(),
+            id_index: HashMap::new(),
+            key_index: HashMap::new(),
+            auto_compact: None,
+            id_source: Box::new(RandomIds),
+            clock: Box::new(SystemClock),
+            compaction_log: None,
+            dirty: false,
+            access: None,
+            cache: None,
+            trash: TrashPolicy::default(),
+            index: None,
+            index_build: None,
+            ivf: None,
+            pq: None,
+            sq: None,
+            eval: EvalSet::default(),
+            kv: KvStore::default(),
+            precision: Precision::F32,
+            compress_metadata: false,
+            insert_hooks: Vec::new(),
+            validators: Vec::new(),
+            slow_log: SlowQueryLog::default(),
+            query_threads: 0,
+            changes: 0,
+            appender: None,
+            max_metadata_len: None,
+            wal: None,
+            duplicate_policy: DuplicatePolicy::Reject,
+            query_limit: None,
+            group_commit: None,
+        };
+        db.rebuild_indexes();
+        db
+    }
 
-        let mut version_buf = [0u8; 4];
-        file.read_exact(&mut version_buf)?;
-        let version = u32::from_le_bytes(version_buf);
-        if version != VERSION {
-            return Err(YAVSError::VersionMismatch);
+    fn rebuild_indexes(&mut self) {
+        self.tag_index.clear();
+        self.tenant_index.clear();
+        self.fields.clear();
+        self.id_index.clear();
+        self.key_index.clear();
+        if let Some(index) = &mut self.index {
+            index.clear();
         }
+        if let Some(build) = &mut self.index_build {
+            build.restart();
+        }
+        if let Some(ivf) = &mut self.ivf {
+            ivf.clear();
+        }
+        if let Some(pq) = &mut self.pq {
+            pq.clear();
+        }
+        if let Some(sq) = &mut self.sq {
+            sq.clear();
+        }
+        for i in 0..self.records.len() {
+            self.index_record(i);
+        }
+    }
 
-        let mut n_records_buf = [0u8; 8];
-        file.read_exact(&mut n_records_buf)?;
-        let n_records = u64::from_le_bytes(n_records_buf);
+    fn index_record(&mut self, i: usize) {
+        let rec = &self.records[i];
+        for tag in &rec.tags {
+            self.tag_index.add(tag, i);
+        }
+        if let Some(tenant) = &rec.tenant {
+            self.tenant_index.add(tenant, i);
+        }
+        self.fields.add(i, &rec.metadata);
+        if !rec.deleted {
+            self.id_index.insert(rec.id, i);
+            if let Some(key) = &rec.key {
+                self.key_index.insert(key.clone(), i);
+            }
+        }
+        if let Some(index) = &mut self.index {
+            index.insert(i, &self.records, self.metric);
+        }
+        if let Some(build) = &mut self.index_build {
+            build.insert(i, &self.records, self.metric);
+        }
+        if let Some(ivf) = &mut self.ivf {
+            ivf.assign(i, &self.records, self.metric);
+        }
+        if self.pq.is_some() || self.sq.is_some() {
+            let rec = &self.records[i];
+            let decoded = (!rec.code.is_empty()).then(|| self.vector(rec).into_owned());
+            let embedding = decoded.as_deref().unwrap_or(&rec.embedding);
+            // Records under Pq and Int8 precision carry their own codes
+            if let Some(pq) = &mut self.pq {
+                if self.precision != Precision::Pq {
+                    pq.encode(i, embedding);
+                }
+            }
+            if let Some(sq) = &mut self.sq {
+                if self.precision != Precision::Int8 {
+                    sq.encode(i, embedding);
+                }
+            }
+        }
+    }
 
-        let mut dim_buf = [0u8; 4];
-        file.read_exact(&mut dim_buf)?;
-        let dim = u32::from_le_bytes(dim_buf);
+    fn unindex_record(&mut self, i: usize) {
+        let rec = &self.records[i];
+        for tag in &rec.tags {
+            self.tag_index.remove(tag, i);
+        }
+        if let Some(tenant) = &rec.tenant {
+            self.tenant_index.remove(tenant, i);
+        }
+        self.fields.remove(i, &rec.metadata);
+        if self.id_index.get(&rec.id) == Some(&i) {
+            self.id_index.remove(&rec.id);
+        }
+        if let Some(key) = &rec.key {
+            if self.key_index.get(key) == Some(&i) {
+                self.key_index.remove(key);
+            }
+        }
+        if let Some(index) = &mut self.index {
+            index.detach(i);
+        }
+        if let Some(build) = &mut self.index_build {
+            build.detach(i);
+        }
+        if let Some(ivf) = &mut self.ivf {
+            ivf.unassign(i);
+        }
+    }
 
-        // Skip reserved
-        let mut reserved = vec![0u8; RESERVED_SIZE];
-        file.read_exact(&mut reserved)?;
+    // Fails with `PendingWal` if a write-ahead log next to the file still
+    // holds entries; `open_wal` replays them
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        wal::check_no_pending_log(path.as_ref())?;
+        Self::read_file(path)
+    }
 
-        // Read records
-        let mut records = Vec::with_capacity(n_records as usize);
+    fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        let mut file = BufReader::new(File::open(path.as_ref())?);
+        format::read_store(&mut file)
+    }
 
-        for _ in 0..n_records {
-            let mut id = [0u8; 16];
-            file.read_exact(&mut id)?;
+    pub fn create<P: AsRef<Path>>(path: P, dim: u32) -> Result<(), YAVSError> {
+        let mut file = File::create(path)?;
+        format::write_header(&mut file, 0, dim, Metric::Euclidean, Precision::F32.into(), None)
+    }
 
-            let mut embedding = vec![0f32; dim as usize];
-            for i in 0..dim as usize {
-                let mut float_buf = [0u8; 4];
-                file.read_exact(&mut float_buf)?;
-                embedding[i] = f32::from_le_bytes(float_buf);
-            }
+    // Writes all records, deleted ones included (see `tombstones`) until
+    // `compact` or `vacuum` purges them or `SaveOptions::compact` is set.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), YAVSError> {
+        self.save_with(path, &SaveOptions::default())
+    }
 
-            let mut meta_len_buf = [0u8; 4];
-            file.read_exact(&mut meta_len_buf)?;
-            let meta_len = u32::from_le_bytes(meta_len_buf) as usize;
+    pub fn save_with<P: AsRef<Path>>(&mut self, path: P, options: &SaveOptions) -> Result<(), YAVSError> {
+        if options.compact {
+            self.compact();
+        }
 
-            let mut metadata = vec![0u8; meta_len];
-            file.read_exact(&mut metadata)?;
+        // Writing through a second handle would pull the file out from under
+        // the appender
+        if self.rw_path() == Some(path.as_ref()) {
+            return self.flush_with_backups(options.backups);
+        }
+        if self.wal_path() == Some(path.as_ref()) {
+            return self.checkpoint();
+        }
+        self.write_file(path.as_ref(), options.backups)?;
+        self.dirty = false;
+        Ok(())
+    }
 
-            records.push(Record {
-                id,
-                embedding,
-                metadata,
-                deleted: false,
-            });
+    // Snapshot to `path` without touching the store, so it works from a shared
+    // reference. Unlike `save` it leaves the dirty flag alone, and it refuses
+    // the file the store has open with `open_rw` or `open_wal`, which only
+    // `save` can bring up to date.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P) -> Result<(), YAVSError> {
+        let path = path.as_ref();
+        if self.rw_path() == Some(path) || self.wal_path() == Some(path) {
+            return Err(YAVSError::OpenFile);
         }
+        self.write_file(path, 0)
+    }
 
-        Ok(Self { dim, records })
+    pub(crate) fn write_file(&self, path: &Path, backups: usize) -> Result<(), YAVSError> {
+        write_atomic(path, backups, |w| format::write_store(self, w))
     }
 
-    pub fn create<P: AsRef<Path>>(path: P, dim: u32) -> Result<(), YAVSError> {
-        let mut file = File::create(path)?;
+    // Whether the store has changed since it was loaded or last saved with `save`
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
 
-        // Write magic
-        file.write_all(MAGIC)?;
-        // Write version
-        file.write_all(&VERSION.to_le_bytes())?;
-        // Write n_records = 0
-        file.write_all(&0u64.to_le_bytes())?;
-        // Write dim
-        file.write_all(&dim.to_le_bytes())?;
-        // Write reserved
-        file.write_all(&[0u8; RESERVED_SIZE])?;
-        Ok(())
+    // Called after any change that affects what is saved or what queries return
+    fn mark_changed(&mut self) {
+        self.dirty = true;
+        self.changes = self.changes.wrapping_add(1);
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
     }
 
-    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), YAVSError> {
-        self.compact();
+    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.insert_tagged(embedding, metadata, &[])
+    }
 
-        let mut file = File::create(path)?;
+    pub fn insert_tagged(&mut self, embedding: &[f32], metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        self.insert_record(embedding.into(), metadata.into(), NewRecord { tags, ..Default::default() })
+    }
 
-        // Write header
-        file.write_all(MAGIC)?;
-        file.write_all(&VERSION.to_le_bytes())?;
-        file.write_all(&(self.records.len() as u64).to_le_bytes())?;
-        file.write_all(&self.dim.to_le_bytes())?;
-        file.write_all(&[0u8; RESERVED_SIZE])?;
-
-        // Write each record
-        for rec in &self.records {
-            file.write_all(&rec.id)?;
-            // embedding
-            for &val in &rec.embedding {
-                file.write_all(&val.to_le_bytes())?;
-            }
-            // metadata length
-            file.write_all(&(rec.metadata.len() as u32).to_le_bytes())?;
-            // metadata
-            file.write_all(&rec.metadata)?;
-        }
+    // Takes ownership of the buffers instead of copying them
+    pub fn insert_owned(&mut self, embedding: Vec<f32>, metadata: Vec<u8>) -> Result<[u8; 16], YAVSError> {
+        self.insert_record(embedding.into(), metadata.into(), NewRecord::default())
+    }
 
-        Ok(())
+    // Copies only the borrowed parts
+    pub fn insert_cow(&mut self, embedding: Cow<'_, [f32]>, metadata: Cow<'_, [u8]>) -> Result<[u8; 16], YAVSError> {
+        self.insert_record(embedding, metadata, NewRecord::default())
     }
 
-    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+    fn insert_record(&mut self, embedding: Cow<'_, [f32]>, metadata: Cow<'_, [u8]>, new: NewRecord<'_>) -> Result<[u8; 16], YAVSError> {
         if embedding.len() as u32 != self.dim {
             return Err(YAVSError::DimMismatch);
         }
-        let new_uuid = Uuid::new_v4();
-        let new_id = *new_uuid.as_bytes();
+        if new.id.is_some_and(|id| self.position(&id).is_some()) {
+            return Err(YAVSError::DuplicateId);
+        }
+        let now = self.clock.now_ms();
+        let mut embedding = embedding.into_owned();
+        self.precision.round_all(&mut embedding);
+        let mut metadata = metadata.into_owned();
+        self.run_insert_hooks(&embedding, &mut metadata, now)?;
+        self.run_validators(&mut embedding, &mut metadata)?;
+        self.check_metadata_len(&metadata)?;
+        let new_id = new.id.unwrap_or_else(|| self.id_source.next_id());
         let rec = Record {
             id: new_id,
-            embedding: embedding.to_vec(),
-            metadata: metadata.to_vec(),
+            embedding,
+            code: Vec::new(),
+            metadata,
+            tags: new.tags.iter().map(|t| t.to_string()).collect(),
+            tenant: new.tenant.map(|t| t.to_string()),
+            key: new.key.map(|k| k.to_vec()),
+            pinned: false,
+            provenance: new.provenance,
+            updated_at: now,
             deleted: false,
         };
-        self.records.push(rec);
+        self.log_inserted(&rec)?;
+        self.commit_inserted(rec)?;
         Ok(new_id)
     }
 
-    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
-        for rec in &mut self.records {
-            if &rec.id == id {
-                rec.deleted = true;
-                return true;
+    // Adds a logged record, appending it to an `open_rw` file. If the append
+    // fails the record is taken out again, so a failed insert leaves nothing
+    // behind; the next `flush` rewrites the file.
+    fn commit_inserted(&mut self, mut rec: Record) -> Result<(), YAVSError> {
+        self.fit_precision(&mut rec);
+        let changes = self.changes;
+        self.records.push(rec);
+        self.index_record(self.records.len() - 1);
+        self.mark_changed();
+        if let Err(e) = self.append_inserted(changes) {
+            self.swap_remove_record(self.records.len() - 1);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Replaces a live record's embedding and metadata in place, keeping its
+    // ID, tags, tenant, key, provenance and pin. Insert hooks and validators
+    // run as for a new record.
+    pub fn update(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<(), YAVSError> {
+        if embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let i = self.position(id).ok_or(YAVSError::NotFound)?;
+        let now = self.clock.now_ms();
+        let mut embedding = embedding.to_vec();
+        let mut metadata = metadata.to_vec();
+        self.run_insert_hooks(&embedding, &mut metadata, now)?;
+        self.run_validators(&mut embedding, &mut metadata)?;
+        self.check_metadata_len(&metadata)?;
+        let mut rec = self.records[i].clone();
+        rec.embedding = embedding;
+        rec.metadata = metadata;
+        rec.updated_at = now;
+        self.precision.round_all(&mut rec.embedding);
+        self.log_updated(&rec)?;
+        self.replace_record(i, rec);
+        Ok(())
+    }
+
+    // Inserts under a caller-provided ID, e.g. one from an upstream system.
+    // An ID already in use is rejected or overwritten according to
+    // `set_duplicate_policy`.
+    pub fn insert_with_id(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<(), YAVSError> {
+        if self.duplicate_policy == DuplicatePolicy::Overwrite && self.position(id).is_some() {
+            return self.update(id, embedding, metadata);
+        }
+        let new = NewRecord {
+            id: Some(*id),
+            ..Default::default()
+        };
+        self.insert_record(embedding.into(), metadata.into(), new)?;
+        Ok(())
+    }
+
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    // `update` if there is a live record with this ID, otherwise inserts one
+    // under it. Returns whether a record was inserted.
+    pub fn upsert(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<bool, YAVSError> {
+        if self.position(id).is_some() {
+            self.update(id, embedding, metadata)?;
+            return Ok(false);
+        }
+        let new = NewRecord {
+            id: Some(*id),
+            ..Default::default()
+        };
+        self.insert_record(embedding.into(), metadata.into(), new)?;
+        Ok(true)
+    }
+
+    // Swaps in a new version of the record at `i`, reindexing it
+    fn replace_record(&mut self, i: usize, mut rec: Record) {
+        self.fit_precision(&mut rec);
+        self.unindex_record(i);
+        self.records[i] = rec;
+        self.index_record(i);
+        self.mark_changed();
+    }
+
+    // Reserves room for `additional` more records ahead of a bulk load
+    pub fn reserve(&mut self, additional: usize) {
+        self.records.reserve(additional);
+    }
+
+    // Releases spare capacity, e.g. after a bulk load or compaction
+    pub fn shrink_to_fit(&mut self) {
+        self.records.shrink_to_fit();
+    }
+
+    // Appends a record as-is, without generating an ID or timestamp, for tools
+    // that replicate or transform stores. Deleted records are kept as tombstones.
+    // Only the embedding may change, rounded to the store's precision.
+    pub fn push_record(&mut self, mut record: Record) -> Result<(), YAVSError> {
+        if record.embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        self.check_metadata_len(&record.metadata)?;
+        if !record.deleted {
+            if self.position(&record.id).is_some() {
+                return Err(YAVSError::DuplicateId);
             }
+            if let Some(key) = &record.key {
+                if key.len() > MAX_KEY_LEN {
+                    return Err(YAVSError::KeyTooLong);
+                }
+                if self.id_for_key(key).is_some() {
+                    return Err(YAVSError::DuplicateId);
+                }
+            }
+        }
+        self.precision.round_all(&mut record.embedding);
+        self.log_inserted(&record)?;
+        self.commit_inserted(record)
+    }
+
+    // Replaces the generator used for new record IDs, e.g. `SeededIds` for
+    // reproducible store files
+    pub fn set_id_source<S: IdSource + 'static>(&mut self, source: S) {
+        self.id_source = Box::new(source);
+    }
+
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    // Bounds the threads exact `query` scans use with the `rayon` feature:
+    // 0 (the default) uses rayon's pool size, 1 scans sequentially. Has no
+    // effect without the feature.
+    pub fn set_query_threads(&mut self, threads: usize) {
+        self.query_threads = threads;
+    }
+
+    // Current time according to the store's clock, in ms since the Unix epoch
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    // False for an unknown or deleted ID, and for a removal that couldn't be
+    // written to the store's write-ahead log (see `wal_failed`)
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        let Some(i) = self.position(id) else {
+            return false;
+        };
+        let now = self.clock.now_ms();
+        if self.remove_positions(&[i], now) == 0 {
+            return false;
+        }
+        self.maybe_compact();
+        true
+    }
+
+    // Changes a record's ID in place, keeping its position, tags and payload.
+    pub fn rekey(&mut self, old_id: &[u8; 16], new_id: [u8; 16]) -> Result<(), YAVSError> {
+        let i = self.position(old_id).ok_or(YAVSError::NotFound)?;
+        if old_id == &new_id {
+            return Ok(());
+        }
+        if self.position(&new_id).is_some() {
+            return Err(YAVSError::DuplicateId);
+        }
+        if self.wal.is_some() {
+            let at = self.clock.now_ms().max(self.records[i].updated_at);
+            let renamed = Record { id: new_id, ..self.expanded(&self.records[i]) };
+            self.log_rekeyed(old_id, &renamed, at)?;
         }
-        false
+        self.records[i].id = new_id;
+        self.id_index.remove(old_id);
+        self.id_index.insert(new_id, i);
+        if let Some(access) = &self.access {
+            access.rekey(old_id, new_id);
+        }
+        self.mark_changed();
+        Ok(())
     }
 
+    // Marks every live record matching `filter` as deleted in a single pass.
+    pub fn remove_where<F: Fn(&Record) -> bool>(&mut self, filter: F) -> usize {
+        let now = self.clock.now_ms();
+        let positions: Vec<usize> = self.records
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.deleted && filter(r))
+            .map(|(i, _)| i)
+            .collect();
+        let removed = self.remove_positions(&positions, now);
+        if removed > 0 {
+            self.maybe_compact();
+        }
+        removed
+    }
+
+    // Purges deleted records, except those the trash policy still retains
     pub fn compact(&mut self) {
-        self.records.retain(|r| !r.deleted);
+        let now = self.clock.now_ms();
+        let purge: Vec<bool> = self.records.iter().map(|r| self.purgeable(r, now)).collect();
+        self.purge(&purge);
+    }
+
+    // Purges every deleted record that isn't pinned, whatever the trash
+    // policy, and releases the memory they held
+    pub fn vacuum(&mut self) {
+        let purge: Vec<bool> = self.records.iter().map(|r| r.deleted && !r.pinned).collect();
+        self.purge(&purge);
+        self.shrink_to_fit();
+    }
+
+    // Drops the records flagged in `purge`, keeping the others in order
+    fn purge(&mut self, purge: &[bool]) {
+        let mut flags = purge.iter();
+        let (purged, kept): (Vec<Record>, Vec<Record>) = std::mem::take(&mut self.records)
+            .into_iter()
+            .partition(|_| flags.next().copied().unwrap_or(false));
+        self.records = kept;
+        self.rebuild_indexes();
+        let purged = purged
+            .into_iter()
+            .map(|r| Tombstone { id: r.id, deleted_at: r.updated_at })
+            .collect();
+        self.after_purge(purged);
+    }
+
+    fn after_purge(&mut self, purged: Vec<Tombstone>) {
+        if purged.is_empty() {
+            return;
+        }
+        if let Some(access) = &self.access {
+            access.forget(purged.iter().map(|t| &t.id));
+        }
+        // Tombstones are saved, so purging changes the file
+        self.mark_changed();
+        self.log_purged(purged);
+    }
+
+    // Incremental compaction: reclaims at most `max_removals` deleted records
+    // and returns whether any purgeable ones remain. Unlike `compact` this does
+    // not preserve record order, as holes are filled from the end of the store.
+    pub fn compact_step(&mut self, max_removals: usize) -> bool {
+        let now = self.clock.now_ms();
+        let mut purged = Vec::new();
+        let mut i = 0;
+        let mut more = false;
+        while i < self.records.len() {
+            if !self.purgeable(&self.records[i], now) {
+                i += 1;
+                continue;
+            }
+            if purged.len() == max_removals {
+                more = true;
+                break;
+            }
+            let rec = &self.records[i];
+            purged.push(Tombstone { id: rec.id, deleted_at: rec.updated_at });
+            self.swap_remove_record(i);
+        }
+        self.after_purge(purged);
+        more
+    }
+
+    fn swap_remove_record(&mut self, i: usize) {
+        self.unindex_record(i);
+        let last = self.records.len() - 1;
+        if i != last {
+            let moved = &self.records[last];
+            for tag in &moved.tags {
+                self.tag_index.relocate(tag, last, i);
+            }
+            if let Some(tenant) = &moved.tenant {
+                self.tenant_index.relocate(tenant, last, i);
+            }
+            self.fields.relocate(last, i, &moved.metadata);
+            if self.id_index.get(&moved.id) == Some(&last) {
+                self.id_index.insert(moved.id, i);
+            }
+            if let Some(key) = &moved.key {
+                if self.key_index.get(key) == Some(&last) {
+                    self.key_index.insert(key.clone(), i);
+                }
+            }
+            if let Some(index) = &mut self.index {
+                index.relocate(last, i);
+            }
+            if let Some(ivf) = &mut self.ivf {
+                ivf.relocate(last, i);
+            }
+            if let Some(pq) = &mut self.pq {
+                pq.relocate(last, i);
+            }
+            if let Some(sq) = &mut self.sq {
+                sq.relocate(last, i);
+            }
+        }
+        self.records.swap_remove(i);
+        if let Some(build) = &mut self.index_build {
+            build.swap_removed(i, last, &self.records, self.metric);
+        }
+    }
+
+    // Compact automatically after removals once the deleted fraction of
+    // records exceeds `threshold` (e.g. 0.3). `None` disables it.
+    pub fn set_auto_compact(&mut self, threshold: Option<f32>) {
+        self.auto_compact = threshold;
+        self.maybe_compact();
+    }
+
+    fn maybe_compact(&mut self) {
+        let Some(threshold) = self.auto_compact else {
+            return;
+        };
+        if self.records.is_empty() {
+            return;
+        }
+        let deleted = self.records.iter().filter(|r| r.deleted).count();
+        if deleted as f32 / self.records.len() as f32 > threshold {
+            self.compact();
+        }
+    }
+
+    // False if there is no such live record, or the change couldn't be
+    // written to the store's write-ahead log
+    pub fn add_tag(&mut self, id: &[u8; 16], tag: &str) -> bool {
+        let Some(i) = self.position(id) else {
+            return false;
+        };
+        if self.records[i].tags.contains(tag) {
+            return true;
+        }
+        let now = self.clock.now_ms();
+        let changed = self.change_record(i, |r| {
+            r.tags.insert(tag.to_string());
+            r.updated_at = now;
+        });
+        if changed.is_err() {
+            return false;
+        }
+        self.mark_changed();
+        self.tag_index.add(tag, i);
+        true
+    }
+
+    // False if the live record doesn't carry the tag, or the change couldn't
+    // be written to the store's write-ahead log
+    pub fn remove_tag(&mut self, id: &[u8; 16], tag: &str) -> bool {
+        let Some(i) = self.position(id) else {
+            return false;
+        };
+        if !self.records[i].tags.contains(tag) {
+            return false;
+        }
+        let now = self.clock.now_ms();
+        let changed = self.change_record(i, |r| {
+            r.tags.remove(tag);
+            r.updated_at = now;
+        });
+        if changed.is_err() {
+            return false;
+        }
+        self.mark_changed();
+        self.tag_index.remove(tag, i);
+        true
+    }
+
+    // Marks every live record carrying `tag` as deleted; returns how many were removed.
+    pub fn remove_by_tag(&mut self, tag: &str) -> usize {
+        let now = self.clock.now_ms();
+        let positions = self.tag_index.get(tag).to_vec();
+        let removed = self.remove_positions(&positions, now);
+        if removed > 0 {
+            self.maybe_compact();
+        }
+        removed
+    }
+
+    // Deletes the live records among `positions`, logging them first, and
+    // returns how many; none if they couldn't be logged
+    pub(crate) fn remove_positions(&mut self, positions: &[usize], now: u64) -> usize {
+        let positions: Vec<usize> = positions.iter().copied().filter(|&i| !self.records[i].deleted).collect();
+        let ids: Vec<[u8; 16]> = positions.iter().map(|&i| self.records[i].id).collect();
+        if positions.is_empty() || self.log_removed(&ids, now).is_err() {
+            return 0;
+        }
+        for &i in &positions {
+            self.records[i].tombstone(now);
+        }
+        self.mark_changed();
+        positions.len()
+    }
+
+    // All tags in use by live records, with the number of records carrying each.
+    pub fn tags(&self) -> Vec<(&str, usize)> {
+        self.live_counts(&self.tag_index)
+    }
+
+    fn live_counts<'a>(&'a self, index: &'a Postings) -> Vec<(&'a str, usize)> {
+        let mut counts: Vec<(&str, usize)> = index
+            .iter()
+            .map(|(key, positions)| {
+                let live = positions.iter().filter(|&&i| !self.records[i].deleted).count();
+                (key, live)
+            })
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        counts.sort_unstable();
+        counts
     }
 
     pub fn query(&self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
         if query_embedding.len() as u32 != self.dim {
             return Err(YAVSError::DimMismatch);
         }
-        // Collect (id, dist) pairs
-        let mut dists: Vec<([u8; 16], f32)> = self.records
-            .iter()
-            .filter(|r| !r.deleted)
-            .map(|r| {
-                let dist = euclidean(&r.embedding, query_embedding);
-                (r.id, dist)
+        let started = self.query_started();
+        if let Some(index) = &self.index {
+            let candidates = index.search(query_embedding, k, &self.records, self.metric);
+            let candidates = candidates
+                .into_iter()
+                .map(|i| &self.records[i])
+                .filter(|r| !r.deleted);
+            return Ok(self.rank(started, Scope::All, self.metric, candidates, query_embedding, k));
+        }
+        let _permit = self.heavy_query_permit()?;
+        Ok(self.rank_all(started, self.metric, query_embedding, k))
+    }
+
+    // Like `query`, with each hit's metadata alongside, so results can be
+    // rendered without looking records up again
+    pub fn query_with_metadata(&self, query_embedding: &[f32], k: usize) -> Result<Vec<QueryResult<'_>>, YAVSError> {
+        let results = self.query(query_embedding, k)?;
+        Ok(results
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let metadata = &self.find(&id)?.metadata;
+                Some(QueryResult { id, score, metadata })
             })
-            .collect();
+            .collect())
+    }
+
+    // Records most like a live one ("more like this"), ranked against its
+    // stored embedding; the record itself is left out
+    pub fn query_by_id(&self, id: &[u8; 16], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        let embedding = self.decoded_embedding(id).ok_or(YAVSError::NotFound)?;
+        let mut results = self.query(&embedding, k.saturating_add(1))?;
+        results.retain(|(other, _)| other != id);
+        results.truncate(k);
+        Ok(results)
+    }
 
-        // Sort by ascending distance
-        dists.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-        dists.truncate(k);
+    // Like `query`, but scores with `metric` instead of the store's own metric
+    pub fn query_with_metric(&self, query_embedding: &[f32], k: usize, metric: Metric) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let _permit = self.heavy_query_permit()?;
+        Ok(self.rank_all(started, metric, query_embedding, k))
+    }
+
+    // Like `query`, but scores with a caller-supplied distance. Results are
+    // never cached, since arbitrary distances can't be compared for equality.
+    pub fn query_with<D: Distance + ?Sized>(&self, query_embedding: &[f32], k: usize, distance: &D) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let _permit = self.heavy_query_permit()?;
+        let scanned = Cell::new(0);
+        let candidates = self.iter().inspect(|_| scanned.set(scanned.get() + 1));
+        let dists = self.scan(distance, candidates, query_embedding, k);
+        self.record_hits(&dists);
+        self.log_query(started, QueryRun {
+            scope: &Scope::All,
+            metric: None,
+            query_embedding,
+            k,
+            results: dists.len(),
+            scanned: scanned.get(),
+        });
         Ok(dists)
     }
 
-    pub fn dimension(&self) -> u32 {
-        self.dim
+    // Like `query`, but only considers records carrying every tag in `tags`.
+    pub fn query_tagged(&self, query_embedding: &[f32], k: usize, tags: &[&str]) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        if tags.is_empty() {
+            return self.query(query_embedding, k);
+        }
+
+        // Walk the shortest posting list and check the remaining tags per record
+        let mut postings: Vec<&[usize]> = tags.iter().map(|t| self.tag_index.get(t)).collect();
+        postings.sort_by_key(|p| p.len());
+
+        let candidates = postings[0]
+            .iter()
+            .map(|&i| &self.records[i])
+            .filter(|r| !r.deleted && tags.iter().all(|t| r.tags.contains(*t)));
+        Ok(self.rank(started, Scope::Tags(tags), self.metric, candidates, query_embedding, k))
     }
 
-    pub fn load_mem(buf: &[u8]) -> Result<Self, YAVSError> {
-        let mut cursor = std::io::Cursor::new(buf);
+    fn rank<'a, I>(&self, started: Option<u64>, scope: Scope<'_>, metric: Metric, candidates: I, query_embedding: &[f32], k: usize) -> Vec<([u8; 16], f32)>
+    where
+        I: Iterator<Item = &'a Record>,
+    {
+        let (dists, scanned) = self.rank_counted(&scope, metric, candidates, query_embedding, k);
+        self.log_query(started, QueryRun {
+            scope: &scope,
+            metric: Some(metric),
+            query_embedding,
+            k,
+            results: dists.len(),
+            scanned,
+        });
+        dists
+    }
+
+    // `rank` without the slow-query log, also returning how many candidates
+    // were scored
+    fn rank_counted<'a, I>(&self, scope: &Scope<'_>, metric: Metric, candidates: I, query_embedding: &[f32], k: usize) -> (Vec<([u8; 16], f32)>, usize)
+    where
+        I: Iterator<Item = &'a Record>,
+    {
+        self.cached(scope, metric, query_embedding, k, || {
+            let scanned = Cell::new(0);
+            let candidates = candidates.inspect(|_| scanned.set(scanned.get() + 1));
+            let dists = self.scan_metric(metric, candidates, query_embedding, k);
+            (dists, scanned.get())
+        })
+    }
 
-        let mut magic_buf = [0u8; 4];
-        cursor.read_exact(&mut magic_buf)?;
-        if magic_buf != MAGIC {
-            return Err(YAVSError::InvalidFile);
+    // Results from the query cache if present, otherwise from `scan` (which
+    // also returns its scan count; 0 is reported for cache hits)
+    fn cached<F>(&self, scope: &Scope<'_>, metric: Metric, query_embedding: &[f32], k: usize, scan: F) -> (Vec<([u8; 16], f32)>, usize)
+    where
+        F: FnOnce() -> (Vec<([u8; 16], f32)>, usize),
+    {
+        let cached = self.cache.as_ref().map(|cache| {
+            let key = cache.key(scope, metric, query_embedding, k);
+            (cache.get(&key), key)
+        });
+        let (dists, scanned) = match cached {
+            Some((Some(hit), _)) => (hit, 0),
+            Some((None, key)) => {
+                let (dists, scanned) = scan();
+                if let Some(cache) = &self.cache {
+                    cache.put(key, dists.clone());
+                }
+                (dists, scanned)
+            }
+            None => scan(),
+        };
+        self.record_hits(&dists);
+        (dists, scanned)
+    }
+
+    // Exact search over every live record. With the `rayon` feature, large
+    // stores are split into chunks ranked on separate threads (see
+    // `set_query_threads`).
+    fn rank_all(&self, started: Option<u64>, metric: Metric, query_embedding: &[f32], k: usize) -> Vec<([u8; 16], f32)> {
+        #[cfg(feature = "rayon")]
+        if self.query_threads != 1 && self.records.len() > MIN_PARALLEL_CHUNK {
+            let (dists, scanned) = self.cached(&Scope::All, metric, query_embedding, k, || self.scan_parallel(metric, query_embedding, k));
+            self.log_query(started, QueryRun {
+                scope: &Scope::All,
+                metric: Some(metric),
+                query_embedding,
+                k,
+                results: dists.len(),
+                scanned,
+            });
+            return dists;
         }
+        self.rank(started, Scope::All, metric, self.iter(), query_embedding, k)
+    }
 
-        let mut version_buf = [0u8; 4];
-        cursor.read_exact(&mut version_buf)?;
-        let version = u32::from_le_bytes(version_buf);
-        if version != VERSION {
-            return Err(YAVSError::VersionMismatch);
+    // Per-chunk top k, merged. Ties and NaNs are ordered as in `scan`, so the
+    // results are the same as a sequential scan's.
+    #[cfg(feature = "rayon")]
+    fn scan_parallel(&self, metric: Metric, query_embedding: &[f32], k: usize) -> (Vec<([u8; 16], f32)>, usize) {
+        use rayon::prelude::*;
+        let threads = match self.query_threads {
+            0 => rayon::current_num_threads(),
+            n => n,
+        };
+        let chunk_len = self.records.len().div_ceil(threads).max(MIN_PARALLEL_CHUNK);
+        let code_score = self.code_scorer(metric, query_embedding);
+        let partials: Vec<_> = self.records
+            .par_chunks(chunk_len)
+            .map(|chunk| {
+                let mut top = TopK::new(&metric, k);
+                let mut scanned = 0;
+                for r in chunk.iter().filter(|r| !r.deleted) {
+                    let score = match &code_score {
+                        Some(score) => score(&r.code),
+                        None => metric.score(&r.embedding, query_embedding),
+                    };
+                    top.push(r.id, score, ());
+                    scanned += 1;
+                }
+                (top, scanned)
+            })
+            .collect();
+        let mut top = TopK::new(&metric, k);
+        let mut scanned = 0;
+        for (partial, n) in partials {
+            top.extend(partial);
+            scanned += n;
         }
+        let dists = top.into_results();
+        (dists, scanned)
+    }
 
-        let mut n_records_buf = [0u8; 8];
-        cursor.read_exact(&mut n_records_buf)?;
-        let n_records = u64::from_le_bytes(n_records_buf);
+    fn record_hits(&self, dists: &[([u8; 16], f32)]) {
+        if let Some(access) = &self.access {
+            access.hit(dists.iter().map(|(id, _)| id), self.clock.now_ms());
+        }
+    }
+
+    fn scan<'a, D, I>(&self, distance: &D, candidates: I, query_embedding: &[f32], k: usize) -> Vec<([u8; 16], f32)>
+    where
+        D: Distance + ?Sized,
+        I: Iterator<Item = &'a Record>,
+    {
+        let mut top = TopK::new(distance, k);
+        for r in candidates {
+            top.push(r.id, distance.score(&self.vector(r), query_embedding), ());
+        }
+        top.into_results()
+    }
+
+    // `scan` under one of the built-in metrics, which score stored codes
+    // without decoding them
+    fn scan_metric<'a, I>(&self, metric: Metric, candidates: I, query_embedding: &[f32], k: usize) -> Vec<([u8; 16], f32)>
+    where
+        I: Iterator<Item = &'a Record>,
+    {
+        let Some(score) = self.code_scorer(metric, query_embedding) else {
+            return self.scan(&metric, candidates, query_embedding, k);
+        };
+        let mut top = TopK::new(&metric, k);
+        for r in candidates {
+            top.push(r.id, score(&r.code), ());
+        }
+        top.into_results()
+    }
 
-        let mut dim_buf = [0u8; 4];
-        cursor.read_exact(&mut dim_buf)?;
-        let dim = u32::from_le_bytes(dim_buf);
+    // Full symmetric distance matrix between the given records, in the order
+    // the ids were passed. Rows are computed in parallel with the `rayon` feature.
+    pub fn pairwise_distances(&self, ids: &[[u8; 16]]) -> Result<Vec<Vec<f32>>, YAVSError> {
+        let embeddings = ids
+            .iter()
+            .map(|id| self.find(id).map(|r| self.vector(r)).ok_or(YAVSError::NotFound))
+            .collect::<Result<Vec<Cow<[f32]>>, YAVSError>>()?;
 
-        // skip reserved
-        let mut reserved = vec![0u8; RESERVED_SIZE];
-        cursor.read_exact(&mut reserved)?;
+        let row = |a: &Cow<[f32]>| -> Vec<f32> {
+            embeddings.iter().map(|b| self.metric.distance(a, b)).collect()
+        };
 
-        let mut records = Vec::with_capacity(n_records as usize);
+        #[cfg(feature = "rayon")]
+        let matrix = {
+            use rayon::prelude::*;
+            embeddings.par_iter().map(row).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let matrix = embeddings.iter().map(row).collect();
 
-        for _ in 0..n_records {
-            let mut id = [0u8; 16];
-            cursor.read_exact(&mut id)?;
+        Ok(matrix)
+    }
 
-            let mut embedding = vec![0f32; dim as usize];
-            for i in 0..dim as usize {
-                let mut float_buf = [0u8; 4];
-                cursor.read_exact(&mut float_buf)?;
-                embedding[i] = f32::from_le_bytes(float_buf);
+    // Mean vector of all live records matching `filter`, or None if none match.
+    pub fn centroid<F: Fn(&Record) -> bool>(&self, filter: F) -> Option<Vec<f32>> {
+        let mut sum = vec![0f32; self.dim as usize];
+        let mut count = 0usize;
+        for rec in self.records.iter().filter(|r| !r.deleted && filter(r)) {
+            for (s, &v) in sum.iter_mut().zip(self.vector(rec).iter()) {
+                *s += v;
             }
+            count += 1;
+        }
+        if count == 0 {
+            return None;
+        }
+        for s in sum.iter_mut() {
+            *s /= count as f32;
+        }
+        Some(sum)
+    }
 
-            let mut meta_len_buf = [0u8; 4];
-            cursor.read_exact(&mut meta_len_buf)?;
-            let meta_len = u32::from_le_bytes(meta_len_buf) as usize;
+    // The matching record with the smallest total distance to every other
+    // matching record. This is O(n^2) in the size of the subset.
+    pub fn medoid<F: Fn(&Record) -> bool>(&self, filter: F) -> Option<[u8; 16]> {
+        let subset: Vec<&Record> = self.records
+            .iter()
+            .filter(|r| !r.deleted && filter(r))
+            .collect();
+        let vectors: Vec<Cow<[f32]>> = subset.iter().map(|r| self.vector(r)).collect();
 
-            let mut metadata = vec![0u8; meta_len];
-            cursor.read_exact(&mut metadata)?;
+        let total = |a: &Cow<[f32]>| -> f32 {
+            vectors.iter().map(|b| self.metric.distance(a, b)).sum()
+        };
 
-            records.push(Record {
-                id,
-                embedding,
-                metadata,
-                deleted: false,
-            });
+        #[cfg(feature = "rayon")]
+        let totals: Vec<f32> = {
+            use rayon::prelude::*;
+            vectors.par_iter().map(total).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let totals: Vec<f32> = vectors.iter().map(total).collect();
+
+        subset
+            .iter()
+            .zip(totals)
+            .min_by(|a, b| compare_scores(&self.metric, a.1, b.1))
+            .map(|(r, _)| r.id)
+    }
+
+    // The record with this ID: the live one, else its latest tombstone
+    pub fn get(&self, id: &[u8; 16]) -> Option<&Record> {
+        self.find(id).or_else(|| self.records.iter().rev().find(|r| &r.id == id))
+    }
+
+    // Borrows a live record's contents; None for unknown or deleted IDs
+    pub fn view(&self, id: &[u8; 16]) -> Option<RecordView<'_>> {
+        self.find(id).map(RecordView::from)
+    }
+
+    // Borrows a live record's embedding; None under Pq and Int8 precision, where
+    // records keep only codes (see `decoded_embedding`)
+    pub fn embedding_of(&self, id: &[u8; 16]) -> Option<&[f32]> {
+        self.find(id).filter(|r| r.code.is_empty()).map(|r| r.embedding.as_slice())
+    }
+
+    // `embedding_of` for each of `ids`, in order
+    pub fn embeddings_for(&self, ids: &[[u8; 16]]) -> Vec<Option<&[f32]>> {
+        ids.iter().map(|id| self.embedding_of(id)).collect()
+    }
+
+    fn find(&self, id: &[u8; 16]) -> Option<&Record> {
+        self.position(id).map(|i| &self.records[i])
+    }
+
+    fn position(&self, id: &[u8; 16]) -> Option<usize> {
+        let &i = self.id_index.get(id)?;
+        let rec = &self.records[i];
+        (!rec.deleted && &rec.id == id).then_some(i)
+    }
+
+    pub fn dimension(&self) -> u32 {
+        self.dim
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    // Number of live (non-deleted) records
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Live records in storage order
+    pub fn iter(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter().filter(|r| !r.deleted)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = [u8; 16]> + '_ {
+        self.iter().map(|r| r.id)
+    }
+
+    pub fn health(&self) -> HealthReport {
+        health::check(self)
+    }
+
+    // Reads every live embedding and the index graph once, so a service can
+    // fault pages in at boot rather than on its first query. Returns the
+    // number of bytes touched.
+    pub fn warm_up(&self) -> u64 {
+        let mut touched = 0;
+        let mut sum = 0f32;
+        for rec in self.iter() {
+            sum += rec.embedding.iter().sum::<f32>();
+            sum += rec.code.iter().map(|&c| c as f32).sum::<f32>();
+            touched += 4 * rec.embedding.len() as u64 + rec.code.len() as u64;
         }
+        std::hint::black_box(sum);
+        if let Some(index) = &self.index {
+            touched += index.warm_up();
+        }
+        touched
+    }
 
-        Ok(YAVS { dim, records })
+    // Exact size in bytes of the store as written by `save`/`save_mem`
+    pub fn estimated_file_size(&self) -> u64 {
+        format::SizeTally::of(self).total()
     }
 
-    pub fn save_mem(&self) -> Result<Vec<u8>, YAVSError> {
-        let mut out = Vec::new();
-        // Header
-        out.write_all(MAGIC)?;
-        out.write_all(&VERSION.to_le_bytes())?;
-        out.write_all(&(self.records.len() as u64).to_le_bytes())?;
-        out.write_all(&self.dim.to_le_bytes())?;
-        out.write_all(&[0u8; RESERVED_SIZE])?;
-
-        // Records
-        for rec in &self.records {
-            out.write_all(&rec.id)?;
-            for &val in &rec.embedding {
-                out.write_all(&val.to_le_bytes())?;
+    // Deletes the oldest live records (in storage order) until the serialized
+    // store fits in `max_bytes`, purging them straight away rather than
+    // keeping tombstones. Existing tombstones count towards the size; see
+    // `vacuum`. Returns the number of records removed.
+    pub fn trim_to_size(&mut self, max_bytes: u64) -> usize {
+        let now = self.clock.now_ms();
+        let mut size = format::SizeTally::of(self);
+        let mut trimmed = Vec::new();
+
+        for i in 0..self.records.len() {
+            if size.total() <= max_bytes {
+                break;
+            }
+            if self.records[i].deleted || self.records[i].pinned {
+                continue;
             }
-            out.write_all(&(rec.metadata.len() as u32).to_le_bytes())?;
-            out.write_all(&rec.metadata)?;
+            if let Some(index) = &self.index {
+                size.sub_index_node(index.node_size(i, &self.records));
+            }
+            trimmed.push(i);
+            size.sub(&self.records[i]);
+        }
+        let removed = self.remove_positions(&trimmed, now);
+        if removed > 0 {
+            let mut purge = vec![false; self.records.len()];
+            for i in trimmed {
+                purge[i] = true;
+            }
+            self.purge(&purge);
+            self.maybe_compact();
         }
+        removed
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, YAVSError> {
+        format::read_store(reader)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), YAVSError> {
+        format::write_store(self, writer)
+    }
+
+    pub fn load_mem(buf: &[u8]) -> Result<Self, YAVSError> {
+        let mut cursor = std::io::Cursor::new(buf);
+        format::read_store(&mut cursor)
+    }
+
+    pub fn save_mem(&self) -> Result<Vec<u8>, YAVSError> {
+        let mut out = Vec::new();
+        format::write_store(self, &mut out)?;
         Ok(out)
     }
 }
 
+// Closest first under `distance`, NaN last, ties broken by ID so result order
+// never depends on insertion order or sort stability
+fn closest_first<D: Distance + ?Sized>(distance: &D, a: &([u8; 16], f32), b: &([u8; 16], f32)) -> Ordering {
+    compare_scores(distance, a.1, b.1).then_with(|| a.0.cmp(&b.0))
+}
+
 fn euclidean(a: &[f32], b: &[f32]) -> f32 {
-    a.iter().zip(b.iter())
-        .map(|(x, y)| (x - y) * (x - y))
-        .sum::<f32>()
-        .sqrt()
+    simd::squared_euclidean(a, b).sqrt()
+}
+
+// Writes to a temporary file next to `path`, syncs it and renames it over
+// `path`, so a crash leaves either the old file or the new one
+pub(crate) fn write_atomic<F>(path: &Path, backups: usize, write: F) -> Result<(), YAVSError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), YAVSError>,
+{
+    let tmp = sibling(path, ".tmp");
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    let written = write(&mut file)
+        .and_then(|_| Ok(file.flush()?))
+        .and_then(|_| Ok(file.get_ref().sync_all()?));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if backups > 0 && path.exists() {
+        rotate_backups(path, backups)?;
+    }
+    fs::rename(&tmp, path)?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// `path` with `suffix` appended to its file name
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Shifts `<path>.1..` up by one, dropping the oldest, and keeps the current
+// file as `<path>.1`. The current file stays in place until it is replaced.
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), YAVSError> {
+    let backup = |n: usize| sibling(path, &format!(".{}", n));
+    for n in (1..backups).rev() {
+        if backup(n).exists() {
+            fs::rename(backup(n), backup(n + 1))?;
+        }
+    }
+    let newest = backup(1);
+    if newest.exists() {
+        fs::remove_file(&newest)?;
+    }
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+// A path in a per-process temp directory with nothing left at it or its
+// siblings from an earlier run, for tests that need files
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("yavs-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    for suffix in ["", ".wal", ".tmp", ".1", ".2"] {
+        let _ = fs::remove_file(sibling(&path, suffix));
+    }
+    path
+}
+
+// `n` vectors with components uniform in [-1, 1)
+#[cfg(test)]
+pub(crate) fn test_vectors(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    (0..n)
+        .map(|_| (0..dim).map(|_| (ids::splitmix64(&mut state) >> 40) as f32 / (1u64 << 23) as f32 - 1.0).collect())
+        .collect()
+}
+
+// Share of `exact`'s IDs that `approx` also returned
+#[cfg(test)]
+pub(crate) fn test_recall(approx: &[([u8; 16], f32)], exact: &[([u8; 16], f32)]) -> f32 {
+    let found = exact.iter().filter(|(id, _)| approx.iter().any(|(other, _)| other == id)).count();
+    found as f32 / exact.len().max(1) as f32
 }
 
+mod access;
+mod append;
+mod archive;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod backup;
+mod binary;
+mod budget;
+mod cache;
+mod classify;
+mod clock;
+mod compression;
+pub mod conformance;
+mod deletions;
+mod diff;
+mod embedding;
+mod encryption;
+mod ephemeral;
+mod eval;
+mod fields;
+mod format;
+mod graph;
+mod group;
+mod health;
+mod highlight;
+mod hooks;
+mod ids;
+mod index;
+mod ingest;
+mod ivf;
+mod keys;
+mod kv;
+mod lang;
+mod merge;
+mod metric;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "nats")]
+mod nats;
+mod pins;
+mod postings;
+mod precision;
+mod privacy;
+mod provenance;
+mod pq;
+#[cfg(feature = "projection")]
+mod projection;
+mod repair;
+mod replica;
+mod rerank;
+mod rewrite;
+mod router;
+mod scoped;
+mod segments;
+mod sizes;
+mod simd;
+mod slowlog;
+mod sq;
+mod tenant;
+mod throttle;
+mod tolerance;
+mod topk;
+mod trash;
+mod validate;
+mod wal;
 mod wasm;
+pub use access::AccessStats;
+pub use archive::ArchiveOptions;
+pub use backup::{list_backups, BackupPolicy};
+pub use binary::{hamming, sign_bits, BinaryRecord, BinaryYAVS};
+pub use budget::BudgetedResults;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use deletions::{CompactionEntry, Tombstone};
+pub use diff::{RecordChange, StoreDiff};
+pub use embedding::{Embedding, Typed};
+pub use ephemeral::EphemeralYAVS;
+pub use eval::{EvalQuery, EvalRun, EvalScores};
+pub use fields::{FieldFilter, FieldKind};
+pub use graph::KnnGraph;
+pub use group::GroupCommit;
+pub use health::{HealthReport, HealthWarning};
+pub use highlight::{highlight, Highlighted};
+pub use hooks::InsertHook;
+pub use ids::{IdSource, RandomIds, SeededIds};
+pub use index::{HnswParams, IndexProgress};
+pub use ingest::{IngestOptions, IngestStats};
+pub use keys::MAX_KEY_LEN;
+pub use merge::{namespaced_id, MergeOptions, MergeStats};
+pub use metric::{Cosine, Distance, Dot, Euclidean, Metric};
+#[cfg(feature = "mmap")]
+pub use mmap::{HotSet, MmapYAVS};
+#[cfg(feature = "nats")]
+pub use nats::{NatsOptions, NatsStats};
+pub use precision::Precision;
+pub use privacy::{Obfuscation, Obfuscator};
+pub use provenance::{Provenance, StoreStats};
+#[cfg(feature = "projection")]
+pub use projection::ProjectedPoint;
+pub use repair::{repair_file, RepairReport};
+pub use replica::DualWrite;
+pub use rerank::{RerankCandidate, Reranker};
+pub use rewrite::{RewriteOptions, RewriteStats};
+pub use router::{RoutedHit, Router};
+pub use scoped::Scoped;
+pub use segments::SegmentedYAVS;
+pub use slowlog::{SlowQuery, SlowQueryHook};
+pub use tenant::{Tenant, TenantStats};
+pub use throttle::QueryOverflow;
+pub use tolerance::{embeddings_equal, records_equal};
+pub use trash::TrashPolicy;
+pub use validate::{FiniteValues, JsonFields, MaxNorm, OnInvalid, ValidateFn, Validator};
 pub use wasm::WasmYAVS;
@@ -0,0 +1,221 @@
+// Last-writer-wins merge of two copies of a store, for offline-first sync.
+//
+// Each record carries `updated_at` from its store's clock, bumped on insert,
+// tag changes and deletion. For every ID the newer version wins, deletions
+// included. Equal timestamps are broken by comparing record contents, so
+// merging a into b and b into a converge on the same result.
+//
+// Keys stay unique among live records the same way: when an incoming record
+// takes a key another live record holds, the newer of the two keeps it and
+// the other is deleted as of the winner's timestamp.
+//
+// Stores generated independently (shards, other devices' exports) can reuse
+// each other's IDs for unrelated records. Merging with a namespace maps every
+// incoming ID to `namespaced_id(namespace, id)`, so records from different
+// sources never collide, and repeated merges of the same source still line up.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use uuid::Builder;
+use crate::ids::splitmix64;
+use crate::{Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    // Rewrite incoming IDs with `namespaced_id`
+    pub namespace: Option<String>,
+    // Tag added to every incoming record, e.g. the name of its shard
+    pub source_tag: Option<String>,
+}
+
+// Deterministic UUIDv8 for `id` within `namespace`
+pub fn namespaced_id(namespace: &str, id: &[u8; 16]) -> [u8; 16] {
+    let mut state = namespace.len() as u64;
+    for chunk in namespace.as_bytes().chunks(8).chain(id.chunks(8)) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        state = splitmix64(&mut (state ^ u64::from_le_bytes(word)));
+    }
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+    bytes[8..].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+    *Builder::from_custom_bytes(bytes).into_uuid().as_bytes()
+}
+
+impl YAVS {
+    pub fn merge(&mut self, other: &YAVS) -> Result<MergeStats, YAVSError> {
+        self.merge_with(other, &MergeOptions::default())
+    }
+
+    pub fn merge_with(&mut self, other: &YAVS, options: &MergeOptions) -> Result<MergeStats, YAVSError> {
+        if other.dim != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        if other.metric != self.metric {
+            return Err(YAVSError::MetricMismatch);
+        }
+
+        // Prefer live records over tombstones that share an ID
+        let mut positions: HashMap<[u8; 16], usize> = HashMap::new();
+        for (i, rec) in self.records.iter().enumerate() {
+            if !rec.deleted || !positions.contains_key(&rec.id) {
+                positions.insert(rec.id, i);
+            }
+        }
+
+        let mut stats = MergeStats::default();
+        for theirs in &other.records {
+            let mut theirs = incoming(other.expanded(theirs), options);
+            self.fit_precision(&mut theirs);
+            let existing = positions.get(&theirs.id).copied();
+            if existing.is_some_and(|i| newer(&theirs, &self.records[i]) != Ordering::Greater) {
+                stats.unchanged += 1;
+                continue;
+            }
+            self.settle_key(&mut theirs);
+            match existing {
+                Some(i) => {
+                    self.replace_record(i, theirs);
+                    stats.updated += 1;
+                }
+                None => {
+                    positions.insert(theirs.id, self.records.len());
+                    self.records.push(theirs);
+                    self.index_record(self.records.len() - 1);
+                    stats.inserted += 1;
+                }
+            }
+        }
+        if stats.inserted + stats.updated > 0 {
+            self.mark_changed();
+        }
+        Ok(stats)
+    }
+
+    // Deletes whichever of `theirs` and the live record holding its key is
+    // older, ties broken by ID
+    fn settle_key(&mut self, theirs: &mut Record) {
+        if theirs.deleted {
+            return;
+        }
+        let Some(j) = theirs.key.as_deref().and_then(|key| self.key_position(key)) else {
+            return;
+        };
+        let ours = &self.records[j];
+        if ours.id == theirs.id {
+            return;
+        }
+        if newer(theirs, ours).then_with(|| theirs.id.cmp(&ours.id)) == Ordering::Greater {
+            let at = theirs.updated_at;
+            self.records[j].tombstone(at);
+        } else {
+            theirs.tombstone(ours.updated_at);
+        }
+    }
+}
+
+fn incoming(mut rec: Record, options: &MergeOptions) -> Record {
+    if let Some(namespace) = &options.namespace {
+        rec.id = namespaced_id(namespace, &rec.id);
+    }
+    if let Some(tag) = &options.source_tag {
+        rec.tags.insert(tag.clone());
+    }
+    rec
+}
+
+// Total order on versions of the same record: timestamp first, then content
+fn newer(a: &Record, b: &Record) -> Ordering {
+    a.updated_at
+        .cmp(&b.updated_at)
+        .then(a.deleted.cmp(&b.deleted))
+        .then_with(|| {
+            let a_bits = a.embedding.iter().map(|v| v.to_bits());
+            let b_bits = b.embedding.iter().map(|v| v.to_bits());
+            a_bits.cmp(b_bits)
+        })
+        .then_with(|| a.code.cmp(&b.code))
+        .then_with(|| a.metadata.cmp(&b.metadata))
+        .then_with(|| a.tags.cmp(&b.tags))
+        .then_with(|| a.tenant.cmp(&b.tenant))
+        .then_with(|| a.key.cmp(&b.key))
+        .then_with(|| a.provenance.cmp(&b.provenance))
+        .then(a.pinned.cmp(&b.pinned))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{ManualClock, SeededIds, YAVS};
+
+    fn store(clock: &Arc<ManualClock>) -> YAVS {
+        let mut db = YAVS::new(2);
+        db.set_clock(clock.clone());
+        db
+    }
+
+    #[test]
+    fn newer_versions_win_deletions_included() {
+        let clock = Arc::new(ManualClock::new(100));
+        let mut ours = store(&clock);
+        let mut theirs = store(&clock);
+        let (a, b, c) = ([1; 16], [2; 16], [3; 16]);
+        for db in [&mut ours, &mut theirs] {
+            db.insert_with_id(&a, &[1.0, 0.0], b"a").unwrap();
+            db.insert_with_id(&b, &[0.0, 1.0], b"b").unwrap();
+        }
+
+        // a: ours at 200, theirs at 300; b: theirs at 300, deleted by ours at 400
+        clock.set(200);
+        ours.update(&a, &[1.0, 0.0], b"a ours").unwrap();
+        clock.set(300);
+        theirs.update(&a, &[1.0, 0.0], b"a theirs").unwrap();
+        theirs.update(&b, &[0.0, 1.0], b"b theirs").unwrap();
+        clock.set(400);
+        assert!(ours.remove(&b));
+        theirs.insert_with_id(&c, &[1.0, 1.0], b"c").unwrap();
+
+        let stats = ours.merge(&theirs).unwrap();
+        assert_eq!((stats.inserted, stats.updated, stats.unchanged), (1, 1, 1));
+        assert_eq!(ours.get(&a).unwrap().metadata, b"a theirs");
+        assert!(ours.get(&b).unwrap().deleted);
+        assert_eq!(ours.get(&c).unwrap().metadata, b"c");
+
+        // Merging back converges, and merging again changes nothing
+        theirs.merge(&ours).unwrap();
+        let again = theirs.merge(&ours).unwrap();
+        assert_eq!(again.inserted + again.updated, 0);
+        assert!(YAVS::diff(&ours, &theirs).unwrap().is_empty());
+    }
+
+    #[test]
+    fn equal_timestamps_converge() {
+        let clock = Arc::new(ManualClock::new(100));
+        let replica = |metadata: &[u8], seed| {
+            let mut db = store(&clock);
+            db.set_id_source(SeededIds::new(seed));
+            db.insert_with_id(&[1; 16], &[1.0, 0.0], metadata).unwrap();
+            // The same key on different IDs
+            db.insert_keyed(b"k", &[0.0, 1.0], metadata).unwrap();
+            db
+        };
+        let (ours, theirs) = (replica(b"ours", 1), replica(b"theirs", 2));
+
+        let mut left = replica(b"ours", 1);
+        left.merge(&theirs).unwrap();
+        let mut right = replica(b"theirs", 2);
+        right.merge(&ours).unwrap();
+        assert_eq!(left.get(&[1; 16]).unwrap().metadata, right.get(&[1; 16]).unwrap().metadata);
+        // One of them keeps the key and the other is deleted
+        assert_eq!(left.len(), 2);
+        assert_eq!(left.id_for_key(b"k"), right.id_for_key(b"k"));
+        assert!(YAVS::diff(&left, &right).unwrap().is_empty());
+    }
+}
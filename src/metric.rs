@@ -0,0 +1,115 @@
+// Distance functions used for ranking. Applications can plug their own in
+// through `Distance` and `YAVS::query_with`; the built-in ones are selected
+// per store with `Metric`, which is persisted in the file header.
+
+use std::cmp::Ordering;
+use crate::{euclidean, simd};
+
+pub trait Distance: Send + Sync {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32;
+
+    // Whether higher scores rank first (similarities) rather than lower ones
+    // (distances)
+    fn larger_is_closer(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+impl Distance for Euclidean {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        euclidean(a, b)
+    }
+}
+
+// 1 - cosine similarity, in [0, 2]; zero vectors are at distance 1 from everything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cosine;
+
+impl Distance for Cosine {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        let (dot, norm_a, norm_b) = simd::cosine_terms(a, b);
+        let norms = (norm_a * norm_b).sqrt();
+        if norms == 0.0 {
+            1.0
+        } else {
+            1.0 - dot / norms
+        }
+    }
+}
+
+// Inner product, for maximum inner product search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dot;
+
+impl Distance for Dot {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        simd::dot(a, b)
+    }
+
+    fn larger_is_closer(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Metric {
+    #[default]
+    Euclidean,
+    Cosine,
+    Dot,
+}
+
+impl Metric {
+    pub fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        self.score(a, b)
+    }
+
+    // Stored in the first reserved header word; 0 keeps older files Euclidean
+    pub(crate) fn code(self) -> u32 {
+        match self {
+            Metric::Euclidean => 0,
+            Metric::Cosine => 1,
+            Metric::Dot => 2,
+        }
+    }
+
+    pub(crate) fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Metric::Euclidean),
+            1 => Some(Metric::Cosine),
+            2 => Some(Metric::Dot),
+            _ => None,
+        }
+    }
+}
+
+impl Distance for Metric {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Euclidean => Euclidean.score(a, b),
+            Metric::Cosine => Cosine.score(a, b),
+            Metric::Dot => Dot.score(a, b),
+        }
+    }
+
+    fn larger_is_closer(&self) -> bool {
+        matches!(self, Metric::Dot)
+    }
+}
+
+// Orders scores closest first under `distance`, with NaN last
+pub(crate) fn compare_scores<D: Distance + ?Sized>(distance: &D, a: f32, b: f32) -> Ordering {
+    compare_ordered(distance.larger_is_closer(), a, b)
+}
+
+// `compare_scores` for a distance's `larger_is_closer`
+pub(crate) fn compare_ordered(larger_is_closer: bool, a: f32, b: f32) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) if larger_is_closer => b.total_cmp(&a),
+        (false, false) => a.total_cmp(&b),
+        (a_nan, b_nan) => a_nan.cmp(&b_nan),
+    }
+}
@@ -0,0 +1,300 @@
+// Read-only, memory-mapped view of a saved store. Opening only walks the
+// record block to note where each record starts; embeddings and metadata
+// stay in the mapping and are decoded as queries touch them, so large files
+// open quickly and don't need to fit in memory.
+//
+// Only the record block is used: tags, tenants and saved indexes are not
+// loaded. Queries scan the mapping exactly, unless a hot set keeps compressed
+// (int8 or PQ) codes of every record in memory: then the codes are scanned
+// and only the best candidates are re-ranked from the mapping. The file must
+// not be modified while it is mapped. Files saved at Pq or Int8 precision are
+// refused, having no embeddings to map.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::path::Path;
+use memmap2::Mmap;
+use crate::compression::decompress;
+use crate::format::{read_encoding, read_metric, MAGIC, RESERVED_SIZE, VERSION};
+use crate::pq::ProductQuantizer;
+use crate::precision::from_f16;
+use crate::sq::ScalarQuantizer;
+use crate::topk::TopK;
+use crate::wal;
+use crate::{Distance, Metric, Precision, YAVS, YAVSError};
+
+// What `MmapYAVS` keeps in memory to scan instead of the mapping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HotSet {
+    // Nothing; queries read every embedding from the mapping
+    #[default]
+    Off,
+    // One byte per dimension per record
+    Int8 { rerank: usize },
+    // One byte per subspace per record
+    Pq { subspaces: usize, rerank: usize },
+}
+
+#[derive(Debug)]
+enum Codes {
+    Int8(ScalarQuantizer),
+    Pq(ProductQuantizer),
+}
+
+#[derive(Debug)]
+pub struct MmapYAVS {
+    map: Mmap,
+    dim: u32,
+    metric: Metric,
+    precision: Precision,
+    compressed: bool,
+    // Byte offset of each record in the mapping
+    offsets: Vec<usize>,
+    ids: Vec<[u8; 16]>,
+    hot_set: HotSet,
+    codes: Option<Codes>,
+}
+
+impl YAVS {
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<MmapYAVS, YAVSError> {
+        wal::check_no_pending_log(path.as_ref())?;
+        let file = File::open(path.as_ref())?;
+        // SAFETY: the mapping is read-only, and callers are told not to
+        // modify the file while it is open
+        let map = unsafe { Mmap::map(&file)? };
+        MmapYAVS::from_map(map)
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], YAVSError> {
+    if bytes.len() < n {
+        return Err(YAVSError::InvalidFile);
+    }
+    let (head, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl MmapYAVS {
+    fn from_map(map: Mmap) -> Result<Self, YAVSError> {
+        let mut rest: &[u8] = &map;
+        if take(&mut rest, 4)? != MAGIC {
+            return Err(YAVSError::InvalidFile);
+        }
+        let version = le_u32(take(&mut rest, 4)?);
+        if version == 0 || version > VERSION {
+            return Err(YAVSError::VersionMismatch);
+        }
+        let n_records = u64::from_le_bytes(take(&mut rest, 8)?.try_into().unwrap_or_default());
+        let dim = le_u32(take(&mut rest, 4)?);
+        let mut reserved = [0u8; RESERVED_SIZE];
+        reserved.copy_from_slice(take(&mut rest, RESERVED_SIZE)?);
+        let metric = read_metric(&reserved)?;
+        let enc = read_encoding(&reserved)?;
+        let precision = enc.precision;
+        if precision == Precision::F16 {
+            from_f16(0)?;
+        }
+        if precision.is_quantized() {
+            return Err(YAVSError::Quantized);
+        }
+
+        let embedding_len = dim as usize * precision.width() as usize;
+        // Each record takes at least its ID and metadata length
+        let min_record = 16 + embedding_len + 4;
+        if n_records > (rest.len() / min_record) as u64 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let mut offsets = Vec::with_capacity(n_records as usize);
+        let mut ids = Vec::with_capacity(n_records as usize);
+        for _ in 0..n_records {
+            offsets.push(map.len() - rest.len());
+            ids.push(take(&mut rest, 16)?.try_into().unwrap_or_default());
+            take(&mut rest, embedding_len)?;
+            let meta_len = le_u32(take(&mut rest, 4)?) as usize;
+            take(&mut rest, meta_len)?;
+        }
+        Ok(Self {
+            map,
+            dim,
+            metric,
+            precision,
+            compressed: enc.compressed,
+            offsets,
+            ids,
+            hot_set: HotSet::Off,
+            codes: None,
+        })
+    }
+
+    pub fn dimension(&self) -> u32 {
+        self.dim
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    fn id_at(&self, i: usize) -> [u8; 16] {
+        self.ids[i]
+    }
+
+    fn embedding_bytes(&self, i: usize) -> &[u8] {
+        let at = self.offsets[i] + 16;
+        &self.map[at..at + self.dim as usize * self.precision.width() as usize]
+    }
+
+    fn decode_into(&self, i: usize, out: &mut Vec<f32>) {
+        out.clear();
+        let bytes = self.embedding_bytes(i);
+        match self.precision {
+            Precision::F32 => out.extend(bytes.chunks_exact(4).map(le_u32).map(f32::from_bits)),
+            // The feature was checked on open
+            Precision::F16 => out.extend(
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| from_f16(u16::from_le_bytes([b[0], b[1]])).unwrap_or(f32::NAN)),
+            ),
+            // Refused on open
+            Precision::Pq | Precision::Int8 => {}
+        }
+    }
+
+    fn position(&self, id: &[u8; 16]) -> Option<usize> {
+        self.ids.iter().position(|i| i == id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = [u8; 16]> + '_ {
+        self.ids.iter().copied()
+    }
+
+    pub fn contains(&self, id: &[u8; 16]) -> bool {
+        self.position(id).is_some()
+    }
+
+    pub fn embedding(&self, id: &[u8; 16]) -> Option<Vec<f32>> {
+        self.position(id).map(|i| self.embedding_at(i))
+    }
+
+    // Borrowed straight from the mapping, unless the file compresses
+    // metadata. None also if compressed metadata fails to decompress.
+    pub fn metadata(&self, id: &[u8; 16]) -> Option<Cow<'_, [u8]>> {
+        let i = self.position(id)?;
+        let at = self.offsets[i] + 16 + self.embedding_bytes(i).len();
+        let len = le_u32(&self.map[at..at + 4]) as usize;
+        let stored = &self.map[at + 4..at + 4 + len];
+        if self.compressed {
+            decompress(stored).ok().map(Cow::Owned)
+        } else {
+            Some(Cow::Borrowed(stored))
+        }
+    }
+
+    // Builds (or drops, with `HotSet::Off`) the in-memory codes: int8 ranges
+    // are fitted in one pass over the mapping, PQ codebooks on a sample
+    pub fn set_hot_set(&mut self, hot_set: HotSet) {
+        let dim = self.dim as usize;
+        let mut buf = Vec::with_capacity(dim);
+        self.codes = match hot_set {
+            _ if self.is_empty() || dim == 0 => None,
+            HotSet::Off => None,
+            HotSet::Int8 { .. } => {
+                let mut sq = ScalarQuantizer::train((0..self.len()).map(|i| self.embedding_at(i)), dim);
+                for i in 0..self.len() {
+                    self.decode_into(i, &mut buf);
+                    sq.encode(i, &buf);
+                }
+                Some(Codes::Int8(sq))
+            }
+            HotSet::Pq { subspaces, .. } => {
+                let subspaces = subspaces.clamp(1, dim);
+                let mut pq = ProductQuantizer::train(self.len(), dim, subspaces, |i| self.embedding_at(i));
+                for i in 0..self.len() {
+                    self.decode_into(i, &mut buf);
+                    pq.encode(i, &buf);
+                }
+                Some(Codes::Pq(pq))
+            }
+        };
+        self.hot_set = if self.codes.is_some() { hot_set } else { HotSet::Off };
+    }
+
+    pub fn hot_set(&self) -> HotSet {
+        self.hot_set
+    }
+
+    fn embedding_at(&self, i: usize) -> Vec<f32> {
+        let mut out = Vec::with_capacity(self.dim as usize);
+        self.decode_into(i, &mut out);
+        out
+    }
+
+    // Ordered as by `YAVS::query`. Exact without a hot set; with one, the
+    // codes pick the best max(rerank, k) candidates, which are re-ranked
+    // exactly from the mapping (or returned with approximate distances if
+    // `rerank` is 0).
+    pub fn query(&self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let mut buf = Vec::with_capacity(self.dim as usize);
+        let Some(codes) = &self.codes else {
+            let mut top = TopK::new(&self.metric, k);
+            for i in 0..self.len() {
+                self.decode_into(i, &mut buf);
+                top.push(self.id_at(i), self.metric.score(&buf, query_embedding), ());
+            }
+            return Ok(top.into_results());
+        };
+
+        let rerank = match self.hot_set {
+            HotSet::Int8 { rerank } | HotSet::Pq { rerank, .. } => rerank,
+            HotSet::Off => 0,
+        };
+        let mut best = TopK::new(&self.metric, if rerank > 0 { rerank.max(k) } else { k });
+        match codes {
+            Codes::Int8(sq) => {
+                let score = sq.scorer(query_embedding, self.metric);
+                for i in 0..self.len() {
+                    best.push(self.id_at(i), score(sq.code(i)), i);
+                }
+            }
+            Codes::Pq(pq) => {
+                let score = pq.scorer(query_embedding, self.metric);
+                for i in 0..self.len() {
+                    best.push(self.id_at(i), score(pq.code(i)), i);
+                }
+            }
+        }
+        if rerank == 0 {
+            return Ok(best.into_results());
+        }
+        let mut top = TopK::new(&self.metric, k);
+        for candidate in best.into_sorted() {
+            self.decode_into(candidate.item, &mut buf);
+            top.push(candidate.id, self.metric.score(&buf, query_embedding), ());
+        }
+        Ok(top.into_results())
+    }
+
+    // Copies every record into an ordinary in-memory store
+    pub fn to_yavs(&self) -> Result<YAVS, YAVSError> {
+        YAVS::load_mem(&self.map)
+    }
+}
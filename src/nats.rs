@@ -0,0 +1,160 @@
+// Continuous ingestion from a NATS JetStream pull consumer, for keeping a store
+// in step with an embedding pipeline. Requires the `nats` feature and a tokio
+// runtime, as async-nats does.
+//
+// Each message is one event. Its payload is the embedding as `dim` little-
+// endian f32s followed by the metadata. A `Yavs-Id` header (a UUID) makes it an
+// upsert of that ID, and `Yavs-Op: delete` with an ID removes the record
+// instead (`Yavs-Op: upsert` is the default). Events that can't be applied
+// (bad payload, rejected by a hook or validator) are terminated so they
+// aren't redelivered.
+//
+// The consumer's ack floor is the checkpoint: a batch is acked only once it is
+// applied and durable, i.e. saved to `checkpoint` or logged to the store's
+// write-ahead log, so after a crash the stream redelivers from the last
+// durable batch. Events with an ID are idempotent on redelivery; plain inserts
+// are not.
+
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+use async_nats::jetstream::consumer::PullConsumer;
+use async_nats::jetstream::AckKind;
+use crate::{YAVS, YAVSError};
+
+const ID_HEADER: &str = "Yavs-Id";
+const OP_HEADER: &str = "Yavs-Op";
+
+#[derive(Debug, Clone)]
+pub struct NatsOptions {
+    pub batch_size: usize,
+    // How long a fetch waits for the batch to fill
+    pub max_wait: Duration,
+    // Saved to after every batch that changed the store, before acking it.
+    // Not needed for a store opened with `open_wal`.
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for NatsOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024,
+            max_wait: Duration::from_secs(1),
+            checkpoint: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NatsStats {
+    pub inserted: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub rejected: usize,
+    pub checkpoints: usize,
+    // Stream sequence of the last acked event, the consumer's offset
+    pub sequence: u64,
+    // Events left on the stream for this consumer as of the last one received
+    pub lag: u64,
+}
+
+impl YAVS {
+    // Fetches one batch from `consumer`, applies it and acks it, adding to
+    // `stats`. Returns how many events were received, 0 if none arrived within
+    // `max_wait`. Call it in a loop; the store can be queried between calls.
+    pub async fn ingest_nats(&mut self, consumer: &PullConsumer, options: &NatsOptions, stats: &mut NatsStats) -> Result<usize, YAVSError> {
+        let mut batch = consumer
+            .fetch()
+            .max_messages(options.batch_size.max(1))
+            .expires(options.max_wait)
+            .messages()
+            .await
+            .map_err(nats_error)?;
+        let mut received = Vec::new();
+        while let Some(message) = std::future::poll_fn(|cx| futures_core::Stream::poll_next(Pin::new(&mut batch), cx)).await {
+            let message = message.map_err(nats_error)?;
+            let applied = match self.apply_nats_event(&message, stats) {
+                Ok(()) => true,
+                Err(e @ (YAVSError::IoError(_) | YAVSError::WalFailed)) => return Err(e),
+                Err(_) => {
+                    stats.rejected += 1;
+                    false
+                }
+            };
+            if let Ok(info) = message.info() {
+                stats.sequence = stats.sequence.max(info.stream_sequence);
+                stats.lag = info.pending;
+            }
+            received.push((message, applied));
+        }
+
+        if let Some(path) = &options.checkpoint {
+            if self.is_dirty() {
+                self.save(path)?;
+                stats.checkpoints += 1;
+            }
+        }
+        // Under group commit the batch's writes may not be synced yet
+        self.commit()?;
+        for (message, applied) in &received {
+            let kind = match applied {
+                true => AckKind::Ack,
+                false => AckKind::Term,
+            };
+            message.ack_with(kind).await.map_err(nats_error)?;
+        }
+        Ok(received.len())
+    }
+
+    fn apply_nats_event(&mut self, message: &async_nats::jetstream::Message, stats: &mut NatsStats) -> Result<(), YAVSError> {
+        let header = |name| message.headers.as_ref().and_then(|h| h.get(name)).map(|v| v.as_str());
+        let id = match header(ID_HEADER) {
+            Some(id) => Some(
+                *uuid::Uuid::parse_str(id)
+                    .map_err(|_| YAVSError::InvalidRecord(format!("bad {ID_HEADER} header `{id}`")))?
+                    .as_bytes(),
+            ),
+            None => None,
+        };
+        match (header(OP_HEADER), id) {
+            (Some("delete"), Some(id)) => {
+                if self.position(&id).is_some() {
+                    // A live record that isn't removed wasn't logged
+                    if !self.remove(&id) {
+                        return Err(YAVSError::WalFailed);
+                    }
+                    stats.removed += 1;
+                }
+                return Ok(());
+            }
+            (Some("delete"), None) => return Err(YAVSError::InvalidRecord(format!("delete without a {ID_HEADER} header"))),
+            (Some(op), _) if op != "upsert" => return Err(YAVSError::InvalidRecord(format!("unknown {OP_HEADER} `{op}`"))),
+            _ => {}
+        }
+
+        let dim = self.dim as usize;
+        if message.payload.len() < dim * 4 {
+            return Err(YAVSError::DimMismatch);
+        }
+        let (embedding, metadata) = message.payload.split_at(dim * 4);
+        let embedding: Vec<f32> = embedding
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        match id {
+            Some(id) => match self.upsert(&id, &embedding, metadata)? {
+                true => stats.inserted += 1,
+                false => stats.updated += 1,
+            },
+            None => {
+                self.insert_owned(embedding, metadata.to_vec())?;
+                stats.inserted += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn nats_error(e: impl std::fmt::Display) -> YAVSError {
+    YAVSError::Nats(e.to_string())
+}
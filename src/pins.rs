@@ -0,0 +1,85 @@
+// Pinned records are exempt from automatic removal such as `trim_to_size`,
+// for canonical documents that must always be retrievable. They can still be
+// removed explicitly, but then stay in the trash, whatever the trash policy
+// and through `compact`, `compact_step` and `vacuum`, until restored or
+// unpinned; `pin` and `unpin` on a removed ID apply to the record `restore`
+// would bring back. Pins are persisted with the store, and changing one
+// counts as an update of the record for `updated_at` and merges.
+
+use crate::YAVS;
+
+impl YAVS {
+    pub fn pin(&mut self, id: &[u8; 16]) -> bool {
+        self.set_pinned(id, true)
+    }
+
+    pub fn unpin(&mut self, id: &[u8; 16]) -> bool {
+        self.set_pinned(id, false)
+    }
+
+    pub fn is_pinned(&self, id: &[u8; 16]) -> bool {
+        self.find(id).is_some_and(|r| r.pinned)
+    }
+
+    pub fn pinned(&self) -> impl Iterator<Item = [u8; 16]> + '_ {
+        self.iter().filter(|r| r.pinned).map(|r| r.id)
+    }
+
+    // Returns whether the record exists and the change, if any, could be
+    // written to the store's write-ahead log; only an actual change marks
+    // the store dirty
+    fn set_pinned(&mut self, id: &[u8; 16], pinned: bool) -> bool {
+        let Some(i) = self.position(id).or_else(|| self.latest_tombstone(id)) else {
+            return false;
+        };
+        if self.records[i].pinned != pinned {
+            let now = self.clock.now_ms();
+            let changed = self.change_record(i, |r| {
+                r.pinned = pinned;
+                r.updated_at = now;
+            });
+            if changed.is_err() {
+                return false;
+            }
+            self.mark_changed();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{ManualClock, YAVS};
+
+    #[test]
+    fn pinned_records_survive_every_purge() {
+        let clock = Arc::new(ManualClock::new(100));
+        let mut db = YAVS::new(2);
+        db.set_clock(clock.clone());
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        clock.set(200);
+        assert!(db.pin(&a));
+        assert_eq!(db.get(&a).unwrap().updated_at, 200);
+
+        assert!(db.remove(&a));
+        assert!(db.remove(&b));
+        db.compact();
+        while db.compact_step(1) {}
+        db.vacuum();
+        assert_eq!(db.list_deleted().map(|r| r.id).collect::<Vec<_>>(), vec![a]);
+        // Trimming only ever takes live records that aren't pinned
+        db.restore(&a).unwrap();
+        assert_eq!(db.trim_to_size(0), 0);
+        assert!(db.is_pinned(&a));
+
+        // Unpinned in the trash, it can go
+        assert!(db.remove(&a));
+        clock.set(300);
+        assert!(db.unpin(&a));
+        assert_eq!(db.get(&a).unwrap().updated_at, 300);
+        db.vacuum();
+        assert!(db.get(&a).is_none());
+    }
+}
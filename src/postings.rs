@@ -0,0 +1,49 @@
+// Inverted index from a string key (tag, tenant) to positions in `records`.
+// Positions shift on compaction, so owners rebuild or relocate entries then.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct Postings {
+    map: HashMap<String, Vec<usize>>,
+}
+
+impl Postings {
+    pub(crate) fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub(crate) fn add(&mut self, key: &str, pos: usize) {
+        match self.map.get_mut(key) {
+            Some(positions) => positions.push(pos),
+            None => {
+                self.map.insert(key.to_string(), vec![pos]);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str, pos: usize) {
+        if let Some(positions) = self.map.get_mut(key) {
+            positions.retain(|&p| p != pos);
+            if positions.is_empty() {
+                self.map.remove(key);
+            }
+        }
+    }
+
+    pub(crate) fn relocate(&mut self, key: &str, from: usize, to: usize) {
+        if let Some(positions) = self.map.get_mut(key) {
+            for p in positions.iter_mut().filter(|p| **p == from) {
+                *p = to;
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> &[usize] {
+        self.map.get(key).map(|p| p.as_slice()).unwrap_or(&[])
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &[usize])> {
+        self.map.iter().map(|(k, p)| (k.as_str(), p.as_slice()))
+    }
+}
@@ -0,0 +1,384 @@
+// Optional product quantization. Embeddings are split into subspaces, each
+// quantized to one of up to 256 learned centroids, so a record's code takes
+// one byte per subspace instead of four per dimension. `query_pq` ranks by
+// asymmetric distance (the exact query against quantized records) and can
+// re-rank the best candidates with the full embeddings.
+//
+// Codes are kept per record position alongside the store and follow records
+// through insert and compaction like the other indexes. Only the codebooks
+// are saved; codes are recomputed on load. Under `Precision::Pq` the records
+// hold their own codes instead, and those are what is saved.
+
+use std::ops::Range;
+use crate::cache::Scope;
+use crate::format::read_u32;
+use crate::ivf::{kmeans, nearest_centroid, sample_positions};
+use crate::slowlog::QueryRun;
+use crate::topk::TopK;
+use crate::{Metric, Precision, Record, YAVS, YAVSError};
+
+const TRAIN_SEED: u64 = 0x9e5eed;
+const MAX_CENTROIDS: usize = 256;
+// Training looks at no more than this many records
+const MAX_SAMPLES: usize = 64 * MAX_CENTROIDS;
+
+#[derive(Debug)]
+pub(crate) struct ProductQuantizer {
+    subspaces: Vec<Range<usize>>,
+    // Per subspace, the centroids over that subspace's dimensions
+    codebooks: Vec<Vec<Vec<f32>>>,
+    // One byte per subspace for each record position
+    codes: Vec<u8>,
+}
+
+impl ProductQuantizer {
+    // Splits `dim` into `n_subspaces` contiguous ranges, the first ones one
+    // dimension longer when it doesn't divide evenly
+    fn subspaces(dim: usize, n_subspaces: usize) -> Vec<Range<usize>> {
+        let (base, extra) = (dim / n_subspaces, dim % n_subspaces);
+        let mut start = 0;
+        (0..n_subspaces)
+            .map(|s| {
+                let len = base + usize::from(s < extra);
+                start += len;
+                start - len..start
+            })
+            .collect()
+    }
+
+    // Trains on a sample of `n` embeddings, the `i`th given by `embedding(i)`
+    pub(crate) fn train<E, F>(n: usize, dim: usize, n_subspaces: usize, embedding: F) -> Self
+    where
+        E: AsRef<[f32]>,
+        F: Fn(usize) -> E,
+    {
+        let mut rng = TRAIN_SEED;
+        let sample: Vec<E> = sample_positions(n, MAX_SAMPLES, &mut rng).into_iter().map(embedding).collect();
+        let sample: Vec<&[f32]> = sample.iter().map(AsRef::as_ref).collect();
+        let k = sample.len().min(MAX_CENTROIDS);
+        let subspaces = Self::subspaces(dim, n_subspaces);
+        let codebooks = subspaces
+            .iter()
+            .map(|range| {
+                let sub: Vec<&[f32]> = sample.iter().map(|e| &e[range.clone()]).collect();
+                kmeans(&sub, k, Metric::Euclidean, &mut rng)
+            })
+            .collect();
+        Self {
+            subspaces,
+            codebooks,
+            codes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.codes.clear();
+    }
+
+    pub(crate) fn encode(&mut self, pos: usize, embedding: &[f32]) {
+        let m = self.subspaces.len();
+        if self.codes.len() < (pos + 1) * m {
+            self.codes.resize((pos + 1) * m, 0);
+        }
+        let code = self.quantize(embedding);
+        self.codes[pos * m..(pos + 1) * m].copy_from_slice(&code);
+    }
+
+    // The code for `embedding`: its nearest centroid in each subspace
+    pub(crate) fn quantize(&self, embedding: &[f32]) -> Vec<u8> {
+        self.subspaces
+            .iter()
+            .zip(&self.codebooks)
+            .map(|(range, codebook)| nearest_centroid(codebook, &embedding[range.clone()], Metric::Euclidean) as u8)
+            .collect()
+    }
+
+    // Whether `code` has a centroid of these codebooks for every subspace
+    pub(crate) fn fits(&self, code: &[u8]) -> bool {
+        code.len() == self.subspaces.len() && self.codebooks.iter().zip(code).all(|(codebook, &c)| (c as usize) < codebook.len())
+    }
+
+    // Codes for positions 0.., as `code` returns them concatenated
+    pub(crate) fn set_codes(&mut self, codes: Vec<u8>) {
+        self.codes = codes;
+    }
+
+    pub(crate) fn relocate(&mut self, from: usize, to: usize) {
+        let m = self.subspaces.len();
+        if self.codes.len() >= (from + 1) * m {
+            self.codes.copy_within(from * m..(from + 1) * m, to * m);
+        }
+        self.codes.truncate(from.max(to) * m);
+    }
+
+    // Approximate score of each code against `query` under `metric`, via
+    // per-subspace tables of dot products and centroid norms
+    pub(crate) fn scorer<'a>(&'a self, query: &[f32], metric: Metric) -> impl Fn(&[u8]) -> f32 + 'a {
+        let tables: Vec<Vec<(f32, f32)>> = self.subspaces
+            .iter()
+            .zip(&self.codebooks)
+            .map(|(range, codebook)| {
+                let q = &query[range.clone()];
+                codebook
+                    .iter()
+                    .map(|c| {
+                        let dot = q.iter().zip(c).map(|(x, y)| x * y).sum::<f32>();
+                        (dot, c.iter().map(|y| y * y).sum::<f32>())
+                    })
+                    .collect()
+            })
+            .collect();
+        let query_norm = query.iter().map(|x| x * x).sum::<f32>();
+        move |code: &[u8]| {
+            let (mut dot, mut norm) = (0f32, 0f32);
+            for (table, &c) in tables.iter().zip(code) {
+                let (d, n) = table[c as usize];
+                dot += d;
+                norm += n;
+            }
+            match metric {
+                Metric::Euclidean => (query_norm - 2.0 * dot + norm).max(0.0).sqrt(),
+                Metric::Cosine => {
+                    let norms = (query_norm * norm).sqrt();
+                    if norms == 0.0 {
+                        1.0
+                    } else {
+                        1.0 - dot / norms
+                    }
+                }
+                Metric::Dot => dot,
+            }
+        }
+    }
+
+    pub(crate) fn code(&self, pos: usize) -> &[u8] {
+        let m = self.subspaces.len();
+        &self.codes[pos * m..(pos + 1) * m]
+    }
+
+    pub(crate) fn n_subspaces(&self) -> usize {
+        self.subspaces.len()
+    }
+
+    // The embedding a code stands for: its centroids concatenated. None if
+    // the code doesn't fit the codebooks.
+    pub(crate) fn decode(&self, code: &[u8]) -> Option<Vec<f32>> {
+        if code.len() != self.subspaces.len() {
+            return None;
+        }
+        let mut embedding = Vec::with_capacity(self.dim());
+        for (codebook, &c) in self.codebooks.iter().zip(code) {
+            embedding.extend_from_slice(codebook.get(c as usize)?);
+        }
+        Some(embedding)
+    }
+
+    pub(crate) fn dim(&self) -> usize {
+        self.subspaces.last().map_or(0, |r| r.end)
+    }
+
+    // Section payload: layout, then the codebooks; codes are rebuilt on load
+    pub(crate) fn encode_codebooks(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let k = self.codebooks[0].len();
+        payload.extend_from_slice(&(self.subspaces.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&(k as u32).to_le_bytes());
+        payload.extend_from_slice(&(self.dim() as u32).to_le_bytes());
+        for codebook in &self.codebooks {
+            for centroid in codebook {
+                for v in centroid {
+                    payload.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+        payload
+    }
+
+    pub(crate) fn codebooks_size(&self) -> u64 {
+        let k = self.codebooks[0].len() as u64;
+        4 + 4 + 4 + 4 * k * self.dim() as u64
+    }
+
+    pub(crate) fn decode_codebooks(payload: &mut &[u8]) -> Result<Self, YAVSError> {
+        let n_subspaces = read_u32(payload)? as usize;
+        let k = read_u32(payload)? as usize;
+        let dim = read_u32(payload)? as usize;
+        if n_subspaces == 0 || n_subspaces > dim || k == 0 || k > MAX_CENTROIDS || (k * dim * 4) as u64 != payload.len() as u64 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let subspaces = Self::subspaces(dim, n_subspaces);
+        let mut codebooks = Vec::with_capacity(n_subspaces);
+        for range in &subspaces {
+            let mut codebook = Vec::with_capacity(k);
+            for _ in 0..k {
+                let mut centroid = Vec::with_capacity(range.len());
+                for _ in range.clone() {
+                    centroid.push(f32::from_bits(read_u32(payload)?));
+                }
+                codebook.push(centroid);
+            }
+            codebooks.push(codebook);
+        }
+        Ok(Self {
+            subspaces,
+            codebooks,
+            codes: Vec::new(),
+        })
+    }
+}
+
+impl YAVS {
+    // Learns product quantization codebooks from the live records, splitting
+    // embeddings into `n_subspaces` (at most the dimension, and 65535), and
+    // encodes every record. New records are encoded on insert. Returns false,
+    // training nothing, for an empty store. Under Pq precision the records
+    // are retrained from their decoded codes and encoded again.
+    pub fn train_pq(&mut self, n_subspaces: usize) -> bool {
+        let quantized = self.precision == Precision::Pq;
+        if quantized {
+            self.decode_pq();
+        }
+        let live: Vec<&Record> = self.iter().collect();
+        let n_subspaces = n_subspaces.clamp(1, (self.dim as usize).clamp(1, u16::MAX as usize));
+        self.pq = (!live.is_empty() && self.dim > 0).then(|| ProductQuantizer::train(live.len(), self.dim as usize, n_subspaces, |i| self.vector(live[i])));
+        if quantized && self.pq.is_some() {
+            self.precision = Precision::Pq;
+            let mut records = std::mem::take(&mut self.records);
+            for rec in &mut records {
+                self.fit_precision(rec);
+            }
+            self.records = records;
+        }
+        self.rebuild_indexes();
+        self.mark_changed();
+        self.pq.is_some()
+    }
+
+    // Under Pq precision the records are decoded to f32 first
+    pub fn drop_pq(&mut self) {
+        self.decode_pq();
+        if self.pq.take().is_some() {
+            self.mark_changed();
+        }
+    }
+
+    fn decode_pq(&mut self) {
+        if self.precision == Precision::Pq {
+            let _ = self.set_precision(Precision::F32);
+        }
+    }
+
+    pub fn has_pq(&self) -> bool {
+        self.pq.is_some()
+    }
+
+    // Approximate `query` ranked on the quantized codes. With `rerank` > 0 the
+    // best max(rerank, k) candidates are re-scored against their full
+    // embeddings and the results carry exact distances; with 0 they carry the
+    // approximate ones. Without trained codebooks this is just `query`. Under
+    // Pq precision there are no full embeddings to re-rank against, so this
+    // ranks as `query` does.
+    pub fn query_pq(&self, query_embedding: &[f32], k: usize, rerank: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let Some(pq) = &self.pq else {
+            return self.query(query_embedding, k);
+        };
+        let _permit = self.heavy_query_permit()?;
+        let score = pq.scorer(query_embedding, self.metric);
+        Ok(self.query_approx(started, "pq", query_embedding, k, rerank, |i| score(self.pq_code(pq, i))))
+    }
+
+    // The PQ code of the record at `pos`, its own under Pq precision
+    pub(crate) fn pq_code<'a>(&'a self, pq: &'a ProductQuantizer, pos: usize) -> &'a [u8] {
+        match self.precision {
+            Precision::Pq => &self.records[pos].code,
+            _ => pq.code(pos),
+        }
+    }
+
+    // Shared by the quantized query paths: keeps the best max(rerank, k) live
+    // records by `score` (of a record position), then re-ranks them exactly
+    // if `rerank` > 0. `kind` keeps their cache entries apart.
+    pub(crate) fn query_approx<F>(&self, started: Option<u64>, kind: &'static str, query_embedding: &[f32], k: usize, rerank: usize, mut score: F) -> Vec<([u8; 16], f32)>
+    where
+        F: FnMut(usize) -> f32,
+    {
+        let keep = if rerank > 0 { rerank.max(k) } else { k };
+        if keep == 0 {
+            return Vec::new();
+        }
+
+        let mut best = TopK::new(&self.metric, keep);
+        let mut scanned = 0;
+        for (i, rec) in self.records.iter().enumerate().filter(|(_, r)| !r.deleted) {
+            scanned += 1;
+            best.push(rec.id, score(i), i);
+        }
+
+        let scope = Scope::Quantized(kind, keep);
+        let results = if rerank > 0 {
+            let candidates = best.into_sorted().into_iter().map(|c| &self.records[c.item]);
+            let (results, rescored) = self.rank_counted(&scope, self.metric, candidates, query_embedding, k);
+            scanned += rescored;
+            results
+        } else {
+            let results = best.into_results();
+            self.record_hits(&results);
+            results
+        };
+        self.log_query(started, QueryRun {
+            scope: &scope,
+            metric: Some(self.metric),
+            query_embedding,
+            k,
+            results: results.len(),
+            scanned,
+        });
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_path, test_recall, test_vectors, Precision, YAVS};
+
+    #[test]
+    fn pq_precision_round_trip_and_ranking() {
+        let path = test_path("pq-precision.yavs");
+        let mut db = YAVS::new(16);
+        let vectors = test_vectors(1000, 16, 5);
+        for v in &vectors[..900] {
+            db.insert(v, b"").unwrap();
+        }
+        let queries = test_vectors(20, 16, 6);
+        assert!(db.train_pq(8));
+        db.set_precision(Precision::Pq).unwrap();
+        // Inserted after the switch, so encoded on the way in
+        for v in &vectors[900..] {
+            db.insert(v, b"").unwrap();
+        }
+        assert!(db.iter().all(|r| r.embedding.is_empty() && r.code.len() == 8));
+        let ranked: Vec<_> = queries.iter().map(|q| db.query(q, 10).unwrap()).collect();
+
+        db.save(&path).unwrap();
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.precision(), Precision::Pq);
+        for (a, b) in db.iter().zip(loaded.iter()) {
+            assert_eq!((a.id, &a.code), (b.id, &b.code));
+        }
+        for (q, r) in queries.iter().zip(&ranked) {
+            assert_eq!(&loaded.query(q, 10).unwrap(), r);
+        }
+
+        // Ranking by codes stays close to ranking the original embeddings
+        let mut exact = YAVS::new(16);
+        for (rec, v) in db.iter().zip(&vectors) {
+            exact.insert_with_id(&rec.id, v, b"").unwrap();
+        }
+        let recall = queries.iter().zip(&ranked).map(|(q, r)| test_recall(r, &exact.query(q, 10).unwrap())).sum::<f32>() / queries.len() as f32;
+        assert!(recall >= 0.7, "recall {recall}");
+    }
+}
@@ -0,0 +1,210 @@
+// Storage precision of embeddings in the record block. Half precision halves
+// file size; embeddings are rounded to it on the way in, so what a store
+// queries is exactly what it saves and loads. Requires the `half` feature.
+//
+// `Pq` keeps only each record's product quantization code, in memory and in
+// the file: embeddings are encoded with the trained codebooks on the way in
+// and then dropped, so a record takes a byte per subspace. Queries score the
+// codes directly, `Record::embedding` is empty (see `decoded_embedding`), and
+// there is no HNSW or IVF index, which need the full embeddings.
+//
+// `Int8` does the same with the trained scalar quantizer (see `train_sq`): a
+// record keeps a signed byte per dimension, a quarter of its f32 embedding.
+
+use std::borrow::Cow;
+use crate::{Metric, Record, YAVS, YAVSError};
+
+type CodeScorer<'a> = Box<dyn Fn(&[u8]) -> f32 + Send + Sync + 'a>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    F32,
+    F16,
+    Pq,
+    Int8,
+}
+
+impl Precision {
+    // Stored in the second reserved header word; 0 keeps older files f32
+    pub(crate) fn code(self) -> u32 {
+        match self {
+            Precision::F32 => 0,
+            Precision::F16 => 1,
+            Precision::Pq => 2,
+            Precision::Int8 => 3,
+        }
+    }
+
+    pub(crate) fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Precision::F32),
+            1 => Some(Precision::F16),
+            2 => Some(Precision::Pq),
+            3 => Some(Precision::Int8),
+            _ => None,
+        }
+    }
+
+    // Bytes per embedding value in the record block; Pq records are sized by
+    // their codes instead
+    pub(crate) fn width(self) -> u64 {
+        match self {
+            Precision::F32 => 4,
+            Precision::F16 => 2,
+            Precision::Pq => 0,
+            Precision::Int8 => 1,
+        }
+    }
+
+    // Whether records keep a code in place of their embedding
+    pub(crate) fn is_quantized(self) -> bool {
+        matches!(self, Precision::Pq | Precision::Int8)
+    }
+
+    // What embeddings are written at where a store's records are written
+    // whole, e.g. in its write-ahead log
+    pub(crate) fn unquantized(self) -> Self {
+        match self.is_quantized() {
+            true => Precision::F32,
+            false => self,
+        }
+    }
+
+    // Rounds values to what they read back as after a save at this precision
+    pub(crate) fn round_all(self, values: &mut [f32]) {
+        if self == Precision::F16 {
+            for v in values {
+                *v = to_f16(*v).and_then(from_f16).unwrap_or(*v);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "half")]
+pub(crate) fn to_f16(v: f32) -> Result<u16, YAVSError> {
+    Ok(half::f16::from_f32(v).to_bits())
+}
+
+#[cfg(feature = "half")]
+pub(crate) fn from_f16(bits: u16) -> Result<f32, YAVSError> {
+    Ok(half::f16::from_bits(bits).to_f32())
+}
+
+#[cfg(not(feature = "half"))]
+pub(crate) fn to_f16(_v: f32) -> Result<u16, YAVSError> {
+    Err(YAVSError::FeatureDisabled("half"))
+}
+
+#[cfg(not(feature = "half"))]
+pub(crate) fn from_f16(_bits: u16) -> Result<f32, YAVSError> {
+    Err(YAVSError::FeatureDisabled("half"))
+}
+
+impl YAVS {
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    // Switches storage precision. Going to F16 rounds every stored embedding
+    // (deleted ones included) and rebuilds the search indexes over them.
+    // Going to Pq or Int8 needs the matching trained quantizer (see
+    // `train_pq`, `train_sq`), encodes every record and drops the HNSW and IVF
+    // indexes; leaving it decodes the records to what their codes stand for.
+    pub fn set_precision(&mut self, precision: Precision) -> Result<(), YAVSError> {
+        if precision == Precision::F16 {
+            to_f16(0.0)?;
+        }
+        if precision == Precision::Pq && self.pq.is_none() {
+            return Err(YAVSError::Untrained("pq"));
+        }
+        if precision == Precision::Int8 && self.sq.is_none() {
+            return Err(YAVSError::Untrained("sq"));
+        }
+        if precision == self.precision {
+            return Ok(());
+        }
+        if self.precision.is_quantized() {
+            let decoded: Vec<Vec<f32>> = self.records.iter().map(|r| self.vector(r).into_owned()).collect();
+            for (rec, embedding) in self.records.iter_mut().zip(decoded) {
+                rec.embedding = embedding;
+                rec.code = Vec::new();
+            }
+        }
+        self.precision = precision;
+        let mut records = std::mem::take(&mut self.records);
+        for rec in &mut records {
+            self.fit_precision(rec);
+        }
+        self.records = records;
+        if precision.is_quantized() {
+            self.index = None;
+            self.index_build = None;
+            self.ivf = None;
+        }
+        self.rebuild_indexes();
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Brings a record about to be stored to the store's precision: rounds
+    // its embedding, or under Pq and Int8 replaces it with its code. A record already
+    // holding only a code is left alone.
+    pub(crate) fn fit_precision(&self, rec: &mut Record) {
+        if rec.embedding.is_empty() && !rec.code.is_empty() {
+            return;
+        }
+        match (self.precision, &self.pq, &self.sq) {
+            (Precision::Pq, Some(pq), _) => {
+                rec.code = pq.quantize(&rec.embedding);
+                rec.embedding = Vec::new();
+            }
+            (Precision::Int8, _, Some(sq)) => {
+                rec.code = sq.quantize(&rec.embedding);
+                rec.embedding = Vec::new();
+            }
+            (precision, _, _) => {
+                precision.round_all(&mut rec.embedding);
+                rec.code = Vec::new();
+            }
+        }
+    }
+
+    // A stored record's embedding, decoded from its code if it has one
+    pub(crate) fn vector<'a>(&self, rec: &'a Record) -> Cow<'a, [f32]> {
+        if rec.code.is_empty() {
+            return Cow::Borrowed(&rec.embedding);
+        }
+        match (self.precision, &self.pq, &self.sq) {
+            (Precision::Pq, Some(pq), _) => Cow::Owned(pq.decode(&rec.code).unwrap_or_default()),
+            (Precision::Int8, _, Some(sq)) => Cow::Owned(sq.decode(&rec.code)),
+            _ => Cow::Borrowed(&rec.embedding),
+        }
+    }
+
+    // A copy of a stored record with its embedding decoded, for handing to
+    // another store or writing out whole
+    pub(crate) fn expanded(&self, rec: &Record) -> Record {
+        Record {
+            embedding: self.vector(rec).into_owned(),
+            code: Vec::new(),
+            ..rec.clone()
+        }
+    }
+
+    // Scores stored codes against `query_embedding` under `metric`, for
+    // stores whose records hold codes; None for the others
+    pub(crate) fn code_scorer(&self, metric: Metric, query_embedding: &[f32]) -> Option<CodeScorer<'_>> {
+        match (self.precision, &self.pq, &self.sq) {
+            (Precision::Pq, Some(pq), _) => Some(Box::new(pq.scorer(query_embedding, metric))),
+            (Precision::Int8, _, Some(sq)) => Some(Box::new(sq.scorer(query_embedding, metric))),
+            _ => None,
+        }
+    }
+
+    // A live record's embedding; under Pq and Int8 precision what its code
+    // stands for, where `embedding_of` has nothing to borrow
+    pub fn decoded_embedding(&self, id: &[u8; 16]) -> Option<Cow<'_, [f32]>> {
+        self.find(id).map(|r| self.vector(r))
+    }
+}
@@ -0,0 +1,124 @@
+// Obfuscated exports, for sharing a store outside the team without handing
+// over the exact geometry of a proprietary embedding model. Gaussian noise
+// perturbs every embedding independently (`gaussian_dp` calibrates it with
+// the classic Gaussian mechanism); a random rotation keeps all distances and
+// dot products between exported embeddings intact but hides the model's
+// basis, so queries must go through the same `Obfuscator` to match.
+// Both are seeded, so an export can be reproduced.
+
+use crate::ids::splitmix64;
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Obfuscation {
+    // Adds N(0, sigma²) to every value
+    Gaussian { sigma: f32, seed: u64 },
+    // Multiplies every embedding by the same random orthogonal matrix
+    Rotation { seed: u64 },
+}
+
+impl Obfuscation {
+    // Noise for (epsilon, delta)-differential privacy of embeddings whose L2
+    // sensitivity (how far one input can move an embedding) is `sensitivity`.
+    // The calibration holds for epsilon < 1.
+    pub fn gaussian_dp(epsilon: f32, delta: f32, sensitivity: f32, seed: u64) -> Result<Self, YAVSError> {
+        let valid = epsilon > 0.0 && epsilon < 1.0 && delta > 0.0 && delta < 1.0 && sensitivity >= 0.0 && sensitivity.is_finite();
+        if !valid {
+            return Err(YAVSError::InvalidNoise);
+        }
+        let sigma = sensitivity * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+        Ok(Obfuscation::Gaussian { sigma, seed })
+    }
+}
+
+// Applies an `Obfuscation` to embeddings of one dimension. Gaussian noise
+// draws fresh values for each call; a rotation is the same for every call.
+#[derive(Debug, Clone)]
+pub struct Obfuscator {
+    dim: usize,
+    rng: u64,
+    sigma: f32,
+    // Row-major dim x dim orthogonal matrix, empty for Gaussian noise
+    rotation: Vec<f32>,
+}
+
+impl Obfuscator {
+    pub fn new(obfuscation: &Obfuscation, dim: u32) -> Result<Self, YAVSError> {
+        let dim = dim as usize;
+        match *obfuscation {
+            Obfuscation::Gaussian { sigma, seed } => {
+                if !(sigma >= 0.0 && sigma.is_finite()) {
+                    return Err(YAVSError::InvalidNoise);
+                }
+                Ok(Self { dim, rng: seed, sigma, rotation: Vec::new() })
+            }
+            Obfuscation::Rotation { seed } => {
+                let mut rng = seed;
+                let rotation = random_rotation(dim, &mut rng);
+                Ok(Self { dim, rng, sigma: 0.0, rotation })
+            }
+        }
+    }
+
+    pub fn apply(&mut self, embedding: &[f32]) -> Result<Vec<f32>, YAVSError> {
+        if embedding.len() != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        if self.rotation.is_empty() {
+            let rng = &mut self.rng;
+            return Ok(embedding.iter().map(|&v| v + self.sigma * gaussian(rng)).collect());
+        }
+        Ok(self.rotation
+            .chunks_exact(self.dim)
+            .map(|row| row.iter().zip(embedding).map(|(r, v)| r * v).sum())
+            .collect())
+    }
+}
+
+// Standard normal sample (Box-Muller)
+fn gaussian(rng: &mut u64) -> f32 {
+    let uniform = |rng: &mut u64| (splitmix64(rng) >> 11) as f64 / (1u64 << 53) as f64;
+    let u1 = 1.0 - uniform(rng);
+    let u2 = uniform(rng);
+    ((-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()) as f32
+}
+
+// Orthonormalizes a Gaussian random matrix row by row (modified
+// Gram-Schmidt), which gives a uniformly random rotation
+fn random_rotation(dim: usize, rng: &mut u64) -> Vec<f32> {
+    let mut m: Vec<f64> = (0..dim * dim).map(|_| gaussian(rng) as f64).collect();
+    for i in 0..dim {
+        for j in 0..i {
+            let dot: f64 = (0..dim).map(|c| m[i * dim + c] * m[j * dim + c]).sum();
+            for c in 0..dim {
+                m[i * dim + c] -= dot * m[j * dim + c];
+            }
+        }
+        let norm = (0..dim).map(|c| m[i * dim + c] * m[i * dim + c]).sum::<f64>().sqrt();
+        for c in 0..dim {
+            m[i * dim + c] /= norm;
+        }
+    }
+    m.into_iter().map(|v| v as f32).collect()
+}
+
+impl YAVS {
+    // A copy of the live records with every embedding obfuscated; metadata,
+    // tags and the rest are copied as-is. A store at Pq or Int8 precision is
+    // exported at f32, its quantizer not fitting the obfuscated embeddings.
+    pub fn export_obfuscated(&self, obfuscation: &Obfuscation) -> Result<YAVS, YAVSError> {
+        let mut obfuscator = Obfuscator::new(obfuscation, self.dim)?;
+        let precision = self.precision.unquantized();
+        let mut records = Vec::with_capacity(self.len());
+        for rec in self.iter() {
+            let mut rec = self.expanded(rec);
+            rec.embedding = obfuscator.apply(&rec.embedding)?;
+            precision.round_all(&mut rec.embedding);
+            records.push(rec);
+        }
+        let mut db = YAVS::from_records(self.dim, self.metric, records);
+        db.precision = precision;
+        db.compress_metadata = self.compress_metadata;
+        Ok(db)
+    }
+}
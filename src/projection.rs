@@ -0,0 +1,111 @@
+// 2D PCA projection of the live records for plotting corpus structure,
+// exportable as JSON or CSV with each point's ID and metadata. Enabled by
+// the `projection` feature.
+
+use std::io::Write;
+use serde::Serialize;
+use uuid::Uuid;
+use crate::{YAVS, YAVSError};
+
+const POWER_ITERATIONS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectedPoint {
+    pub id: String,
+    pub x: f32,
+    pub y: f32,
+    // Metadata decoded as UTF-8, with invalid bytes replaced
+    pub metadata: String,
+}
+
+impl YAVS {
+    // Coordinates along the top two principal components
+    pub fn project_2d(&self) -> Vec<ProjectedPoint> {
+        let dim = self.dim as usize;
+        let n = self.len();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let mut mean = vec![0f64; dim];
+        for rec in self.iter() {
+            for (m, &v) in mean.iter_mut().zip(self.vector(rec).iter()) {
+                *m += v as f64;
+            }
+        }
+        mean.iter_mut().for_each(|m| *m /= n as f64);
+        let centered: Vec<Vec<f64>> = self
+            .iter()
+            .map(|r| self.vector(r).iter().zip(&mean).map(|(&v, m)| v as f64 - m).collect())
+            .collect();
+
+        let first = principal_component(&centered, dim, None);
+        let second = principal_component(&centered, dim, Some(&first));
+
+        self.iter()
+            .zip(&centered)
+            .map(|(rec, row)| ProjectedPoint {
+                id: Uuid::from_bytes(rec.id).hyphenated().to_string(),
+                x: dot(row, &first) as f32,
+                y: dot(row, &second) as f32,
+                metadata: String::from_utf8_lossy(&rec.metadata).into_owned(),
+            })
+            .collect()
+    }
+
+    pub fn write_projection_json<W: Write>(&self, w: &mut W) -> Result<(), YAVSError> {
+        serde_json::to_writer(&mut *w, &self.project_2d()).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn write_projection_csv<W: Write>(&self, w: &mut W) -> Result<(), YAVSError> {
+        writeln!(w, "id,x,y,metadata")?;
+        for p in self.project_2d() {
+            writeln!(w, "{},{},{},\"{}\"", p.id, p.x, p.y, p.metadata.replace('"', "\"\""))?;
+        }
+        Ok(())
+    }
+}
+
+// Unit eigenvector of the covariance with the largest eigenvalue, found by
+// power iteration; `orthogonal_to` deflates out a previous component
+fn principal_component(rows: &[Vec<f64>], dim: usize, orthogonal_to: Option<&[f64]>) -> Vec<f64> {
+    // Fixed start so projections are reproducible
+    let mut v: Vec<f64> = (0..dim).map(|i| 1.0 + i as f64 / dim as f64).collect();
+    for _ in 0..POWER_ITERATIONS {
+        if let Some(u) = orthogonal_to {
+            let p = dot(&v, u);
+            v.iter_mut().zip(u).for_each(|(a, b)| *a -= p * b);
+        }
+        if !normalize(&mut v) {
+            break;
+        }
+        let mut next = vec![0f64; dim];
+        for row in rows {
+            let p = dot(row, &v);
+            next.iter_mut().zip(row).for_each(|(a, b)| *a += p * b);
+        }
+        v = next;
+    }
+    if let Some(u) = orthogonal_to {
+        let p = dot(&v, u);
+        v.iter_mut().zip(u).for_each(|(a, b)| *a -= p * b);
+    }
+    normalize(&mut v);
+    v
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// Scales `v` to unit length; returns false (leaving it zero) if it has none
+fn normalize(v: &mut [f64]) -> bool {
+    let norm = dot(v, v).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        v.iter_mut().for_each(|a| *a = 0.0);
+        return false;
+    }
+    v.iter_mut().for_each(|a| *a /= norm);
+    true
+}
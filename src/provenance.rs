@@ -0,0 +1,117 @@
+// Where a record came from: the source document and the pipeline and
+// embedder versions that produced it, kept outside the opaque metadata so it
+// can be audited, filtered on and counted. Records ingested together share
+// one provenance, so it is reference-counted in memory and each distinct
+// value is written once per file.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::cache::Scope;
+use crate::{NewRecord, Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Provenance {
+    // URI of the source document
+    pub source: Option<String>,
+    pub pipeline_version: Option<String>,
+    pub embedder_version: Option<String>,
+}
+
+impl Provenance {
+    // Whether every field set in `filter` has the same value here
+    pub fn matches(&self, filter: &Provenance) -> bool {
+        let field = |want: &Option<String>, have: &Option<String>| want.is_none() || want == have;
+        field(&filter.source, &self.source)
+            && field(&filter.pipeline_version, &self.pipeline_version)
+            && field(&filter.embedder_version, &self.embedder_version)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub live_records: usize,
+    pub deleted_records: usize,
+    // Live records with no provenance
+    pub without_provenance: usize,
+    // Live record counts per value, in value order
+    pub sources: Vec<(String, usize)>,
+    pub pipeline_versions: Vec<(String, usize)>,
+    pub embedder_versions: Vec<(String, usize)>,
+}
+
+impl YAVS {
+    pub fn insert_with_provenance<P: Into<Arc<Provenance>>>(&mut self, embedding: &[f32], metadata: &[u8], provenance: P) -> Result<[u8; 16], YAVSError> {
+        let new = NewRecord {
+            provenance: Some(provenance.into()),
+            ..Default::default()
+        };
+        self.insert_record(embedding.into(), metadata.into(), new)
+    }
+
+    pub fn provenance_of(&self, id: &[u8; 16]) -> Option<&Provenance> {
+        self.find(id)?.provenance.as_deref()
+    }
+
+    // Replaces (or with None clears) a live record's provenance. Returns
+    // false if there is no such record, or the change couldn't be written to
+    // the store's write-ahead log.
+    pub fn set_provenance(&mut self, id: &[u8; 16], provenance: Option<Provenance>) -> bool {
+        let Some(i) = self.position(id) else {
+            return false;
+        };
+        let provenance = provenance.map(Arc::new);
+        let now = self.clock.now_ms();
+        let changed = self.change_record(i, |r| {
+            r.provenance = provenance.clone();
+            r.updated_at = now;
+        });
+        if changed.is_err() {
+            return false;
+        }
+        self.mark_changed();
+        true
+    }
+
+    // Like `query`, but only over records whose provenance matches `filter`
+    // (see `Provenance::matches`); fields left None match anything
+    pub fn query_provenance(&self, query_embedding: &[f32], k: usize, filter: &Provenance) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let candidates = self.iter().filter(|r| r.provenance.as_ref().is_some_and(|p| p.matches(filter)));
+        Ok(self.rank(started, Scope::Provenance(filter), self.metric, candidates, query_embedding, k))
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        StoreStats::of(&self.records)
+    }
+}
+
+impl StoreStats {
+    pub(crate) fn of<'a, I: IntoIterator<Item = &'a Record>>(records: I) -> Self {
+        let mut stats = StoreStats::default();
+        let mut counts: [BTreeMap<&str, usize>; 3] = Default::default();
+        for rec in records {
+            if rec.deleted {
+                stats.deleted_records += 1;
+                continue;
+            }
+            stats.live_records += 1;
+            let Some(p) = &rec.provenance else {
+                stats.without_provenance += 1;
+                continue;
+            };
+            for (count, field) in counts.iter_mut().zip([&p.source, &p.pipeline_version, &p.embedder_version]) {
+                if let Some(value) = field {
+                    *count.entry(value.as_str()).or_default() += 1;
+                }
+            }
+        }
+        let [sources, pipelines, embedders] = counts.map(|c| c.into_iter().map(|(v, n)| (v.to_string(), n)).collect());
+        stats.sources = sources;
+        stats.pipeline_versions = pipelines;
+        stats.embedder_versions = embedders;
+        stats
+    }
+}
@@ -0,0 +1,159 @@
+// Salvaging readable records from a damaged store file when there is no
+// backup to restore. Records that fail to parse are skipped by scanning
+// forward for the next offset where records parse again; the skipped byte
+// ranges are reported. Records that parse but fail their checksum (corrupt
+// embedding or metadata bytes) are carried over as-is; `load_file` is what
+// reports those.
+
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use crate::format::{self, read_encoding, read_metric, read_record, read_section, read_u32, read_u64, Attached, Encoding, HEADER_SIZE, MAGIC, RESERVED_SIZE, VERSION};
+use crate::precision::from_f16;
+use crate::{Precision, Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    // Record count claimed by the header
+    pub records_expected: u64,
+    pub records_recovered: usize,
+    pub sections_recovered: usize,
+    // Byte ranges of the source file that could not be read
+    pub lost_regions: Vec<Range<u64>>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.lost_regions.is_empty() && self.records_recovered as u64 == self.records_expected
+    }
+}
+
+// Writes whatever can be read from `src` to a new store at `dst`. Fails only
+// if the header itself is unreadable, or for a store at Pq or Int8 precision,
+// whose quantizer as well.
+pub fn repair_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<RepairReport, YAVSError> {
+    let bytes = fs::read(src)?;
+    let mut header = bytes.as_slice();
+    let mut magic = [0u8; 4];
+    std::io::Read::read_exact(&mut header, &mut magic)?;
+    if magic != MAGIC {
+        return Err(YAVSError::InvalidFile);
+    }
+    let version = read_u32(&mut header)?;
+    if version == 0 || version > VERSION {
+        return Err(YAVSError::VersionMismatch);
+    }
+    let n_records = read_u64(&mut header)?;
+    let dim = read_u32(&mut header)?;
+    let mut reserved = [0u8; RESERVED_SIZE];
+    std::io::Read::read_exact(&mut header, &mut reserved)?;
+    let metric = read_metric(&reserved)?;
+    // Without the features every record would fail to parse and look lost
+    let enc = read_encoding(&reserved)?;
+    if enc.precision == Precision::F16 {
+        from_f16(0)?;
+    }
+
+    let mut report = RepairReport {
+        records_expected: n_records,
+        ..Default::default()
+    };
+    let mut records = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pos = HEADER_SIZE as usize;
+    let mut slots = 0;
+    // Once a resync has located the sections, records end there instead of
+    // after `n_records`, since an unknown number of records were lost
+    let mut records_end = None;
+
+    loop {
+        let more = match records_end {
+            Some(end) => pos < end,
+            None => slots < n_records && pos < bytes.len(),
+        };
+        if !more {
+            break;
+        }
+        match record_at(&bytes, pos, dim, enc) {
+            Some((rec, size)) => {
+                if seen.insert(rec.id) {
+                    records.push(rec);
+                }
+                pos += size;
+                slots += 1;
+            }
+            None => {
+                let (next, end) = resync(&bytes, pos, dim, enc);
+                report.lost_regions.push(pos as u64..next as u64);
+                pos = next;
+                records_end = records_end.or(end);
+            }
+        }
+    }
+
+    let mut attached = Attached::default();
+    if version >= 2 {
+        let mut rest = &bytes[pos..];
+        while !rest.is_empty() {
+            let start = bytes.len() - rest.len();
+            if read_section(&mut rest, &mut records, &mut attached, dim, enc).is_err() {
+                report.lost_regions.push(start as u64..bytes.len() as u64);
+                break;
+            }
+            report.sections_recovered += 1;
+        }
+    }
+
+    // Under Pq and Int8 precision records are only readable with their
+    // quantizer
+    let (pq, sq) = match enc.precision {
+        Precision::Pq => {
+            let pq = attached.pq.take().filter(|pq| pq.n_subspaces() == enc.subspaces as usize).ok_or(YAVSError::InvalidFile)?;
+            records.retain(|r| pq.fits(&r.code));
+            (Some(pq), None)
+        }
+        Precision::Int8 => (None, Some(attached.sq.take().filter(|sq| sq.dim() == dim as usize).ok_or(YAVSError::InvalidFile)?)),
+        _ => (None, None),
+    };
+
+    report.records_recovered = records.len();
+    let mut db = YAVS::from_records(dim, metric, records);
+    db.precision = enc.precision;
+    db.pq = pq;
+    db.sq = sq;
+    db.compress_metadata = enc.compressed;
+    db.save_as(dst)?;
+    Ok(report)
+}
+
+fn record_at(bytes: &[u8], pos: usize, dim: u32, enc: Encoding) -> Option<(Record, usize)> {
+    let mut slice = &bytes[pos..];
+    let rec = read_record(&mut slice, dim, enc).ok()?;
+    Some((rec, bytes.len() - pos - slice.len()))
+}
+
+// Finds the next offset after `pos` where records resume. Prefers an offset
+// from which records run cleanly into the sections (or the end of the file),
+// returning where the records end; otherwise falls back to the first run of
+// a few parseable records, and failing that gives up on the rest.
+fn resync(bytes: &[u8], pos: usize, dim: u32, enc: Encoding) -> (usize, Option<usize>) {
+    const MIN_RUN: usize = 3;
+
+    let mut fallback = None;
+    for p in pos + 1..bytes.len() {
+        let mut q = p;
+        let mut run = 0;
+        while let Some((_, size)) = record_at(bytes, q, dim, enc) {
+            q += size;
+            run += 1;
+            if format::is_section_chain(&bytes[q..]) {
+                return (p, Some(q));
+            }
+        }
+        if run >= MIN_RUN && fallback.is_none() {
+            fallback = Some(p);
+        }
+    }
+    (fallback.unwrap_or(bytes.len()), None)
+}
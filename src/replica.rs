@@ -0,0 +1,171 @@
+// Dual writes for migrating between stores (e.g. to a new precision or file,
+// or from a local copy to a shared one) without a write freeze. Every change
+// goes to the primary and is then copied to the secondary, record for record
+// under the same ID. A copy that fails doesn't fail the write: the ID is kept
+// as pending until `resync` or `backfill` brings it over, so the primary
+// stays the source of truth. Once `verify` finds no differences, `promote`
+// swaps the roles, keeping the old primary as a warm fallback.
+
+use std::collections::BTreeSet;
+use crate::{records_equal, StoreDiff, YAVS, YAVSError};
+
+#[derive(Debug)]
+pub struct DualWrite {
+    primary: YAVS,
+    secondary: YAVS,
+    // IDs whose last copy to the secondary failed
+    pending: BTreeSet<[u8; 16]>,
+}
+
+impl DualWrite {
+    // The secondary may hold anything; `backfill` copies what it's missing
+    pub fn new(primary: YAVS, secondary: YAVS) -> Result<Self, YAVSError> {
+        if primary.dim != secondary.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        Ok(Self { primary, secondary, pending: BTreeSet::new() })
+    }
+
+    pub fn primary(&self) -> &YAVS {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &YAVS {
+        &self.secondary
+    }
+
+    // For changes not covered here; call `sync` or `backfill` afterwards to
+    // carry them to the secondary
+    pub fn primary_mut(&mut self) -> &mut YAVS {
+        &mut self.primary
+    }
+
+    pub fn into_stores(self) -> (YAVS, YAVS) {
+        (self.primary, self.secondary)
+    }
+
+    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        let id = self.primary.insert(embedding, metadata)?;
+        self.mirror(&id);
+        Ok(id)
+    }
+
+    pub fn insert_with_id(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<(), YAVSError> {
+        self.primary.insert_with_id(id, embedding, metadata)?;
+        self.mirror(id);
+        Ok(())
+    }
+
+    pub fn update(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<(), YAVSError> {
+        self.primary.update(id, embedding, metadata)?;
+        self.mirror(id);
+        Ok(())
+    }
+
+    pub fn upsert(&mut self, id: &[u8; 16], embedding: &[f32], metadata: &[u8]) -> Result<bool, YAVSError> {
+        let inserted = self.primary.upsert(id, embedding, metadata)?;
+        self.mirror(id);
+        Ok(inserted)
+    }
+
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        let removed = self.primary.remove(id);
+        self.mirror(id);
+        removed
+    }
+
+    // IDs whose copy to the secondary failed and hasn't been redone yet
+    pub fn pending(&self) -> impl Iterator<Item = &[u8; 16]> {
+        self.pending.iter()
+    }
+
+    // Makes the secondary's record with this ID match the primary's: copied
+    // over if live there, removed otherwise. Returns whether anything changed.
+    pub fn sync(&mut self, id: &[u8; 16]) -> Result<bool, YAVSError> {
+        let changed = copy_record(&self.primary, &mut self.secondary, id)?;
+        self.pending.remove(id);
+        Ok(changed)
+    }
+
+    // Retries the pending IDs, returning how many are still pending
+    pub fn resync(&mut self) -> usize {
+        let pending = std::mem::take(&mut self.pending);
+        for id in &pending {
+            self.mirror(id);
+        }
+        self.pending.len()
+    }
+
+    // Syncs every record that differs between the stores, e.g. after
+    // starting dual writes on a secondary holding an older copy. Returns
+    // how many records changed on the secondary; failed copies are left
+    // pending.
+    pub fn backfill(&mut self) -> usize {
+        let mut ids: BTreeSet<[u8; 16]> = self.primary.ids().collect();
+        ids.extend(self.secondary.ids());
+        ids.extend(std::mem::take(&mut self.pending));
+        let mut changed = 0;
+        for id in ids {
+            match copy_record(&self.primary, &mut self.secondary, &id) {
+                Ok(true) => changed += 1,
+                Ok(false) => {}
+                Err(_) => {
+                    self.pending.insert(id);
+                }
+            }
+        }
+        changed
+    }
+
+    // Differences between the live records of the two stores, with embeddings
+    // within `eps` per component counting as equal (for a secondary at lower
+    // precision)
+    pub fn verify(&self, eps: f32) -> Result<StoreDiff, YAVSError> {
+        YAVS::diff_with_tolerance(&self.primary, &self.secondary, eps)
+    }
+
+    // Swaps the stores once the secondary matches (see `verify`) and nothing
+    // is pending; writes then go to the new primary first and are copied
+    // back to the old one. Fails with `ReplicaMismatch` otherwise.
+    pub fn promote(&mut self, eps: f32) -> Result<(), YAVSError> {
+        if !self.pending.is_empty() || !self.verify(eps)?.is_empty() {
+            return Err(YAVSError::ReplicaMismatch);
+        }
+        std::mem::swap(&mut self.primary, &mut self.secondary);
+        Ok(())
+    }
+
+    fn mirror(&mut self, id: &[u8; 16]) {
+        if copy_record(&self.primary, &mut self.secondary, id).is_err() {
+            self.pending.insert(*id);
+        } else {
+            self.pending.remove(id);
+        }
+    }
+}
+
+// Insert hooks don't run on the copy; the record is taken as the primary
+// stored it, timestamps included
+fn copy_record(from: &YAVS, to: &mut YAVS, id: &[u8; 16]) -> Result<bool, YAVSError> {
+    let Some(rec) = from.find(id) else {
+        // A live record that isn't removed wasn't logged
+        return match to.position(id) {
+            Some(_) if !to.remove(id) => Err(YAVSError::WalFailed),
+            found => Ok(found.is_some()),
+        };
+    };
+    let rec = from.expanded(rec);
+    let Some(i) = to.position(id) else {
+        to.push_record(rec)?;
+        return Ok(true);
+    };
+    let mut stored = rec.clone();
+    to.fit_precision(&mut stored);
+    if records_equal(&to.records[i], &stored, 0.0) {
+        return Ok(false);
+    }
+    to.check_metadata_len(&rec.metadata)?;
+    to.log_updated(&rec)?;
+    to.replace_record(i, stored);
+    Ok(true)
+}
@@ -0,0 +1,52 @@
+// Two-stage retrieval: `query` picks the nearest candidates, then a
+// caller-supplied reranker (a cross-encoder, business rules, ...) scores them
+// with access to each record's metadata and decides the final order.
+
+use crate::{Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RerankCandidate<'a> {
+    // Distance (or similarity) from the first stage
+    pub distance: f32,
+    pub record: &'a Record,
+}
+
+pub trait Reranker {
+    // One score per candidate, in the same order; higher scores rank first.
+    // An error aborts the query.
+    fn rerank(&self, query_embedding: &[f32], candidates: &[RerankCandidate<'_>]) -> Result<Vec<f32>, YAVSError>;
+}
+
+impl<F> Reranker for F
+where
+    F: Fn(&[f32], &[RerankCandidate<'_>]) -> Result<Vec<f32>, YAVSError>,
+{
+    fn rerank(&self, query_embedding: &[f32], candidates: &[RerankCandidate<'_>]) -> Result<Vec<f32>, YAVSError> {
+        self(query_embedding, candidates)
+    }
+}
+
+impl YAVS {
+    // Passes the `candidates` nearest records (at least `k`) to `reranker` and
+    // returns the best `k` by its scores, paired with those scores. Ties and
+    // NaN scores fall back to first-stage order.
+    pub fn query_reranked<R: Reranker + ?Sized>(&self, query_embedding: &[f32], k: usize, candidates: usize, reranker: &R) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        let nearest = self.query(query_embedding, candidates.max(k))?;
+        let candidates: Vec<RerankCandidate> = nearest
+            .iter()
+            .filter_map(|(id, distance)| self.find(id).map(|record| RerankCandidate { distance: *distance, record }))
+            .collect();
+        let scores = reranker.rerank(query_embedding, &candidates)?;
+        if scores.len() != candidates.len() {
+            return Err(YAVSError::RerankMismatch);
+        }
+        let mut ranked: Vec<([u8; 16], f32)> = candidates.iter().zip(scores).map(|(c, score)| (c.record.id, score)).collect();
+        // Stable, so equal scores keep their first-stage order
+        ranked.sort_by(|a, b| match (a.1.is_nan(), b.1.is_nan()) {
+            (false, false) => b.1.total_cmp(&a.1),
+            (a_nan, b_nan) => a_nan.cmp(&b_nan),
+        });
+        ranked.truncate(k);
+        Ok(ranked)
+    }
+}
@@ -0,0 +1,97 @@
+// In-place metadata migrations. A transformation is applied to every live
+// record's metadata; returning None leaves a record untouched, so a
+// migration that recognises already-migrated metadata can simply be re-run
+// after an interruption and picks up where its last checkpoint left off.
+
+use std::path::PathBuf;
+use crate::{YAVS, YAVSError};
+
+#[derive(Debug, Clone)]
+pub struct RewriteOptions {
+    // Progress is reported after every `progress_every` visited records
+    pub progress_every: usize,
+    // Saved to after every `checkpoint_every` rewritten records and at the end
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+}
+
+impl Default for RewriteOptions {
+    fn default() -> Self {
+        Self {
+            progress_every: 10_000,
+            checkpoint: None,
+            checkpoint_every: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RewriteStats {
+    // Live records when the rewrite started
+    pub total: usize,
+    pub visited: usize,
+    pub rewritten: usize,
+    pub checkpoints: usize,
+}
+
+impl YAVS {
+    // Replaces each live record's metadata with `transform`'s result, where
+    // it returns one. Returns how many records were rewritten.
+    pub fn rewrite_metadata<F>(&mut self, transform: F) -> Result<usize, YAVSError>
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>>,
+    {
+        let stats = self.rewrite_metadata_with(transform, &RewriteOptions::default(), |_| {})?;
+        Ok(stats.rewritten)
+    }
+
+    // Like `rewrite_metadata`, reporting progress to `progress` and saving
+    // checkpoints as configured in `options`
+    pub fn rewrite_metadata_with<F, P>(&mut self, mut transform: F, options: &RewriteOptions, mut progress: P) -> Result<RewriteStats, YAVSError>
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>>,
+        P: FnMut(&RewriteStats),
+    {
+        let mut stats = RewriteStats {
+            total: self.len(),
+            ..Default::default()
+        };
+        let mut since_checkpoint = 0;
+        for i in 0..self.records.len() {
+            if self.records[i].deleted {
+                continue;
+            }
+            stats.visited += 1;
+            if let Some(metadata) = transform(&self.records[i].metadata) {
+                self.check_metadata_len(&metadata)?;
+                let now = self.clock.now_ms();
+                self.fields.remove(i, &self.records[i].metadata);
+                self.fields.add(i, &metadata);
+                let rec = &mut self.records[i];
+                rec.metadata = metadata;
+                rec.updated_at = now;
+                self.mark_changed();
+                stats.rewritten += 1;
+                since_checkpoint += 1;
+            }
+            if let Some(path) = &options.checkpoint {
+                if options.checkpoint_every > 0 && since_checkpoint >= options.checkpoint_every {
+                    self.save(path)?;
+                    stats.checkpoints += 1;
+                    since_checkpoint = 0;
+                }
+            }
+            if options.progress_every > 0 && stats.visited.is_multiple_of(options.progress_every) {
+                progress(&stats);
+            }
+        }
+        if let Some(path) = &options.checkpoint {
+            if self.is_dirty() {
+                self.save(path)?;
+                stats.checkpoints += 1;
+            }
+        }
+        progress(&stats);
+        Ok(stats)
+    }
+}
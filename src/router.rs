@@ -0,0 +1,176 @@
+// Several named stores behind one API, e.g. one per embedding model or
+// locale. Inserts and queries name a route: a store's own name or an alias
+// added with `alias`. `query_all` searches every store that fits the query
+// and merges the results, which only makes sense for stores with the same
+// metric and a shared embedding space.
+//
+// Store names double as file names in `save_dir`, so `add` rejects empty
+// names, `.`, `..` and any holding `/`, `\`, `:` or NUL.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use crate::topk::TopK;
+use crate::{YAVS, YAVSError};
+
+// A `query_all` hit and the store it came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoutedHit<'a> {
+    pub store: &'a str,
+    pub id: [u8; 16],
+    pub score: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct Router {
+    stores: BTreeMap<String, YAVS>,
+    // Route name to store name
+    aliases: BTreeMap<String, String>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a store under `name`, returning the one it replaces
+    pub fn add(&mut self, name: &str, store: YAVS) -> Result<Option<YAVS>, YAVSError> {
+        check_name(name)?;
+        Ok(self.stores.insert(name.to_string(), store))
+    }
+
+    // Removes a store and the aliases pointing to it
+    pub fn remove(&mut self, name: &str) -> Option<YAVS> {
+        let store = self.stores.remove(name)?;
+        self.aliases.retain(|_, target| target != name);
+        Some(store)
+    }
+
+    // Routes `route` to the store named `name`; replaces an existing alias
+    pub fn alias(&mut self, route: &str, name: &str) -> Result<(), YAVSError> {
+        if !self.stores.contains_key(name) {
+            return Err(YAVSError::UnknownRoute(name.to_string()));
+        }
+        self.aliases.insert(route.to_string(), name.to_string());
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.stores.keys().map(String::as_str)
+    }
+
+    // The store a route leads to: an alias first, else a store of that name
+    pub fn get(&self, route: &str) -> Option<&YAVS> {
+        self.stores.get(self.resolve(route))
+    }
+
+    pub fn get_mut(&mut self, route: &str) -> Option<&mut YAVS> {
+        let name = self.resolve(route).to_string();
+        self.stores.get_mut(&name)
+    }
+
+    fn resolve<'a>(&'a self, route: &'a str) -> &'a str {
+        self.aliases.get(route).map_or(route, String::as_str)
+    }
+
+    fn route(&self, route: &str) -> Result<&YAVS, YAVSError> {
+        self.get(route).ok_or_else(|| YAVSError::UnknownRoute(route.to_string()))
+    }
+
+    fn route_mut(&mut self, route: &str) -> Result<&mut YAVS, YAVSError> {
+        self.get_mut(route).ok_or_else(|| YAVSError::UnknownRoute(route.to_string()))
+    }
+
+    pub fn insert(&mut self, route: &str, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.route_mut(route)?.insert(embedding, metadata)
+    }
+
+    pub fn remove_record(&mut self, route: &str, id: &[u8; 16]) -> Result<bool, YAVSError> {
+        Ok(self.route_mut(route)?.remove(id))
+    }
+
+    pub fn query(&self, route: &str, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        self.route(route)?.query(query_embedding, k)
+    }
+
+    // The k best hits across every store with the query's dimension. Fails
+    // with `MetricMismatch` if those stores don't share a metric.
+    pub fn query_all(&self, query_embedding: &[f32], k: usize) -> Result<Vec<RoutedHit<'_>>, YAVSError> {
+        let stores: Vec<(&str, &YAVS)> = self.stores
+            .iter()
+            .filter(|(_, db)| db.dimension() as usize == query_embedding.len())
+            .map(|(name, db)| (name.as_str(), db))
+            .collect();
+        let Some(&(_, first)) = stores.first() else {
+            return Ok(Vec::new());
+        };
+        let metric = first.metric();
+        if stores.iter().any(|(_, db)| db.metric() != metric) {
+            return Err(YAVSError::MetricMismatch);
+        }
+        let mut top = TopK::new(&metric, k);
+        for (name, db) in stores {
+            for (id, score) in db.query(query_embedding, k)? {
+                top.push(id, score, name);
+            }
+        }
+        Ok(top.into_sorted().into_iter().map(|c| RoutedHit { store: c.item, id: c.id, score: c.score }).collect())
+    }
+
+    // Saves each store as `<dir>/<name>.yavs`; aliases aren't saved
+    pub fn save_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), YAVSError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for (name, db) in &mut self.stores {
+            db.save(dir.join(format!("{}.yavs", name)))?;
+        }
+        Ok(())
+    }
+
+    // Loads every `.yavs` file in `dir`, named after the file
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Self, YAVSError> {
+        let mut router = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "yavs") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    router.add(name, YAVS::load_file(&path)?)?;
+                }
+            }
+        }
+        Ok(router)
+    }
+}
+
+// A name that stays a single file inside the directory it is saved to
+fn check_name(name: &str) -> Result<(), YAVSError> {
+    let separator = |c: char| matches!(c, '/' | '\\' | ':' | '\0');
+    if name.is_empty() || name == "." || name == ".." || name.contains(separator) {
+        return Err(YAVSError::InvalidStoreName(name.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::Router;
+    use crate::{test_path, YAVS, YAVSError};
+
+    #[test]
+    fn names_stay_inside_the_directory() {
+        let mut router = Router::new();
+        for name in ["", ".", "..", "../escape", "a/b", "a\\b", "/abs", "C:x"] {
+            assert!(matches!(router.add(name, YAVS::new(2)), Err(YAVSError::InvalidStoreName(_))), "{name:?}");
+        }
+        router.add("en", YAVS::new(2)).unwrap();
+        router.add("de.v2", YAVS::new(2)).unwrap();
+
+        let dir = test_path("router");
+        let _ = fs::remove_dir_all(&dir);
+        router.save_dir(&dir).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let loaded = Router::load_dir(&dir).unwrap();
+        assert_eq!(loaded.names().collect::<Vec<_>>(), vec!["de.v2", "en"]);
+    }
+}
@@ -0,0 +1,92 @@
+// Read-only views restricted to the records matching a filter, e.g. one
+// category or tenant, for handing to code that must not see the rest of the
+// store. Every lookup, query and count goes through the filter. Results are
+// never cached, since arbitrary filters can't be compared.
+
+use std::cell::Cell;
+use crate::cache::Scope;
+use crate::slowlog::QueryRun;
+use crate::{QueryResult, Record, RecordView, StoreStats, YAVS, YAVSError};
+
+pub struct Scoped<'a, F> {
+    db: &'a YAVS,
+    filter: F,
+}
+
+impl YAVS {
+    pub fn scoped<F: Fn(&Record) -> bool>(&self, filter: F) -> Scoped<'_, F> {
+        Scoped { db: self, filter }
+    }
+
+    // Like `query`, but only scores records whose metadata passes `filter`,
+    // so rare matches aren't lost the way they are when over-fetching and
+    // filtering afterwards
+    pub fn query_filtered<F: Fn(&[u8]) -> bool>(&self, query_embedding: &[f32], k: usize, filter: F) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        self.scoped(|r| filter(&r.metadata)).query(query_embedding, k)
+    }
+}
+
+impl<'a, F: Fn(&Record) -> bool> Scoped<'a, F> {
+    pub fn iter(&self) -> impl Iterator<Item = &'a Record> + '_ {
+        self.db.iter().filter(|r| (self.filter)(r))
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = [u8; 16]> + '_ {
+        self.iter().map(|r| r.id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn contains(&self, id: &[u8; 16]) -> bool {
+        self.view(id).is_some()
+    }
+
+    // None unless the live record with this ID matches the filter
+    pub fn view(&self, id: &[u8; 16]) -> Option<RecordView<'a>> {
+        self.db.find(id).filter(|r| (self.filter)(r)).map(RecordView::from)
+    }
+
+    pub fn query(&self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        let db = self.db;
+        if query_embedding.len() as u32 != db.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = db.query_started();
+        let _permit = db.heavy_query_permit()?;
+        let scanned = Cell::new(0);
+        let candidates = self.iter().inspect(|_| scanned.set(scanned.get() + 1));
+        let dists = db.scan_metric(db.metric, candidates, query_embedding, k);
+        db.record_hits(&dists);
+        db.log_query(started, QueryRun {
+            scope: &Scope::Filter,
+            metric: Some(db.metric),
+            query_embedding,
+            k,
+            results: dists.len(),
+            scanned: scanned.get(),
+        });
+        Ok(dists)
+    }
+
+    pub fn query_with_metadata(&self, query_embedding: &[f32], k: usize) -> Result<Vec<QueryResult<'a>>, YAVSError> {
+        let results = self.query(query_embedding, k)?;
+        Ok(results
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let metadata = &self.db.find(&id)?.metadata;
+                Some(QueryResult { id, score, metadata })
+            })
+            .collect())
+    }
+
+    // `YAVS::stats` over the matching records, deleted ones included
+    pub fn stats(&self) -> StoreStats {
+        StoreStats::of(self.db.records.iter().filter(|r| (self.filter)(r)))
+    }
+}
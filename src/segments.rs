@@ -0,0 +1,333 @@
+// Directory-backed store built from immutable segments plus an in-memory
+// memtable, for ingest-heavy workloads where rewriting one flat file per save
+// is too slow.
+//
+// Inserts land in the memtable. `flush` (or reaching the flush threshold)
+// writes it out as a new segment: `seg-<seq>.yavs`, in the native format with
+// records sorted by id, plus an optional `seg-<seq>.del` listing the 16-byte
+// ids it deletes from older segments. Segments are never modified; `merge`
+// folds them into one. The `MANIFEST` file lists the live segments and is
+// replaced atomically, so a crash mid-flush or mid-merge leaves the previous
+// state intact. Unflushed memtable contents are lost on a crash.
+//
+// Memory is not bounded: `open` loads every segment in full, and queries
+// scan them all in memory, so the store needs about as much memory as a flat
+// `YAVS` holding the same records. Segments save rewrite time on ingest, not
+// memory.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::{closest_first, write_atomic, Metric, Record, YAVS, YAVSError};
+
+const MANIFEST: &str = "MANIFEST";
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    db: YAVS,
+}
+
+#[derive(Debug)]
+pub struct SegmentedYAVS {
+    dir: PathBuf,
+    dim: u32,
+    metric: Metric,
+    segments: Vec<Segment>,
+    memtable: YAVS,
+    // Deletions of segment records not yet written to a segment
+    pending_deletes: Vec<[u8; 16]>,
+    next_seq: u64,
+    // memtable size that triggers a flush; 0 flushes only on request
+    flush_threshold: usize,
+    // segment count that triggers a merge after a flush; 0 merges only on request
+    merge_threshold: usize,
+}
+
+impl SegmentedYAVS {
+    // Opens the store in `dir`, creating an empty one if it has no manifest
+    pub fn open<P: AsRef<Path>>(dir: P, dim: u32) -> Result<Self, YAVSError> {
+        Self::open_with_metric(dir, dim, Metric::Euclidean)
+    }
+
+    pub fn open_with_metric<P: AsRef<Path>>(dir: P, dim: u32, metric: Metric) -> Result<Self, YAVSError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let manifest = dir.join(MANIFEST);
+        let seqs = if manifest.exists() {
+            parse_manifest(&fs::read_to_string(&manifest)?)?
+        } else {
+            Vec::new()
+        };
+
+        let mut segments: Vec<Segment> = Vec::with_capacity(seqs.len());
+        for &seq in &seqs {
+            let db = YAVS::load_file(segment_path(&dir, seq))?;
+            if db.dimension() != dim {
+                return Err(YAVSError::DimMismatch);
+            }
+            if db.metric() != metric {
+                return Err(YAVSError::MetricMismatch);
+            }
+            let del_path = deletes_path(&dir, seq);
+            if del_path.exists() {
+                let bytes = fs::read(del_path)?;
+                if bytes.len() % 16 != 0 {
+                    return Err(YAVSError::InvalidFile);
+                }
+                for chunk in bytes.chunks_exact(16) {
+                    let id: [u8; 16] = chunk.try_into().unwrap_or_default();
+                    for older in &mut segments {
+                        older.db.remove(&id);
+                    }
+                }
+            }
+            segments.push(Segment { seq, db });
+        }
+
+        Ok(Self {
+            dir,
+            dim,
+            metric,
+            next_seq: seqs.iter().max().map_or(1, |s| s + 1),
+            segments,
+            memtable: YAVS::with_metric(dim, metric),
+            pending_deletes: Vec::new(),
+            flush_threshold: 10_000,
+            merge_threshold: 8,
+        })
+    }
+
+    pub fn set_flush_threshold(&mut self, records: usize) {
+        self.flush_threshold = records;
+    }
+
+    pub fn set_merge_threshold(&mut self, segments: usize) {
+        self.merge_threshold = segments;
+    }
+
+    pub fn dimension(&self) -> u32 {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.memtable.len() + self.segments.iter().map(|s| s.db.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.insert_tagged(embedding, metadata, &[])
+    }
+
+    pub fn insert_tagged(&mut self, embedding: &[f32], metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        let id = self.memtable.insert_tagged(embedding, metadata, tags)?;
+        if self.flush_threshold > 0 && self.memtable.len() >= self.flush_threshold {
+            self.flush()?;
+        }
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        if self.memtable.remove(id) {
+            return true;
+        }
+        let mut found = false;
+        for segment in &mut self.segments {
+            found |= segment.db.remove(id);
+        }
+        if found {
+            self.pending_deletes.push(*id);
+        }
+        found
+    }
+
+    pub fn query(&self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        let mut results = self.memtable.query(query_embedding, k)?;
+        for segment in &self.segments {
+            results.extend(segment.db.query(query_embedding, k)?);
+        }
+        results.sort_by(|a, b| closest_first(&self.metric, a, b));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    // Warms every segment and the memtable; see `YAVS::warm_up`
+    pub fn warm_up(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|s| s.db.warm_up())
+            .sum::<u64>()
+            + self.memtable.warm_up()
+    }
+
+    // Live records, oldest segment first and the memtable last
+    pub fn iter(&self) -> impl Iterator<Item = &Record> {
+        self.segments
+            .iter()
+            .flat_map(|s| s.db.iter())
+            .chain(self.memtable.iter())
+    }
+
+    // Writes the memtable and pending deletions out as a new segment
+    pub fn flush(&mut self) -> Result<(), YAVSError> {
+        if self.memtable.is_empty() && self.pending_deletes.is_empty() {
+            return Ok(());
+        }
+        let seq = self.next_seq;
+
+        let mut records: Vec<Record> = self.memtable.iter().cloned().collect();
+        records.sort_by_key(|r| r.id);
+        let db = YAVS::from_records(self.dim, self.metric, records);
+
+        if !self.pending_deletes.is_empty() {
+            let bytes: Vec<u8> = self.pending_deletes.concat();
+            write_bytes(&deletes_path(&self.dir, seq), &bytes)?;
+        }
+        db.save_as(segment_path(&self.dir, seq))?;
+
+        let mut seqs: Vec<u64> = self.segments.iter().map(|s| s.seq).collect();
+        seqs.push(seq);
+        self.write_manifest(&seqs)?;
+
+        self.segments.push(Segment { seq, db });
+        self.next_seq += 1;
+        self.memtable = YAVS::with_metric(self.dim, self.metric);
+        self.pending_deletes.clear();
+
+        if self.merge_threshold > 0 && self.segments.len() >= self.merge_threshold {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    // Flushes, then folds every segment into one, dropping deleted records
+    pub fn merge(&mut self) -> Result<(), YAVSError> {
+        self.flush()?;
+        if self.segments.len() <= 1 {
+            return Ok(());
+        }
+        let seq = self.next_seq;
+
+        let mut records: Vec<Record> = self.segments
+            .iter()
+            .flat_map(|s| s.db.iter())
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| r.id);
+        let db = YAVS::from_records(self.dim, self.metric, records);
+
+        db.save_as(segment_path(&self.dir, seq))?;
+        self.write_manifest(&[seq])?;
+
+        let old = std::mem::replace(&mut self.segments, vec![Segment { seq, db }]);
+        self.next_seq += 1;
+        for segment in old {
+            fs::remove_file(segment_path(&self.dir, segment.seq))?;
+            let del_path = deletes_path(&self.dir, segment.seq);
+            if del_path.exists() {
+                fs::remove_file(del_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes, then copies the current segments and manifest into `dir`.
+    // Segments are immutable, so they are hard-linked where possible.
+    pub fn snapshot_to<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), YAVSError> {
+        self.flush()?;
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for segment in &self.segments {
+            for path in [segment_path(&self.dir, segment.seq), deletes_path(&self.dir, segment.seq)] {
+                if !path.exists() {
+                    continue;
+                }
+                let target = dir.join(path.file_name().unwrap_or_default());
+                if fs::hard_link(&path, &target).is_err() {
+                    fs::copy(&path, &target)?;
+                }
+            }
+        }
+        fs::copy(self.dir.join(MANIFEST), dir.join(MANIFEST))?;
+        Ok(())
+    }
+
+    // Writes every live record to a single flat YAVS file
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Result<(), YAVSError> {
+        YAVS::from_records(self.dim, self.metric, self.iter().cloned().collect()).save_as(path)
+    }
+
+    fn write_manifest(&self, seqs: &[u64]) -> Result<(), YAVSError> {
+        let manifest: String = seqs.iter().map(|s| format!("{}\n", s)).collect();
+        write_bytes(&self.dir.join(MANIFEST), manifest.as_bytes())
+    }
+}
+
+fn parse_manifest(manifest: &str) -> Result<Vec<u64>, YAVSError> {
+    manifest
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.trim().parse().map_err(|_| YAVSError::InvalidFile))
+        .collect()
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("seg-{:06}.yavs", seq))
+}
+
+fn deletes_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("seg-{:06}.del", seq))
+}
+
+fn write_bytes(path: &Path, bytes: &[u8]) -> Result<(), YAVSError> {
+    write_atomic(path, 0, |w| Ok(w.write_all(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::SegmentedYAVS;
+    use crate::test_path;
+
+    #[test]
+    fn flush_merge_and_reopen() {
+        let dir = test_path("segments");
+        let _ = fs::remove_dir_all(&dir);
+        let mut db = SegmentedYAVS::open(&dir, 2).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        db.flush().unwrap();
+        // Removing a flushed record is a deletion in the next segment
+        assert!(db.remove(&a));
+        let c = db.insert(&[1.0, 1.0], b"c").unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.segment_count(), 2);
+        // Not flushed, so lost on reopening
+        db.insert(&[0.5, 0.5], b"d").unwrap();
+
+        let mut db = SegmentedYAVS::open(&dir, 2).unwrap();
+        assert_eq!(db.segment_count(), 2);
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.query(&[1.0, 0.0], 3).unwrap().iter().map(|r| r.0).collect::<Vec<_>>(), vec![c, b]);
+
+        db.merge().unwrap();
+        assert_eq!(db.segment_count(), 1);
+        let db = SegmentedYAVS::open(&dir, 2).unwrap();
+        assert_eq!(db.segment_count(), 1);
+        let mut ids: Vec<_> = db.iter().map(|r| r.id).collect();
+        ids.sort();
+        let mut expected = vec![b, c];
+        expected.sort();
+        assert_eq!(ids, expected);
+        // Only the merged segment and the manifest are left
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+}
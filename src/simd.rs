@@ -0,0 +1,184 @@
+// Vectorized kernels behind the built-in metrics. On x86_64 with AVX2 and
+// FMA, detected at runtime, they use explicit 256-bit intrinsics; elsewhere
+// a portable loop over 8 independent lanes, which the compiler vectorizes
+// for whatever the target offers (SSE2, NEON, wasm simd128). Summation order
+// differs from a plain scalar loop, so results can differ in the last bits.
+//
+// Like `zip`, each kernel only looks at the shorter slice's length.
+
+const LANES: usize = 8;
+
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if avx2::available() {
+        // SAFETY: the required CPU features were just detected
+        return unsafe { avx2::dot(a, b) };
+    }
+    portable::dot(a, b)
+}
+
+pub(crate) fn squared_euclidean(a: &[f32], b: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if avx2::available() {
+        // SAFETY: as in `dot`
+        return unsafe { avx2::squared_euclidean(a, b) };
+    }
+    portable::squared_euclidean(a, b)
+}
+
+// (a·b, |a|², |b|²) in one pass, for cosine distance
+pub(crate) fn cosine_terms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    #[cfg(target_arch = "x86_64")]
+    if avx2::available() {
+        // SAFETY: as in `dot`
+        return unsafe { avx2::cosine_terms(a, b) };
+    }
+    portable::cosine_terms(a, b)
+}
+
+mod portable {
+    use super::LANES;
+
+    pub(super) fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let (a, b) = (&a[..n], &b[..n]);
+        let mut acc = [0f32; LANES];
+        for (x, y) in a.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+            for l in 0..LANES {
+                acc[l] += x[l] * y[l];
+            }
+        }
+        let tail = n - n % LANES;
+        let tail: f32 = a[tail..].iter().zip(&b[tail..]).map(|(x, y)| x * y).sum();
+        acc.iter().sum::<f32>() + tail
+    }
+
+    pub(super) fn squared_euclidean(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let (a, b) = (&a[..n], &b[..n]);
+        let mut acc = [0f32; LANES];
+        for (x, y) in a.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+            for l in 0..LANES {
+                let d = x[l] - y[l];
+                acc[l] += d * d;
+            }
+        }
+        let tail = n - n % LANES;
+        let tail: f32 = a[tail..].iter().zip(&b[tail..]).map(|(x, y)| (x - y) * (x - y)).sum();
+        acc.iter().sum::<f32>() + tail
+    }
+
+    pub(super) fn cosine_terms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len().min(b.len());
+        let (a, b) = (&a[..n], &b[..n]);
+        let (mut dot, mut norm_a, mut norm_b) = ([0f32; LANES], [0f32; LANES], [0f32; LANES]);
+        for (x, y) in a.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+            for l in 0..LANES {
+                dot[l] += x[l] * y[l];
+                norm_a[l] += x[l] * x[l];
+                norm_b[l] += y[l] * y[l];
+            }
+        }
+        let (mut dot, mut norm_a, mut norm_b) = (dot.iter().sum::<f32>(), norm_a.iter().sum::<f32>(), norm_b.iter().sum::<f32>());
+        let tail = n - n % LANES;
+        for (x, y) in a[tail..].iter().zip(&b[tail..]) {
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        (dot, norm_a, norm_b)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+    use super::LANES;
+
+    pub(super) fn available() -> bool {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn sum(v: __m256) -> f32 {
+        let halves = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+        let pairs = _mm_add_ps(halves, _mm_movehl_ps(halves, halves));
+        _mm_cvtss_f32(_mm_add_ss(pairs, _mm_shuffle_ps(pairs, pairs, 1)))
+    }
+
+    // Two accumulators per sum hide the FMA latency
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 2 * LANES <= n {
+            // SAFETY: i + 16 <= n, so both loads are in bounds of both slices
+            let (x0, y0, x1, y1) = unsafe {
+                (
+                    _mm256_loadu_ps(pa.add(i)),
+                    _mm256_loadu_ps(pb.add(i)),
+                    _mm256_loadu_ps(pa.add(i + LANES)),
+                    _mm256_loadu_ps(pb.add(i + LANES)),
+                )
+            };
+            acc0 = _mm256_fmadd_ps(x0, y0, acc0);
+            acc1 = _mm256_fmadd_ps(x1, y1, acc1);
+            i += 2 * LANES;
+        }
+        let mut total = unsafe { sum(_mm256_add_ps(acc0, acc1)) };
+        for (x, y) in a[i..n].iter().zip(&b[i..n]) {
+            total += x * y;
+        }
+        total
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn squared_euclidean(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 2 * LANES <= n {
+            // SAFETY: as in `dot`
+            let (d0, d1) = unsafe {
+                (
+                    _mm256_sub_ps(_mm256_loadu_ps(pa.add(i)), _mm256_loadu_ps(pb.add(i))),
+                    _mm256_sub_ps(_mm256_loadu_ps(pa.add(i + LANES)), _mm256_loadu_ps(pb.add(i + LANES))),
+                )
+            };
+            acc0 = _mm256_fmadd_ps(d0, d0, acc0);
+            acc1 = _mm256_fmadd_ps(d1, d1, acc1);
+            i += 2 * LANES;
+        }
+        let mut total = unsafe { sum(_mm256_add_ps(acc0, acc1)) };
+        for (x, y) in a[i..n].iter().zip(&b[i..n]) {
+            total += (x - y) * (x - y);
+        }
+        total
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) unsafe fn cosine_terms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+        let n = a.len().min(b.len());
+        let (pa, pb) = (a.as_ptr(), b.as_ptr());
+        let (mut dot, mut norm_a, mut norm_b) = (_mm256_setzero_ps(), _mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + LANES <= n {
+            // SAFETY: i + 8 <= n, so both loads are in bounds
+            let (x, y) = unsafe { (_mm256_loadu_ps(pa.add(i)), _mm256_loadu_ps(pb.add(i))) };
+            dot = _mm256_fmadd_ps(x, y, dot);
+            norm_a = _mm256_fmadd_ps(x, x, norm_a);
+            norm_b = _mm256_fmadd_ps(y, y, norm_b);
+            i += LANES;
+        }
+        let (mut dot, mut norm_a, mut norm_b) = unsafe { (sum(dot), sum(norm_a), sum(norm_b)) };
+        for (x, y) in a[i..n].iter().zip(&b[i..n]) {
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        (dot, norm_a, norm_b)
+    }
+}
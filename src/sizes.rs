@@ -0,0 +1,40 @@
+// Per-record size accounting, for finding the few records (usually giant
+// metadata blobs) that dominate a store's file, and an optional cap on
+// metadata length enforced on insert.
+
+use crate::format::serialized_size;
+use crate::{YAVS, YAVSError};
+
+impl YAVS {
+    // Bytes the live record adds to the saved file, section entries included
+    // (store-wide index sections aren't attributed to records)
+    pub fn record_size(&self, id: &[u8; 16]) -> Option<u64> {
+        self.find(id).map(|r| serialized_size(r, self.encoding()))
+    }
+
+    // The `n` live records that take the most space, largest first
+    pub fn largest_records(&self, n: usize) -> Vec<([u8; 16], u64)> {
+        let mut sizes: Vec<([u8; 16], u64)> = self.iter().map(|r| (r.id, serialized_size(r, self.encoding()))).collect();
+        sizes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        sizes.truncate(n);
+        sizes
+    }
+
+    // Rejects inserts (after insert hooks run) and metadata rewrites whose
+    // metadata is longer than `max` bytes with `MetadataTooLarge`. Records
+    // already in the store are left alone. None removes the limit.
+    pub fn set_max_metadata_len(&mut self, max: Option<usize>) {
+        self.max_metadata_len = max;
+    }
+
+    pub fn max_metadata_len(&self) -> Option<usize> {
+        self.max_metadata_len
+    }
+
+    pub(crate) fn check_metadata_len(&self, metadata: &[u8]) -> Result<(), YAVSError> {
+        match self.max_metadata_len {
+            Some(max) if metadata.len() > max => Err(YAVSError::MetadataTooLarge),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// Optional slow-query log. Queries taking at least the threshold (by the
+// store clock) are kept in a bounded ring buffer and passed to a hook, so
+// they can be inspected without tracing infrastructure. Query vectors are
+// recorded by hash only.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::cache::Scope;
+use crate::{Metric, YAVS};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    // FNV-1a over the query vector's bits
+    pub query_hash: u64,
+    // Restriction the query ran under: "" for the whole store, otherwise
+    // e.g. "tags:...", "tenant:...", "ivf:<nprobe>" or "pq:<candidates>"
+    pub scope: String,
+    // None for a caller-supplied distance
+    pub metric: Option<Metric>,
+    pub k: usize,
+    pub results: usize,
+    // Records scored, counting quantized codes; 0 for cached results
+    pub scanned: usize,
+    pub latency_ms: u64,
+    // Store clock time the query finished at
+    pub at_ms: u64,
+}
+
+pub trait SlowQueryHook: Debug + Send + Sync {
+    fn on_slow_query(&self, query: &SlowQuery);
+}
+
+impl<H: SlowQueryHook + ?Sized> SlowQueryHook for Arc<H> {
+    fn on_slow_query(&self, query: &SlowQuery) {
+        (**self).on_slow_query(query)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SlowQueryLog {
+    // None while the log is off
+    threshold_ms: Option<u64>,
+    capacity: usize,
+    entries: Mutex<VecDeque<SlowQuery>>,
+    hook: Option<Box<dyn SlowQueryHook>>,
+}
+
+impl SlowQueryLog {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<SlowQuery>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Details of a finished query, for `log_query`
+pub(crate) struct QueryRun<'a> {
+    pub(crate) scope: &'a Scope<'a>,
+    pub(crate) metric: Option<Metric>,
+    pub(crate) query_embedding: &'a [f32],
+    pub(crate) k: usize,
+    pub(crate) results: usize,
+    pub(crate) scanned: usize,
+}
+
+fn query_hash(query: &[f32]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in query.iter().flat_map(|v| v.to_bits().to_le_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+impl YAVS {
+    // Logs queries taking at least `threshold_ms`, keeping the latest
+    // `capacity` of them; with a capacity of 0 they only go to the hook.
+    pub fn set_slow_query_log(&mut self, threshold_ms: u64, capacity: usize) {
+        self.slow_log.threshold_ms = Some(threshold_ms);
+        self.slow_log.capacity = capacity;
+        let mut entries = self.slow_log.lock();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+
+    // Turns the log off and discards its entries; the hook is kept
+    pub fn disable_slow_query_log(&mut self) {
+        self.slow_log.threshold_ms = None;
+        self.slow_log.lock().clear();
+    }
+
+    // Called with every logged query, on the querying thread
+    pub fn set_slow_query_hook<H: SlowQueryHook + 'static>(&mut self, hook: H) {
+        self.slow_log.hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_slow_query_hook(&mut self) {
+        self.slow_log.hook = None;
+    }
+
+    // Logged queries, oldest first
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.slow_log.lock().iter().cloned().collect()
+    }
+
+    pub fn clear_slow_queries(&self) {
+        self.slow_log.lock().clear();
+    }
+
+    // Start time to pass to `log_query`, or None while the log is off
+    pub(crate) fn query_started(&self) -> Option<u64> {
+        self.slow_log.threshold_ms.map(|_| self.clock.now_ms())
+    }
+
+    pub(crate) fn log_query(&self, started: Option<u64>, run: QueryRun<'_>) {
+        let (Some(started), Some(threshold_ms)) = (started, self.slow_log.threshold_ms) else {
+            return;
+        };
+        let at_ms = self.clock.now_ms();
+        let latency_ms = at_ms.saturating_sub(started);
+        if latency_ms < threshold_ms {
+            return;
+        }
+        let entry = SlowQuery {
+            query_hash: query_hash(run.query_embedding),
+            scope: run.scope.describe(),
+            metric: run.metric,
+            k: run.k,
+            results: run.results,
+            scanned: run.scanned,
+            latency_ms,
+            at_ms,
+        };
+        if let Some(hook) = &self.slow_log.hook {
+            hook.on_slow_query(&entry);
+        }
+        if self.slow_log.capacity > 0 {
+            let mut entries = self.slow_log.lock();
+            if entries.len() >= self.slow_log.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+}
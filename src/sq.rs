@@ -0,0 +1,303 @@
+// Optional scalar (int8) quantization. Each dimension is mapped linearly onto
+// 256 levels between the smallest and largest value seen in training, so
+// codes take a quarter of the f32 embeddings' memory and `query_sq` scans far
+// less data, scoring the codes as they are. Results can be re-scored exactly
+// against the full embeddings.
+//
+// Like the PQ codes, the int8 codes follow record positions and only the
+// per-dimension offsets and scales are saved; codes are recomputed on load.
+// Under `Precision::Int8` the records hold their own codes instead, in place
+// of their embeddings, and those are what is saved.
+
+use crate::format::read_u32;
+use crate::{Metric, Precision, Record, YAVS, YAVSError};
+
+#[derive(Debug)]
+pub(crate) struct ScalarQuantizer {
+    // Per dimension: value of code -128, and the step between levels
+    offset: Vec<f32>,
+    scale: Vec<f32>,
+    // `offset.len()` codes for each record position, each an i8 kept as its
+    // byte, as in `Record::code`
+    codes: Vec<u8>,
+}
+
+impl ScalarQuantizer {
+    pub(crate) fn train<E, I>(embeddings: I, dim: usize) -> Self
+    where
+        E: AsRef<[f32]>,
+        I: IntoIterator<Item = E>,
+    {
+        let mut min = vec![f32::INFINITY; dim];
+        let mut max = vec![f32::NEG_INFINITY; dim];
+        for embedding in embeddings {
+            for ((lo, hi), &v) in min.iter_mut().zip(max.iter_mut()).zip(embedding.as_ref()) {
+                if v.is_finite() {
+                    *lo = lo.min(v);
+                    *hi = hi.max(v);
+                }
+            }
+        }
+        // Dimensions with no finite values (or only one) get a unit range
+        let (offset, scale) = min
+            .into_iter()
+            .zip(max)
+            .map(|(lo, hi)| {
+                if lo.is_finite() && hi > lo {
+                    (lo, (hi - lo) / 255.0)
+                } else if lo.is_finite() {
+                    (lo, 1.0 / 255.0)
+                } else {
+                    (0.0, 1.0 / 255.0)
+                }
+            })
+            .unzip();
+        Self {
+            offset,
+            scale,
+            codes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.codes.clear();
+    }
+
+    pub(crate) fn encode(&mut self, pos: usize, embedding: &[f32]) {
+        let dim = self.offset.len();
+        if self.codes.len() < (pos + 1) * dim {
+            self.codes.resize((pos + 1) * dim, 0);
+        }
+        let code = self.quantize(embedding);
+        self.codes[pos * dim..(pos + 1) * dim].copy_from_slice(&code);
+    }
+
+    pub(crate) fn quantize(&self, embedding: &[f32]) -> Vec<u8> {
+        embedding
+            .iter()
+            .enumerate()
+            .map(|(d, &v)| {
+                // Values outside the trained range saturate; NaN maps to the bottom
+                let level = ((v - self.offset[d]) / self.scale[d]).round().clamp(0.0, 255.0);
+                (level as i32 - 128) as i8 as u8
+            })
+            .collect()
+    }
+
+    pub(crate) fn relocate(&mut self, from: usize, to: usize) {
+        let dim = self.offset.len();
+        if self.codes.len() >= (from + 1) * dim {
+            self.codes.copy_within(from * dim..(from + 1) * dim, to * dim);
+        }
+        self.codes.truncate(from.max(to) * dim);
+    }
+
+    pub(crate) fn code(&self, pos: usize) -> &[u8] {
+        let dim = self.offset.len();
+        &self.codes[pos * dim..(pos + 1) * dim]
+    }
+
+    // The embedding a code stands for: each level's value
+    pub(crate) fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .enumerate()
+            .map(|(d, &c)| self.offset[d] + self.scale[d] * (c as i8 as i32 + 128) as f32)
+            .collect()
+    }
+
+    // Score of each code against `query` under `metric`, computed on the
+    // codes: a value is `base + scale * c` for its code c, so with per-
+    // dimension terms taken from the query up front a code costs a multiply-
+    // add or two per dimension and is never decoded
+    pub(crate) fn scorer(&self, query: &[f32], metric: Metric) -> impl Fn(&[u8]) -> f32 + '_ {
+        let base: Vec<f32> = self.offset.iter().zip(&self.scale).map(|(o, s)| o + 128.0 * s).collect();
+        // Dot: the query against the bases, and the query scaled per dimension
+        let dot_base = query.iter().zip(&base).map(|(q, b)| q * b).sum::<f32>();
+        let weights: Vec<f32> = query.iter().zip(&self.scale).map(|(q, s)| q * s).collect();
+        // Euclidean: the query's offset from each base
+        let residual: Vec<f32> = query.iter().zip(&base).map(|(q, b)| q - b).collect();
+        let query_norm = query.iter().map(|x| x * x).sum::<f32>();
+        move |code: &[u8]| {
+            let dot = || dot_base + weights.iter().zip(code).map(|(w, &c)| w * (c as i8) as f32).sum::<f32>();
+            match metric {
+                Metric::Euclidean => residual
+                    .iter()
+                    .zip(&self.scale)
+                    .zip(code)
+                    .map(|((r, s), &c)| {
+                        let d = r - s * (c as i8) as f32;
+                        d * d
+                    })
+                    .sum::<f32>()
+                    .sqrt(),
+                Metric::Cosine => {
+                    let norm = base
+                        .iter()
+                        .zip(&self.scale)
+                        .zip(code)
+                        .map(|((b, s), &c)| {
+                            let v = b + s * (c as i8) as f32;
+                            v * v
+                        })
+                        .sum::<f32>();
+                    let norms = (query_norm * norm).sqrt();
+                    if norms == 0.0 {
+                        1.0
+                    } else {
+                        1.0 - dot() / norms
+                    }
+                }
+                Metric::Dot => dot(),
+            }
+        }
+    }
+
+    pub(crate) fn dim(&self) -> usize {
+        self.offset.len()
+    }
+
+    pub(crate) fn params_size(&self) -> u64 {
+        4 + 8 * self.offset.len() as u64
+    }
+
+    // Section payload: dimension, offsets, scales
+    pub(crate) fn encode_params(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.offset.len() as u32).to_le_bytes());
+        for v in self.offset.iter().chain(&self.scale) {
+            payload.extend_from_slice(&v.to_le_bytes());
+        }
+        payload
+    }
+
+    pub(crate) fn decode_params(payload: &mut &[u8]) -> Result<Self, YAVSError> {
+        let dim = read_u32(payload)? as usize;
+        if dim as u64 * 8 != payload.len() as u64 {
+            return Err(YAVSError::InvalidFile);
+        }
+        let mut values = Vec::with_capacity(2 * dim);
+        for _ in 0..2 * dim {
+            values.push(f32::from_bits(read_u32(payload)?));
+        }
+        let scale = values.split_off(dim);
+        Ok(Self {
+            offset: values,
+            scale,
+            codes: Vec::new(),
+        })
+    }
+}
+
+impl YAVS {
+    // Fits per-dimension int8 ranges to the live records and encodes every
+    // record; new records are encoded on insert. Returns false, training
+    // nothing, for an empty store. Under Int8 precision the records are
+    // refitted from their decoded codes and encoded again.
+    pub fn train_sq(&mut self) -> bool {
+        let quantized = self.precision == Precision::Int8;
+        if quantized {
+            self.decode_sq();
+        }
+        let live: Vec<&Record> = self.iter().collect();
+        self.sq = (!live.is_empty()).then(|| ScalarQuantizer::train(live.iter().map(|r| self.vector(r)), self.dim as usize));
+        if quantized && self.sq.is_some() {
+            self.precision = Precision::Int8;
+            let mut records = std::mem::take(&mut self.records);
+            for rec in &mut records {
+                self.fit_precision(rec);
+            }
+            self.records = records;
+        }
+        self.rebuild_indexes();
+        self.mark_changed();
+        self.sq.is_some()
+    }
+
+    // Under Int8 precision the records are decoded to f32 first
+    pub fn drop_sq(&mut self) {
+        self.decode_sq();
+        if self.sq.take().is_some() {
+            self.mark_changed();
+        }
+    }
+
+    fn decode_sq(&mut self) {
+        if self.precision == Precision::Int8 {
+            let _ = self.set_precision(Precision::F32);
+        }
+    }
+
+    pub fn has_sq(&self) -> bool {
+        self.sq.is_some()
+    }
+
+    // Approximate `query` over the int8 codes, with `rerank` as for `query_pq`.
+    // Without trained ranges this is just `query`, and under Int8 precision
+    // it ranks as `query` does.
+    pub fn query_sq(&self, query_embedding: &[f32], k: usize, rerank: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        let Some(sq) = &self.sq else {
+            return self.query(query_embedding, k);
+        };
+        let _permit = self.heavy_query_permit()?;
+        let score = sq.scorer(query_embedding, self.metric);
+        Ok(self.query_approx(started, "sq", query_embedding, k, rerank, |i| score(self.sq_code(sq, i))))
+    }
+
+    // The int8 code of the record at `pos`, its own under Int8 precision
+    pub(crate) fn sq_code<'a>(&'a self, sq: &'a ScalarQuantizer, pos: usize) -> &'a [u8] {
+        match self.precision {
+            Precision::Int8 => &self.records[pos].code,
+            _ => sq.code(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_path, test_recall, test_vectors, Precision, YAVS};
+
+    #[test]
+    fn int8_precision_round_trip_and_ranking() {
+        let path = test_path("sq-precision.yavs");
+        let mut db = YAVS::new(16);
+        let vectors = test_vectors(1000, 16, 7);
+        for v in &vectors[..900] {
+            db.insert(v, b"").unwrap();
+        }
+        let queries = test_vectors(20, 16, 8);
+        assert!(db.train_sq());
+        db.set_precision(Precision::Int8).unwrap();
+        // Inserted after the switch, so encoded on the way in
+        for v in &vectors[900..] {
+            db.insert(v, b"").unwrap();
+        }
+        assert!(db.iter().all(|r| r.embedding.is_empty() && r.code.len() == 16));
+        // Within a level of the original, over the range [-1, 1) trained on
+        for (rec, v) in db.iter().zip(&vectors) {
+            let decoded = db.decoded_embedding(&rec.id).unwrap();
+            assert!(decoded.iter().zip(v).all(|(a, b)| (a - b).abs() <= 2.0 / 255.0 + 1e-6));
+        }
+        let ranked: Vec<_> = queries.iter().map(|q| db.query(q, 10).unwrap()).collect();
+
+        db.save(&path).unwrap();
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.precision(), Precision::Int8);
+        for (a, b) in db.iter().zip(loaded.iter()) {
+            assert_eq!((a.id, &a.code), (b.id, &b.code));
+        }
+        for (q, r) in queries.iter().zip(&ranked) {
+            assert_eq!(&loaded.query(q, 10).unwrap(), r);
+        }
+
+        let mut exact = YAVS::new(16);
+        for (rec, v) in db.iter().zip(&vectors) {
+            exact.insert_with_id(&rec.id, v, b"").unwrap();
+        }
+        let recall = queries.iter().zip(&ranked).map(|(q, r)| test_recall(r, &exact.query(q, 10).unwrap())).sum::<f32>() / queries.len() as f32;
+        assert!(recall >= 0.9, "recall {recall}");
+    }
+}
@@ -0,0 +1,107 @@
+// Tenant-scoped access. Every record inserted through a `Tenant` handle is
+// owned by that tenant, and the handle can only see, query and delete its own
+// records, so application code can't leak data across tenants by forgetting
+// a filter.
+
+use crate::cache::Scope;
+use crate::{NewRecord, Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    pub records: usize,
+    pub metadata_bytes: usize,
+}
+
+pub struct Tenant<'a> {
+    db: &'a mut YAVS,
+    name: String,
+}
+
+impl YAVS {
+    pub fn tenant(&mut self, name: &str) -> Tenant<'_> {
+        Tenant {
+            db: self,
+            name: name.to_string(),
+        }
+    }
+
+    // All tenants with live records, with their record counts
+    pub fn tenants(&self) -> Vec<(&str, usize)> {
+        self.live_counts(&self.tenant_index)
+    }
+
+    pub fn query_tenant(&self, tenant: &str, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        if query_embedding.len() as u32 != self.dim {
+            return Err(YAVSError::DimMismatch);
+        }
+        let started = self.query_started();
+        Ok(self.rank(started, Scope::Tenant(tenant), self.metric, self.tenant_records(tenant), query_embedding, k))
+    }
+
+    pub fn tenant_stats(&self, tenant: &str) -> TenantStats {
+        let mut stats = TenantStats::default();
+        for rec in self.tenant_records(tenant) {
+            stats.records += 1;
+            stats.metadata_bytes += rec.metadata.len();
+        }
+        stats
+    }
+
+    fn tenant_records<'a>(&'a self, tenant: &'a str) -> impl Iterator<Item = &'a Record> + 'a {
+        self.tenant_index
+            .get(tenant)
+            .iter()
+            .map(|&i| &self.records[i])
+            .filter(|r| !r.deleted)
+    }
+}
+
+impl Tenant<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<[u8; 16], YAVSError> {
+        self.insert_tagged(embedding, metadata, &[])
+    }
+
+    pub fn insert_tagged(&mut self, embedding: &[f32], metadata: &[u8], tags: &[&str]) -> Result<[u8; 16], YAVSError> {
+        let new = NewRecord {
+            tags,
+            tenant: Some(&self.name),
+            ..Default::default()
+        };
+        self.db.insert_record(embedding.into(), metadata.into(), new)
+    }
+
+    pub fn query(&self, query_embedding: &[f32], k: usize) -> Result<Vec<([u8; 16], f32)>, YAVSError> {
+        self.db.query_tenant(&self.name, query_embedding, k)
+    }
+
+    // Removes the record only if it belongs to this tenant
+    pub fn remove(&mut self, id: &[u8; 16]) -> bool {
+        match self.db.find(id) {
+            Some(rec) if rec.tenant.as_deref() == Some(self.name.as_str()) => self.db.remove(id),
+            _ => false,
+        }
+    }
+
+    pub fn ids(&self) -> Vec<[u8; 16]> {
+        self.db.tenant_records(&self.name).map(|r| r.id).collect()
+    }
+
+    pub fn stats(&self) -> TenantStats {
+        self.db.tenant_stats(&self.name)
+    }
+
+    // Deletes every record owned by this tenant; returns how many were removed
+    pub fn clear(&mut self) -> usize {
+        let now = self.db.clock.now_ms();
+        let positions = self.db.tenant_index.get(&self.name).to_vec();
+        let removed = self.db.remove_positions(&positions, now);
+        if removed > 0 {
+            self.db.maybe_compact();
+        }
+        removed
+    }
+}
@@ -0,0 +1,109 @@
+// Optional limit on concurrent heavy queries, for services sharing one store
+// between many threads. Scans over the whole store (`query` without an HNSW
+// index, `query_with_metric`, `query_with`, `query_within`, `query_pq`,
+// `query_sq`, `Scoped::query`) need a slot; once all are taken, further scans either wait for one or fail with
+// `Busy`, so a burst of them can't occupy every thread. Cached results still
+// take a slot.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use crate::{YAVS, YAVSError};
+
+// What a heavy query does when every slot is taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueryOverflow {
+    // Block until a running query finishes
+    #[default]
+    Queue,
+    // Fail with `Busy` straight away
+    Reject,
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryLimiter {
+    max: usize,
+    overflow: QueryOverflow,
+    running: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl QueryLimiter {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Holds a slot until dropped
+pub(crate) struct QueryPermit<'a>(&'a QueryLimiter);
+
+impl Drop for QueryPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.lock() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl YAVS {
+    // Allows at most `max` heavy queries at once; 0 removes the limit
+    pub fn set_query_concurrency(&mut self, max: usize, overflow: QueryOverflow) {
+        self.query_limit = (max > 0).then(|| QueryLimiter {
+            max,
+            overflow,
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+        });
+    }
+
+    pub fn query_concurrency(&self) -> Option<(usize, QueryOverflow)> {
+        self.query_limit.as_ref().map(|l| (l.max, l.overflow))
+    }
+
+    // Heavy queries currently holding a slot
+    pub fn running_queries(&self) -> usize {
+        self.query_limit.as_ref().map_or(0, |l| *l.lock())
+    }
+
+    pub(crate) fn heavy_query_permit(&self) -> Result<Option<QueryPermit<'_>>, YAVSError> {
+        let Some(limiter) = &self.query_limit else {
+            return Ok(None);
+        };
+        let mut running = limiter.lock();
+        while *running >= limiter.max {
+            if limiter.overflow == QueryOverflow::Reject {
+                return Err(YAVSError::Busy);
+            }
+            running = limiter.freed.wait(running).unwrap_or_else(|e| e.into_inner());
+        }
+        *running += 1;
+        Ok(Some(QueryPermit(limiter)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryOverflow;
+    use crate::{test_vectors, YAVS, YAVSError};
+
+    #[test]
+    fn full_scans_need_a_slot() {
+        let mut db = YAVS::new(4);
+        for v in test_vectors(300, 4, 9) {
+            db.insert(&v, b"").unwrap();
+        }
+        assert!(db.train_pq(2));
+        assert!(db.train_sq());
+        db.set_query_concurrency(1, QueryOverflow::Reject);
+        let q = [0.5, 0.5, 0.5, 0.5];
+
+        let held = db.heavy_query_permit().unwrap();
+        assert!(matches!(db.query(&q, 3), Err(YAVSError::Busy)));
+        assert!(matches!(db.query_within(&q, 3, 1_000), Err(YAVSError::Busy)));
+        assert!(matches!(db.query_pq(&q, 3, 0), Err(YAVSError::Busy)));
+        assert!(matches!(db.query_sq(&q, 3, 0), Err(YAVSError::Busy)));
+        drop(held);
+
+        assert_eq!(db.query_within(&q, 3, 1_000).unwrap().results.len(), 3);
+        assert_eq!(db.query_pq(&q, 3, 10).unwrap().len(), 3);
+        assert_eq!(db.query_sq(&q, 3, 10).unwrap().len(), 3);
+        assert_eq!(db.running_queries(), 0);
+    }
+}
@@ -0,0 +1,27 @@
+// Approximate equality for embeddings that went through a lossy path, such as
+// quantization or a text round trip, and may differ in their last bits.
+
+use crate::Record;
+
+// Same length and every component within `eps`. Bitwise-equal components
+// always match, so NaNs and infinities compare equal to themselves.
+pub fn embeddings_equal(a: &[f32], b: &[f32], eps: f32) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(x, y)| x.to_bits() == y.to_bits() || (x - y).abs() <= eps)
+}
+
+// Same id and content, with embeddings compared via `embeddings_equal` and
+// codes exactly. Bookkeeping such as timestamps, pins and deletion state is
+// ignored.
+pub fn records_equal(a: &Record, b: &Record, eps: f32) -> bool {
+    a.id == b.id
+        && embeddings_equal(&a.embedding, &b.embedding, eps)
+        && a.code == b.code
+        && a.metadata == b.metadata
+        && a.tags == b.tags
+        && a.tenant == b.tenant
+        && a.key == b.key
+        && a.provenance == b.provenance
+}
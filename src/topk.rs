@@ -0,0 +1,85 @@
+// Bounded top-k selection for the scans: a max-heap of the k closest
+// candidates seen so far, worst on top, so a scan takes O(k) memory and
+// O(n log k) time instead of collecting and sorting every distance. Order is
+// the same as `closest_first`: NaN last, ties broken by ID.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use crate::metric::compare_ordered;
+use crate::Distance;
+
+pub(crate) struct TopK<T> {
+    k: usize,
+    larger_is_closer: bool,
+    heap: BinaryHeap<Candidate<T>>,
+}
+
+pub(crate) struct Candidate<T> {
+    larger_is_closer: bool,
+    pub(crate) id: [u8; 16],
+    pub(crate) score: f32,
+    // Carried along, e.g. the record position; not part of the order
+    pub(crate) item: T,
+}
+
+impl<T> TopK<T> {
+    pub(crate) fn new<D: Distance + ?Sized>(distance: &D, k: usize) -> Self {
+        Self {
+            k,
+            larger_is_closer: distance.larger_is_closer(),
+            heap: BinaryHeap::with_capacity(k.saturating_add(1).min(1 << 16)),
+        }
+    }
+
+    pub(crate) fn push(&mut self, id: [u8; 16], score: f32, item: T) {
+        let candidate = Candidate {
+            larger_is_closer: self.larger_is_closer,
+            id,
+            score,
+            item,
+        };
+        if self.heap.len() < self.k {
+            self.heap.push(candidate);
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if candidate < *worst {
+                *worst = candidate;
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn extend(&mut self, other: TopK<T>) {
+        for c in other.heap {
+            self.push(c.id, c.score, c.item);
+        }
+    }
+
+    // Closest first
+    pub(crate) fn into_sorted(self) -> Vec<Candidate<T>> {
+        self.heap.into_sorted_vec()
+    }
+
+    pub(crate) fn into_results(self) -> Vec<([u8; 16], f32)> {
+        self.into_sorted().into_iter().map(|c| (c.id, c.score)).collect()
+    }
+}
+
+impl<T> Ord for Candidate<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_ordered(self.larger_is_closer, self.score, other.score).then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl<T> PartialOrd for Candidate<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> PartialEq for Candidate<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Candidate<T> {}
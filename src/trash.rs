@@ -0,0 +1,97 @@
+// Undoing deletions. Removed records stay as tombstones, in memory and in
+// saved files, until compaction purges them, and until then they can be
+// listed and restored. `TrashPolicy` keeps recent deletions restorable
+// through `compact`, `compact_step` and auto-compaction; `vacuum` ignores it.
+// Pinned tombstones are never purged (see `pin`).
+
+use crate::{Record, YAVS, YAVSError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrashPolicy {
+    // Deleted records younger than this (by the store clock) survive compaction
+    pub retain_ms: u64,
+}
+
+impl YAVS {
+    pub fn set_trash_policy(&mut self, policy: TrashPolicy) {
+        self.trash = policy;
+    }
+
+    pub fn list_deleted(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter().filter(|r| r.deleted)
+    }
+
+    // Undeletes the most recently deleted record with this ID
+    pub fn restore(&mut self, id: &[u8; 16]) -> Result<(), YAVSError> {
+        if self.position(id).is_some() {
+            return Err(YAVSError::DuplicateId);
+        }
+        let i = self.latest_tombstone(id).ok_or(YAVSError::NotFound)?;
+        if let Some(key) = &self.records[i].key {
+            if self.id_for_key(key).is_some() {
+                return Err(YAVSError::DuplicateId);
+            }
+        }
+        let now = self.clock.now_ms();
+        self.change_record(i, |r| {
+            r.deleted = false;
+            r.updated_at = now;
+        })?;
+        self.id_index.insert(*id, i);
+        if let Some(key) = &self.records[i].key {
+            self.key_index.insert(key.clone(), i);
+        }
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Position of the most recently deleted record with this ID, the one
+    // `restore` brings back
+    pub(crate) fn latest_tombstone(&self, id: &[u8; 16]) -> Option<usize> {
+        self.records
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.deleted && &r.id == id)
+            .max_by_key(|(_, r)| r.updated_at)
+            .map(|(i, _)| i)
+    }
+
+    // Whether compaction may drop this record now
+    pub(crate) fn purgeable(&self, rec: &Record, now: u64) -> bool {
+        rec.deleted && !rec.pinned && now.saturating_sub(rec.updated_at) >= self.trash.retain_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_path, YAVS};
+
+    #[test]
+    fn tombstones_survive_save_and_load() {
+        let path = test_path("trash-tombstones.yavs");
+        let mut db = YAVS::new(2);
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        db.add_tag(&a, "t");
+        assert!(db.remove(&a));
+        db.save(&path).unwrap();
+
+        // Saved twice over, so loading doesn't compact either
+        let mut loaded = YAVS::load_file(&path).unwrap();
+        loaded.save(&path).unwrap();
+        let mut loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.list_deleted().map(|r| r.id).collect::<Vec<_>>(), vec![a]);
+        assert_eq!(loaded.get(&a).unwrap().updated_at, db.get(&a).unwrap().updated_at);
+        assert_eq!(loaded.query(&[1.0, 0.0], 2).unwrap().iter().map(|r| r.0).collect::<Vec<_>>(), vec![b]);
+
+        loaded.restore(&a).unwrap();
+        assert!(loaded.get(&a).unwrap().tags.contains("t"));
+        assert!(loaded.remove(&a));
+        loaded.vacuum();
+        loaded.save(&path).unwrap();
+        let loaded = YAVS::load_file(&path).unwrap();
+        assert_eq!(loaded.list_deleted().count(), 0);
+        assert!(loaded.get(&a).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+// Data-quality rules checked on insert and update, so they live with the
+// store rather than in every ingestion path. Each validator is added with
+// what to do about a record it flags: reject the write, or repair the record
+// and carry on (rejecting after all if it can't be repaired). Validators run
+// in the order they were added, after the insert hooks.
+
+use std::fmt::Debug;
+use crate::{YAVS, YAVSError};
+
+pub trait Validator: Debug + Send + Sync {
+    // Why the record is invalid, or None if it passes
+    fn validate(&self, embedding: &[f32], metadata: &[u8]) -> Option<String>;
+
+    // Fixes a record `validate` flagged; returns false if it can't be fixed
+    fn repair(&self, _embedding: &mut Vec<f32>, _metadata: &mut Vec<u8>) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnInvalid {
+    // Fail the write with `InvalidRecord`
+    #[default]
+    Reject,
+    // Repair the record with `Validator::repair`
+    Repair,
+}
+
+// Embeddings with an L2 norm above the limit; repaired by scaling down to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxNorm(pub f32);
+
+impl Validator for MaxNorm {
+    fn validate(&self, embedding: &[f32], _: &[u8]) -> Option<String> {
+        let norm = norm(embedding);
+        (norm > self.0).then(|| format!("norm {} exceeds {}", norm, self.0))
+    }
+
+    fn repair(&self, embedding: &mut Vec<f32>, _: &mut Vec<u8>) -> bool {
+        let norm = norm(embedding);
+        if !norm.is_finite() {
+            return false;
+        }
+        let scale = self.0 / norm;
+        embedding.iter_mut().for_each(|v| *v *= scale);
+        true
+    }
+}
+
+fn norm(embedding: &[f32]) -> f32 {
+    embedding.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+// Embeddings with NaN or infinite values, or (unless allowed) all zeros,
+// which usually mean a failed embedder call. Non-finite values are repaired
+// to 0; an all-zero embedding can't be repaired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FiniteValues {
+    pub allow_zero: bool,
+}
+
+impl Validator for FiniteValues {
+    fn validate(&self, embedding: &[f32], _: &[u8]) -> Option<String> {
+        if let Some(i) = embedding.iter().position(|v| !v.is_finite()) {
+            return Some(format!("non-finite value at {}", i));
+        }
+        (!self.allow_zero && embedding.iter().all(|&v| v == 0.0)).then(|| "all-zero embedding".to_string())
+    }
+
+    fn repair(&self, embedding: &mut Vec<f32>, _: &mut Vec<u8>) -> bool {
+        embedding.iter_mut().filter(|v| !v.is_finite()).for_each(|v| *v = 0.0);
+        self.allow_zero || embedding.iter().any(|&v| v != 0.0)
+    }
+}
+
+// Metadata that isn't a JSON object with every one of these fields
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonFields(pub Vec<String>);
+
+impl Validator for JsonFields {
+    fn validate(&self, _: &[f32], metadata: &[u8]) -> Option<String> {
+        let value: serde_json::Value = match serde_json::from_slice(metadata) {
+            Ok(value) => value,
+            Err(e) => return Some(format!("metadata is not JSON: {}", e)),
+        };
+        let Some(object) = value.as_object() else {
+            return Some("metadata is not a JSON object".to_string());
+        };
+        let missing: Vec<&str> = self.0.iter().filter(|f| !object.contains_key(*f)).map(|f| f.as_str()).collect();
+        (!missing.is_empty()).then(|| format!("metadata lacks {}", missing.join(", ")))
+    }
+}
+
+// A closure returning why a record is invalid, or None; never repairs
+pub struct ValidateFn<F>(pub F);
+
+impl<F> Debug for ValidateFn<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ValidateFn")
+    }
+}
+
+impl<F> Validator for ValidateFn<F>
+where
+    F: Fn(&[f32], &[u8]) -> Option<String> + Send + Sync,
+{
+    fn validate(&self, embedding: &[f32], metadata: &[u8]) -> Option<String> {
+        (self.0)(embedding, metadata)
+    }
+}
+
+impl YAVS {
+    pub fn add_validator<V: Validator + 'static>(&mut self, validator: V, on_invalid: OnInvalid) {
+        self.validators.push((Box::new(validator), on_invalid));
+    }
+
+    pub fn clear_validators(&mut self) {
+        self.validators.clear();
+    }
+
+    // Checks a record about to be written, repairing it where allowed. A
+    // repair that changes the dimension counts as a failure.
+    pub(crate) fn run_validators(&self, embedding: &mut Vec<f32>, metadata: &mut Vec<u8>) -> Result<(), YAVSError> {
+        for (validator, on_invalid) in &self.validators {
+            let Some(reason) = validator.validate(embedding, metadata) else {
+                continue;
+            };
+            let repaired = *on_invalid == OnInvalid::Repair
+                && validator.repair(embedding, metadata)
+                && embedding.len() as u32 == self.dim;
+            if !repaired {
+                return Err(YAVSError::InvalidRecord(reason));
+            }
+            self.precision.round_all(embedding);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+// Write-ahead log. A store opened with `open_wal` appends every insert,
+// update and removal to a `<path>.wal` sidecar before applying it, so a crash
+// (even one in the middle of a save) loses at most the entry being written,
+// and a write that fails to log fails without changing the store: inserts and
+// updates return the error, removals remove nothing. After a failure every
+// logged write fails with `WalFailed` until a `checkpoint` succeeds. `open_wal`
+// replays the log over the main file and `checkpoint` folds it in: the store
+// is written out and synced, then the log is emptied. The other loaders
+// refuse a file whose log still holds entries.
+//
+// Each entry is its payload length (u32), the payload's CRC32 (u32) and the
+// payload. Replay stops at the first torn or corrupt entry and cuts the log
+// there. Entries are idempotent, so a crash between writing the main file
+// and emptying the log just replays what is already in the file. Records go
+// in whole with f32 embeddings under Pq and Int8 precision, before they are
+// encoded, and are encoded again on replay.
+//
+// Inserts, updates, removals, restores, ID changes and changes to a record's
+// tags, pin or provenance are logged; the rest (see `open_wal`) reaches the
+// main file with the next `checkpoint`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use crate::format::{self, read_u64};
+use crate::group::{Batch, GroupCommit};
+use crate::{Record, Tombstone, YAVS, YAVSError};
+
+const MAGIC: &[u8] = b"YAVW";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 4 + 4;
+const LOG_EXT: &str = ".wal";
+
+const ENTRY_INSERT: u8 = 1;
+const ENTRY_TOMBSTONE: u8 = 2;
+const ENTRY_UPDATE: u8 = 3;
+
+#[derive(Debug)]
+pub(crate) struct Wal {
+    file: File,
+    // The main store file
+    path: PathBuf,
+    size: u64,
+    // sync_data after every entry, not just at checkpoints
+    sync: bool,
+    // An entry couldn't be written; every write fails until `checkpoint`
+    failed: bool,
+    // Entries written but not yet synced under group commit
+    pub(crate) batch: Batch,
+}
+
+impl Wal {
+    fn write(&mut self, payload: &[u8], group: Option<GroupCommit>, now: u64) -> Result<(), YAVSError> {
+        self.write_all(&[payload], group, now)
+    }
+
+    // Writes the entries together: if any fails, none of them stay in the log.
+    // With syncing on they count as one write towards the group commit batch.
+    fn write_all(&mut self, payloads: &[&[u8]], group: Option<GroupCommit>, now: u64) -> Result<(), YAVSError> {
+        if self.failed {
+            return Err(YAVSError::WalFailed);
+        }
+        let mut entries = Vec::with_capacity(payloads.iter().map(|p| 8 + p.len()).sum());
+        for payload in payloads {
+            entries.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            entries.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+            entries.extend_from_slice(payload);
+        }
+        let due = self.sync && self.batch.add(group, now);
+        let written = self.file.write_all(&entries).and_then(|_| if due { self.file.sync_data() } else { Ok(()) });
+        if let Err(e) = written {
+            // A later entry after a gap would replay out of context
+            self.failed = true;
+            let _ = self.file.set_len(self.size);
+            return Err(e.into());
+        }
+        if due {
+            self.batch.clear();
+        }
+        self.size += entries.len() as u64;
+        Ok(())
+    }
+
+    // Syncs the entries pending under group commit
+    pub(crate) fn commit(&mut self) -> Result<(), YAVSError> {
+        if self.batch.len() > 0 {
+            self.file.sync_data()?;
+            self.batch.clear();
+        }
+        Ok(())
+    }
+}
+
+fn log_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(LOG_EXT);
+    PathBuf::from(name)
+}
+
+// Fails with `PendingWal` if the store at `path` has a log with entries
+// that only `open_wal` would replay
+pub(crate) fn check_no_pending_log(path: &Path) -> Result<(), YAVSError> {
+    match std::fs::metadata(log_path(path)) {
+        Ok(meta) if meta.len() > HEADER_SIZE => Err(YAVSError::PendingWal),
+        _ => Ok(()),
+    }
+}
+
+fn apply_entry(db: &mut YAVS, mut payload: &[u8]) -> Result<(), YAVSError> {
+    let (&kind, rest) = payload.split_first().ok_or(YAVSError::InvalidFile)?;
+    payload = rest;
+    match kind {
+        ENTRY_INSERT => {
+            let rec = format::read_full_record(&mut payload, db.dim, db.precision.unquantized().into())?;
+            // Already in the main file if a checkpoint was cut short. One
+            // deleted since is pushed again and then deleted by its logged
+            // tombstone.
+            if db.position(&rec.id).is_some() {
+                return Ok(());
+            }
+            match db.push_record(rec) {
+                Err(YAVSError::DuplicateId) => Ok(()),
+                result => result,
+            }
+        }
+        ENTRY_UPDATE => {
+            let rec = format::read_full_record(&mut payload, db.dim, db.precision.unquantized().into())?;
+            // A restored or re-pinned record replaces its latest tombstone,
+            // as `restore` and `pin` do
+            match db.position(&rec.id).or_else(|| db.latest_tombstone(&rec.id)) {
+                Some(i) => {
+                    db.replace_record(i, rec);
+                    Ok(())
+                }
+                None => db.push_record(rec),
+            }
+        }
+        ENTRY_TOMBSTONE => {
+            let id = payload.get(..16).and_then(|b| b.try_into().ok()).ok_or(YAVSError::InvalidFile)?;
+            payload = &payload[16..];
+            let deleted_at = read_u64(&mut payload)?;
+            db.apply_tombstones(&[Tombstone { id, deleted_at }]);
+            Ok(())
+        }
+        _ => Err(YAVSError::InvalidFile),
+    }
+}
+
+// Applies every intact entry and returns the length of the log up to the
+// last one
+fn replay(db: &mut YAVS, log: &[u8]) -> Result<u64, YAVSError> {
+    if log.len() < HEADER_SIZE as usize || &log[..4] != MAGIC {
+        return Err(YAVSError::InvalidFile);
+    }
+    if u32::from_le_bytes([log[4], log[5], log[6], log[7]]) != VERSION {
+        return Err(YAVSError::VersionMismatch);
+    }
+    let mut at = HEADER_SIZE as usize;
+    while let Some(frame) = log.get(at..at + 8) {
+        let len = u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+        let crc = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+        let Some(payload) = log.get(at + 8..at + 8 + len) else {
+            break;
+        };
+        if crc32fast::hash(payload) != crc {
+            break;
+        }
+        apply_entry(db, payload)?;
+        at += 8 + len;
+    }
+    Ok(at as u64)
+}
+
+impl YAVS {
+    // Loads a store and replays its write-ahead log, creating the log if
+    // there is none; from then on changes to records are logged. Not logged,
+    // so lost in a crash before the next `checkpoint`: `merge`, metadata
+    // rewrites, compaction, index and quantizer training, precision changes,
+    // field declarations, eval sets and the key-value namespace.
+    pub fn open_wal<P: AsRef<Path>>(path: P) -> Result<Self, YAVSError> {
+        let path = path.as_ref();
+        let mut db = Self::read_file(path)?;
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(log_path(path))?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+        let size = if log.is_empty() {
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            HEADER_SIZE
+        } else {
+            replay(&mut db, &log)?
+        };
+        if size < log.len() as u64 {
+            file.set_len(size)?;
+        }
+        db.wal = Some(Wal {
+            file,
+            path: path.to_path_buf(),
+            size,
+            sync: false,
+            failed: false,
+            batch: Batch::default(),
+        });
+        Ok(db)
+    }
+
+    // Writes the whole store to its file, syncs it and empties the log. Does
+    // nothing for a store not opened with `open_wal`.
+    pub fn checkpoint(&mut self) -> Result<(), YAVSError> {
+        let Some(mut wal) = self.wal.take() else {
+            return Ok(());
+        };
+        let result = self.checkpoint_to(&mut wal);
+        self.wal = Some(wal);
+        result
+    }
+
+    fn checkpoint_to(&mut self, wal: &mut Wal) -> Result<(), YAVSError> {
+        self.write_file(&wal.path, 0)?;
+        wal.file.set_len(HEADER_SIZE)?;
+        wal.file.sync_data()?;
+        wal.size = HEADER_SIZE;
+        wal.failed = false;
+        wal.batch.clear();
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Checkpoints and stops logging, leaving an ordinary in-memory store
+    pub fn close_wal(&mut self) -> Result<(), YAVSError> {
+        self.checkpoint()?;
+        self.wal = None;
+        Ok(())
+    }
+
+    // Syncs the log to disk after every entry (or every batch, see
+    // `set_group_commit`), so entries also survive an OS crash or power loss
+    // rather than just the process dying. Off by default.
+    pub fn set_wal_sync(&mut self, sync: bool) {
+        if let Some(wal) = &mut self.wal {
+            wal.sync = sync;
+        }
+    }
+
+    // Path of the main file opened with `open_wal`
+    pub fn wal_path(&self) -> Option<&Path> {
+        self.wal.as_ref().map(|w| w.path.as_path())
+    }
+
+    // Current size of the log in bytes, e.g. to decide when to checkpoint
+    pub fn wal_size(&self) -> Option<u64> {
+        self.wal.as_ref().map(|w| w.size)
+    }
+
+    // Whether writing to the log failed since the last checkpoint, so logged
+    // writes fail until the next `checkpoint`
+    pub fn wal_failed(&self) -> bool {
+        self.wal.as_ref().is_some_and(|w| w.failed)
+    }
+
+    // Called by inserts with the new record, before it is added
+    pub(crate) fn log_inserted(&mut self, rec: &Record) -> Result<(), YAVSError> {
+        self.log_record(ENTRY_INSERT, rec)
+    }
+
+    // Called by updates with the new version, before it replaces the old one
+    pub(crate) fn log_updated(&mut self, rec: &Record) -> Result<(), YAVSError> {
+        self.log_record(ENTRY_UPDATE, rec)
+    }
+
+    // Applies `change` to the record at `i` in place, once it is logged as an
+    // update. The caller keeps the store's indexes in step.
+    pub(crate) fn change_record<F: Fn(&mut Record)>(&mut self, i: usize, change: F) -> Result<(), YAVSError> {
+        if self.wal.is_some() {
+            let mut rec = self.expanded(&self.records[i]);
+            change(&mut rec);
+            self.log_updated(&rec)?;
+        }
+        change(&mut self.records[i]);
+        Ok(())
+    }
+
+    fn log_record(&mut self, kind: u8, rec: &Record) -> Result<(), YAVSError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let mut payload = vec![kind];
+        format::write_full_record(&mut payload, rec, self.precision.unquantized().into())?;
+        let now = self.clock.now_ms();
+        match &mut self.wal {
+            Some(wal) => wal.write(&payload, self.group_commit, now),
+            None => Ok(()),
+        }
+    }
+
+    // Called by `rekey` before `rec` takes its new ID: logged as a tombstone
+    // for the old ID and an insert under the new one, written together
+    pub(crate) fn log_rekeyed(&mut self, old_id: &[u8; 16], rec: &Record, at: u64) -> Result<(), YAVSError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let mut tombstone = vec![ENTRY_TOMBSTONE];
+        tombstone.extend_from_slice(old_id);
+        tombstone.extend_from_slice(&at.to_le_bytes());
+        let mut insert = vec![ENTRY_INSERT];
+        format::write_full_record(&mut insert, rec, self.precision.unquantized().into())?;
+        let now = self.clock.now_ms();
+        match &mut self.wal {
+            Some(wal) => wal.write_all(&[&tombstone, &insert], self.group_commit, now),
+            None => Ok(()),
+        }
+    }
+
+    // Called by removals with the records they are about to delete, written
+    // together. On an error the removal doesn't happen.
+    pub(crate) fn log_tombstones(&mut self, tombstones: &[Tombstone]) -> Result<(), YAVSError> {
+        let now = self.clock.now_ms();
+        let Some(wal) = self.wal.as_mut() else {
+            return Ok(());
+        };
+        let payloads: Vec<Vec<u8>> = tombstones
+            .iter()
+            .map(|t| {
+                let mut payload = vec![ENTRY_TOMBSTONE];
+                payload.extend_from_slice(&t.id);
+                payload.extend_from_slice(&t.deleted_at.to_le_bytes());
+                payload
+            })
+            .collect();
+        wal.write_all(&payloads.iter().map(Vec::as_slice).collect::<Vec<_>>(), self.group_commit, now)
+    }
+
+    pub(crate) fn log_removed(&mut self, ids: &[[u8; 16]], deleted_at: u64) -> Result<(), YAVSError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let tombstones: Vec<Tombstone> = ids.iter().map(|&id| Tombstone { id, deleted_at }).collect();
+        self.log_tombstones(&tombstones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::log_path;
+    use crate::{test_path, YAVS};
+
+    #[test]
+    fn replays_tags_pins_and_restores() {
+        let path = test_path("wal-changes.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_wal(&path).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        assert!(db.add_tag(&a, "t"));
+        assert!(db.pin(&a));
+        assert!(db.remove(&b));
+        db.restore(&b).unwrap();
+        // A crash before any checkpoint
+        drop(db);
+
+        let db = YAVS::open_wal(&path).unwrap();
+        assert!(db.get(&a).unwrap().tags.contains("t"));
+        assert!(db.is_pinned(&a));
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.list_deleted().count(), 0);
+        assert_eq!(db.query(&[0.0, 1.0], 1).unwrap()[0].0, b);
+    }
+
+    #[test]
+    fn replay_stops_at_a_torn_tail() {
+        let path = test_path("wal-torn.yavs");
+        YAVS::new(2).save(&path).unwrap();
+        let mut db = YAVS::open_wal(&path).unwrap();
+        let a = db.insert(&[1.0, 0.0], b"a").unwrap();
+        let whole = fs::metadata(log_path(&path)).unwrap().len();
+        let b = db.insert(&[0.0, 1.0], b"b").unwrap();
+        drop(db);
+
+        // The second entry cut short, then whole but with a flipped byte
+        let full = fs::read(log_path(&path)).unwrap();
+        let mut flipped = full.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        for log in [&full[..full.len() - 3], &flipped[..]] {
+            fs::write(log_path(&path), log).unwrap();
+            let db = YAVS::open_wal(&path).unwrap();
+            assert!(db.get(&a).is_some());
+            assert!(db.get(&b).is_none());
+            // The tail is cut off, so later entries don't follow garbage
+            assert_eq!(db.wal_size(), Some(whole));
+        }
+
+        let mut db = YAVS::open_wal(&path).unwrap();
+        let c = db.insert(&[1.0, 1.0], b"c").unwrap();
+        drop(db);
+        let db = YAVS::open_wal(&path).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.get(&c).unwrap().metadata, b"c");
+        assert_eq!(db.wal_size(), Some(fs::metadata(log_path(&path)).unwrap().len()));
+    }
+}
@@ -1,13 +1,84 @@
 // WASM bindings for methods implemented in lib.rs
 
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use wasm_bindgen::prelude::*;
-use js_sys::{Uint8Array, Array};
-use crate::{YAVS, YAVSError};
+use js_sys::{Uint8Array, Float32Array, Array, Date, Function};
+use serde::Serialize;
+use serde_wasm_bindgen::Serializer;
+use crate::{DuplicatePolicy, YAVS, YAVSError};
+
+// Deleted records reclaimed between deadline checks in `maintenance_step`
+const MAINTENANCE_CHUNK: usize = 256;
+// Size of the chunks passed to a StorageAdapter
+const ADAPTER_CHUNK: usize = 64 * 1024;
 
 fn map_error(err: YAVSError) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
 
+#[wasm_bindgen(typescript_custom_section)]
+const STORAGE_ADAPTER: &str = r#"
+export interface StorageAdapter {
+  read(offset: number, len: number): Uint8Array;
+  write(chunk: Uint8Array): void;
+  flush(): void;
+}
+"#;
+
+// Any JS object implementing StorageAdapter (OPFS sync handles, an in-memory
+// buffer over IndexedDB, a remote endpoint, ...). `read` returns fewer than
+// `len` bytes only at the end of the store; `write` appends sequentially.
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "StorageAdapter")]
+    pub type StorageAdapter;
+
+    #[wasm_bindgen(method, catch)]
+    fn read(this: &StorageAdapter, offset: f64, len: u32) -> Result<Uint8Array, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn write(this: &StorageAdapter, chunk: Uint8Array) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn flush(this: &StorageAdapter) -> Result<(), JsValue>;
+}
+
+fn adapter_error(err: JsValue) -> io::Error {
+    io::Error::other(format!("storage adapter: {:?}", err))
+}
+
+struct AdapterReader<'a> {
+    adapter: &'a StorageAdapter,
+    offset: u64,
+}
+
+impl Read for AdapterReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self.adapter
+            .read(self.offset as f64, buf.len() as u32)
+            .map_err(adapter_error)?;
+        let n = (chunk.length() as usize).min(buf.len());
+        chunk.subarray(0, n as u32).copy_to(&mut buf[..n]);
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+struct AdapterWriter<'a> {
+    adapter: &'a StorageAdapter,
+}
+
+impl Write for AdapterWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.adapter.write(Uint8Array::from(buf)).map_err(adapter_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.adapter.flush().map_err(adapter_error)
+    }
+}
+
 #[wasm_bindgen]
 pub struct WasmYAVS {
     inner: YAVS,
@@ -38,6 +109,24 @@ impl WasmYAVS {
         }
     }
 
+    #[wasm_bindgen]
+    pub fn load_adapter(adapter: &StorageAdapter) -> Result<WasmYAVS, JsValue> {
+        let mut reader = BufReader::with_capacity(ADAPTER_CHUNK, AdapterReader { adapter, offset: 0 });
+        match YAVS::read_from(&mut reader) {
+            Ok(db) => Ok(WasmYAVS { inner: db }),
+            Err(e) => Err(map_error(e)),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn save_adapter(&self, adapter: &StorageAdapter) -> Result<(), JsValue> {
+        let mut writer = BufWriter::with_capacity(ADAPTER_CHUNK, AdapterWriter { adapter });
+        self.inner
+            .write_to(&mut writer)
+            .and_then(|_| writer.flush().map_err(YAVSError::from))
+            .map_err(map_error)
+    }
+
     #[wasm_bindgen]
     pub fn insert(&mut self, embedding: &[f32], metadata: &[u8]) -> Result<Uint8Array, JsValue> {
         match self.inner.insert(embedding, metadata) {
@@ -46,6 +135,43 @@ impl WasmYAVS {
         }
     }
 
+    // Inserts `embeddings.length / dimension` records from one flat array of
+    // embeddings. Record i's metadata is `metadata[offsets[i]..offsets[i + 1]]`,
+    // so `offsets` has one more entry than there are records. Returns an
+    // array of Uint8Array IDs.
+    #[wasm_bindgen]
+    pub fn insert_batch(&mut self, embeddings: &[f32], metadata: &[u8], offsets: &[u32]) -> Result<Array, JsValue> {
+        let dim = (self.inner.dimension() as usize).max(1);
+        let n = embeddings.len() / dim;
+        let bounds_ok = offsets.len() == n + 1
+            && offsets.windows(2).all(|w| w[0] <= w[1])
+            && offsets.last().is_some_and(|&end| end as usize <= metadata.len());
+        if !embeddings.len().is_multiple_of(dim) || !bounds_ok {
+            return Err(JsValue::from_str("embeddings, metadata and offsets don't line up"));
+        }
+        let items: Vec<(&[f32], &[u8])> = embeddings
+            .chunks_exact(dim)
+            .zip(offsets.windows(2))
+            .map(|(embedding, w)| (embedding, &metadata[w[0] as usize..w[1] as usize]))
+            .collect();
+        let ids = self.inner.insert_batch(&items).map_err(map_error)?;
+        Ok(ids.iter().map(|id| JsValue::from(Uint8Array::from(&id[..]))).collect())
+    }
+
+    // `id` must be 16 bytes; see `set_overwrite_duplicates` for IDs in use
+    #[wasm_bindgen]
+    pub fn insert_with_id(&mut self, id: &[u8], embedding: &[f32], metadata: &[u8]) -> Result<(), JsValue> {
+        let id: [u8; 16] = id.try_into().map_err(|_| JsValue::from_str("ID must be 16 bytes"))?;
+        self.inner.insert_with_id(&id, embedding, metadata).map_err(map_error)
+    }
+
+    // Whether `insert_with_id` replaces a record already using the ID
+    // instead of failing
+    #[wasm_bindgen]
+    pub fn set_overwrite_duplicates(&mut self, overwrite: bool) {
+        self.inner.set_duplicate_policy(if overwrite { DuplicatePolicy::Overwrite } else { DuplicatePolicy::Reject });
+    }
+
     #[wasm_bindgen]
     pub fn remove(&mut self, id: &[u8]) -> bool {
         if id.len() != 16 {
@@ -56,17 +182,60 @@ impl WasmYAVS {
         self.inner.remove(&arr)
     }
 
+    // [Float32Array embedding, Uint8Array metadata] of a live record, or
+    // undefined
+    #[wasm_bindgen]
+    pub fn get(&self, id: &[u8]) -> Option<Array> {
+        let id: [u8; 16] = id.try_into().ok()?;
+        let view = self.inner.view(&id)?;
+        let pair = Array::new();
+        pair.push(&Float32Array::from(&self.inner.decoded_embedding(&id)?[..]));
+        pair.push(&Uint8Array::from(view.metadata));
+        Some(pair)
+    }
+
     #[wasm_bindgen]
     pub fn compact(&mut self) {
         self.inner.compact();
     }
 
+    // Application data saved in the store file, see `YAVS::kv_set`
+    #[wasm_bindgen]
+    pub fn kv_set(&mut self, key: &str, value: &[u8]) {
+        self.inner.kv_set(key, value);
+    }
+
+    #[wasm_bindgen]
+    pub fn kv_get(&self, key: &str) -> Option<Uint8Array> {
+        self.inner.kv_get(key).map(Uint8Array::from)
+    }
+
+    #[wasm_bindgen]
+    pub fn kv_remove(&mut self, key: &str) -> bool {
+        self.inner.kv_remove(key).is_some()
+    }
+
+    #[wasm_bindgen]
+    pub fn kv_keys(&self) -> Array {
+        self.inner.kv_keys().map(JsValue::from_str).collect()
+    }
+
+    // Runs incremental compaction until `budget_ms` has elapsed, for use from
+    // requestIdleCallback. Returns true while there is still work left.
+    #[wasm_bindgen]
+    pub fn maintenance_step(&mut self, budget_ms: f64) -> bool {
+        let deadline = Date::now() + budget_ms;
+        while self.inner.compact_step(MAINTENANCE_CHUNK) {
+            if Date::now() >= deadline {
+                return true;
+            }
+        }
+        false
+    }
+
     #[wasm_bindgen]
     pub fn query(&self, embedding: &[f32], k: usize) -> Array {
-        let result = match self.inner.query(embedding, k) {
-            Ok(res) => res,
-            Err(_) => Vec::new(),
-        };
+        let result = self.inner.query(embedding, k).unwrap_or_default();
         // Return an array of [Uint8Array, distance]
         let arr = Array::new();
         for (id, dist) in result {
@@ -78,9 +247,95 @@ impl WasmYAVS {
         arr
     }
 
+    // Like `query`, with a third element holding the metadata bytes
+    #[wasm_bindgen]
+    pub fn query_with_metadata(&self, embedding: &[f32], k: usize) -> Result<Array, JsValue> {
+        let result = self.inner.query_with_metadata(embedding, k).map_err(map_error)?;
+        let arr = Array::new();
+        for hit in result {
+            let tuple = Array::new();
+            tuple.push(&Uint8Array::from(&hit.id[..]));
+            tuple.push(&JsValue::from_f64(hit.score as f64));
+            tuple.push(&Uint8Array::from(hit.metadata));
+            arr.push(&tuple);
+        }
+        Ok(arr)
+    }
+
+    // Stores `value` (any JSON-serializable JS value) as UTF-8 JSON metadata
+    #[wasm_bindgen]
+    pub fn insert_json(&mut self, embedding: &[f32], value: JsValue) -> Result<Uint8Array, JsValue> {
+        let value: serde_json::Value = serde_wasm_bindgen::from_value(value)?;
+        let metadata = serde_json::to_vec(&value).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.insert(embedding, &metadata)
+    }
+
+    // Like `query`, with a third element holding the metadata parsed as JSON
+    // (or null when it isn't valid JSON)
+    #[wasm_bindgen]
+    pub fn query_json(&self, embedding: &[f32], k: usize) -> Result<Array, JsValue> {
+        let result = self.inner.query(embedding, k).map_err(map_error)?;
+        let arr = Array::new();
+        for (id, dist) in result {
+            let value = self.inner
+                .find(&id)
+                .and_then(|r| serde_json::from_slice::<serde_json::Value>(&r.metadata).ok())
+                .unwrap_or(serde_json::Value::Null);
+            let tuple = Array::new();
+            tuple.push(&Uint8Array::from(&id[..]));
+            tuple.push(&JsValue::from_f64(dist as f64));
+            tuple.push(&value.serialize(&Serializer::json_compatible())?);
+            arr.push(&tuple);
+        }
+        Ok(arr)
+    }
+
     #[wasm_bindgen]
     pub fn dimension(&self) -> u32 {
         self.inner.dimension()
     }
+
+    #[wasm_bindgen]
+    pub fn record_count(&self) -> usize {
+        self.inner.len()
+    }
+
+    // Bytes `save_bytes` would currently produce
+    #[wasm_bindgen]
+    pub fn estimated_file_size(&self) -> f64 {
+        self.inner.estimated_file_size() as f64
+    }
+
+    // Drops the oldest records until the store fits in `max_bytes`, e.g. to
+    // stay under navigator.storage.estimate() quota. Returns how many were dropped.
+    #[wasm_bindgen]
+    pub fn trim_to_size(&mut self, max_bytes: f64) -> usize {
+        self.inner.trim_to_size(max_bytes as u64)
+    }
+
+    // Array of Uint8Array IDs of all live records
+    #[wasm_bindgen]
+    pub fn ids(&self) -> Array {
+        self.inner.ids().map(|id| JsValue::from(Uint8Array::from(&id[..]))).collect()
+    }
+
+    // Calls `callback(id, embedding, metadata)` for every live record, with
+    // Uint8Array, Float32Array and Uint8Array arguments. Returning `false`
+    // from the callback stops the export early.
+    #[wasm_bindgen]
+    pub fn export_records(&self, callback: &Function) -> Result<(), JsValue> {
+        for rec in self.inner.iter() {
+            let keep_going = callback.call3(
+                &JsValue::NULL,
+                &Uint8Array::from(&rec.id[..]),
+                &Float32Array::from(&self.inner.vector(rec)[..]),
+                &Uint8Array::from(&rec.metadata[..]),
+            )?;
+            if keep_going == JsValue::FALSE {
+                break;
+            }
+        }
+        Ok(())
+    }
 }
 